@@ -0,0 +1,78 @@
+import base64
+import getpass
+import json
+import sys
+
+import serial
+
+# Usage: python3 import_key.py <arweave-keyfile.json> [port] [slot] [label]
+
+ERRORS = {
+    0x01: "modulus is not 4096 bits",
+    0x02: "public exponent is not 65537",
+    0x03: "n is not p * q",
+    0x04: "p or q is not prime",
+    0x05: "dp or dq does not match e",
+    0x06: "qi is not q^-1 mod p",
+    0x07: "d does not match dp and dq",
+    ord("E"): "invalid slot, label or PIN",
+    ord("W"): "wrong PIN, the keys were wiped",
+    ord("F"): "could not store the key",
+    ord("R"): "rejected on the device",
+}
+
+
+def member(jwk, name, size):
+    value = jwk[name]
+    raw = base64.urlsafe_b64decode(value + "=" * (-len(value) % 4))
+    # JWK members are minimal big-endian, pad them to the fixed size.
+    raw = raw.lstrip(b"\0")
+    if len(raw) > size:
+        sys.exit(f"{name} is longer than {size} bytes")
+    return raw.rjust(size, b"\0")
+
+
+with open(sys.argv[1]) as f:
+    jwk = json.load(f)
+
+if jwk.get("kty") != "RSA":
+    sys.exit("not an RSA JWK")
+
+payload = (
+    member(jwk, "n", 512)
+    + member(jwk, "e", 4)
+    + member(jwk, "d", 512)
+    + member(jwk, "p", 256)
+    + member(jwk, "q", 256)
+    + member(jwk, "dp", 256)
+    + member(jwk, "dq", 256)
+    + member(jwk, "qi", 256)
+)
+
+slot = int(sys.argv[3]) if len(sys.argv) > 3 else 0
+label = sys.argv[4].encode() if len(sys.argv) > 4 else b""
+if len(label) > 16:
+    sys.exit("the label must be at most 16 bytes")
+
+fd = serial.Serial(sys.argv[2] if len(sys.argv) > 2 else "/dev/ttyUSB0")
+fd.baudrate = 115_200
+
+# The PIN of the other keys, or a new one for the first key.
+pin = getpass.getpass("Device PIN: ").encode()
+if not 4 <= len(pin) <= 32:
+    sys.exit("the PIN must be 4 to 32 bytes")
+
+fd.write(bytes([0x0A, slot, len(label)]) + label)
+fd.write(payload)
+fd.write(bytes([len(pin)]) + pin)
+
+print("Checking the key, confirm on the device...")
+status = fd.read(1)[0]
+if status == 0:
+    print("imported")
+elif status == ord("P"):
+    print("error: wrong PIN,", fd.read(1)[0], "tries left")
+else:
+    print("error:", ERRORS.get(status, hex(status)))
+
+fd.close()
@@ -1,7 +1,9 @@
 /* GD32VF103CB */
 MEMORY
 {
-	FLASH : ORIGIN = 0x08000000, LENGTH = 128K
+	FLASH : ORIGIN = 0x08000000, LENGTH = 120K
+	/* Not used by the linker, reserved for src/keystore.rs */
+	KEYSTORE : ORIGIN = 0x0801E000, LENGTH = 8K
 	RAM : ORIGIN = 0x20000000, LENGTH = 32K
 }
 
@@ -10,4 +12,7 @@ REGION_ALIAS("REGION_RODATA", FLASH);
 REGION_ALIAS("REGION_DATA", RAM);
 REGION_ALIAS("REGION_BSS", RAM);
 REGION_ALIAS("REGION_HEAP", RAM);
-REGION_ALIAS("REGION_STACK", RAM);
\ No newline at end of file
+REGION_ALIAS("REGION_STACK", RAM);
+
+_skeystore = ORIGIN(KEYSTORE);
+_ekeystore = ORIGIN(KEYSTORE) + LENGTH(KEYSTORE);
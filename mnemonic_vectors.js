@@ -0,0 +1,16 @@
+// Prints the modulus that arweave-mnemonic-keys derives for each mnemonic
+// given on the command line, as hex, for the vectors of derive.rs.
+//
+// Usage: npm install arweave-mnemonic-keys
+//        node mnemonic_vectors.js "<mnemonic>" ...
+
+async function main() {
+  const { getKeyFromMnemonic } = await import("arweave-mnemonic-keys");
+  for (const mnemonic of process.argv.slice(2)) {
+    const jwk = await getKeyFromMnemonic(mnemonic);
+    const n = Buffer.from(jwk.n, "base64url").toString("hex");
+    console.log(JSON.stringify(mnemonic), n);
+  }
+}
+
+main();
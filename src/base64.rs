@@ -0,0 +1,57 @@
+//! base64url without padding, as used by JWKs and Arweave addresses.
+//! See https://datatracker.ietf.org/doc/html/rfc4648#section-5
+
+const ALPHABET: &[u8; 64] =
+  b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Length of the encoding of len bytes.
+pub const fn encoded_len(len: usize) -> usize {
+  (len * 4 + 2) / 3
+}
+
+/// Writes the encoding of data to out and returns its length. out must hold
+/// at least encoded_len(data.len()) bytes.
+pub fn encode(data: &[u8], out: &mut [u8]) -> usize {
+  let len = encoded_len(data.len());
+  for (i, chunk) in data.chunks(3).enumerate() {
+    let mut group = [0u8; 3];
+    group[..chunk.len()].copy_from_slice(chunk);
+    let bits = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+
+    // A partial group gives one character more than it has bytes.
+    for j in 0..chunk.len() + 1 {
+      let index = (bits >> (18 - 6 * j)) & 0x3F;
+      out[4 * i + j] = ALPHABET[index as usize];
+    }
+  }
+  len
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn vectors() {
+    // RFC 4648, section 10, without the padding.
+    let vectors = [
+      ("", ""),
+      ("f", "Zg"),
+      ("fo", "Zm8"),
+      ("foo", "Zm9v"),
+      ("foob", "Zm9vYg"),
+      ("fooba", "Zm9vYmE"),
+      ("foobar", "Zm9vYmFy"),
+    ];
+    for (data, encoding) in vectors {
+      let mut out = [0u8; 8];
+      let len = encode(data.as_bytes(), &mut out);
+      assert_eq!(len, encoding.len());
+      assert_eq!(&out[..len], encoding.as_bytes());
+    }
+
+    let mut out = [0u8; 4];
+    encode(&[0xFB, 0xFF, 0xBF], &mut out);
+    assert_eq!(&out, b"-_-_");
+  }
+}
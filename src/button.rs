@@ -0,0 +1,55 @@
+use gd32vf103xx_hal::gpio::gpioa::PA8;
+use gd32vf103xx_hal::gpio::Input;
+use gd32vf103xx_hal::gpio::PullUp;
+use longan_nano::hal::prelude::*;
+use riscv::asm;
+
+/// Core clock cycles per millisecond at 108 MHz.
+const CYCLES_PER_MS: u32 = 108_000;
+
+/// Milliseconds the button has to be held down to count as a press.
+const DEBOUNCE_MS: u32 = 50;
+
+/// How long a confirmation prompt waits before it is treated as rejected.
+const TIMEOUT_MS: u32 = 30_000;
+
+/// Push button between PA8 and ground, used to approve requests on the
+/// device itself.
+pub struct Button {
+  pub pin: PA8<Input<PullUp>>,
+}
+
+impl Button {
+  fn is_pressed(&self) -> bool {
+    self.pin.is_low().unwrap_or(false)
+  }
+
+  /// Waits for the user to press and release the button. Returns false if
+  /// that does not happen within TIMEOUT_MS.
+  pub fn confirm(&self) -> bool {
+    // A button that is already held down does not count, it has to be
+    // pressed after the prompt is shown.
+    while self.is_pressed() {
+      asm::delay(CYCLES_PER_MS);
+    }
+
+    let mut held = 0;
+    for _ in 0..TIMEOUT_MS {
+      asm::delay(CYCLES_PER_MS);
+      if !self.is_pressed() {
+        held = 0;
+        continue;
+      }
+
+      held += 1;
+      if held == DEBOUNCE_MS {
+        while self.is_pressed() {
+          asm::delay(CYCLES_PER_MS);
+        }
+        return true;
+      }
+    }
+
+    false
+  }
+}
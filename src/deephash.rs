@@ -0,0 +1,351 @@
+//! Arweave's deepHash, the SHA-384 tree hash that v2 transactions and
+//! ANS-104 data items are signed over:
+//!
+//! ```text
+//! deepHash(blob) = SHA-384(SHA-384("blob" || len) || SHA-384(blob))
+//! deepHash(list) = acc_n, acc_0 = SHA-384("list" || n),
+//!                  acc_i = SHA-384(acc_i-1 || deepHash(item_i))
+//! ```
+//!
+//! with the lengths in decimal ASCII. DeepHash takes the items in order,
+//! holding one hash for every open list, so that a transaction can be
+//! streamed in and its data never held at once.
+//!
+//! The stream format, integers big-endian:
+//!
+//! | size | field                                       |
+//! |------|---------------------------------------------|
+//! | 1    | 0x00 for a blob, 0x01 for a list            |
+//! | 4    | length of the blob, or items of the list    |
+//! | len  | data of a blob, in chunks of msg::CHUNK_LEN |
+//!
+//! followed by the items of a list. Every header and chunk is answered
+//! with Stream::ack before the next is read.
+
+use crate::msg::CHUNK_LEN;
+use sha2_const::Sha384;
+
+pub const HASH_LEN: usize = 48;
+
+/// Lists nested deeper than this are rejected. A transaction has three
+/// levels.
+pub const MAX_DEPTH: usize = 8;
+
+const BLOB: u8 = 0x00;
+const LIST: u8 = 0x01;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  /// Lists nested deeper than MAX_DEPTH.
+  Depth,
+  /// An unknown item, an item or data where none is expected, or more
+  /// data than the blob holds.
+  Stream,
+}
+
+/// SHA-384 of the tag followed by len in decimal.
+fn tag(tag: &[u8], len: usize) -> [u8; HASH_LEN] {
+  let mut digits = [0u8; 20];
+  let mut at = digits.len();
+  let mut n = len;
+  loop {
+    at -= 1;
+    digits[at] = b'0' + (n % 10) as u8;
+    n /= 10;
+    if n == 0 {
+      break;
+    }
+  }
+  Sha384::new().update(tag).update(&digits[at..]).finalize()
+}
+
+struct Blob {
+  tag: [u8; HASH_LEN],
+  data: Sha384,
+  left: usize,
+}
+
+pub struct DeepHash {
+  /// Accumulator and items left of the open lists, innermost last.
+  lists: [([u8; HASH_LEN], usize); MAX_DEPTH],
+  depth: usize,
+  blob: Option<Blob>,
+  hash: Option<[u8; HASH_LEN]>,
+}
+
+impl DeepHash {
+  pub fn new() -> Self {
+    DeepHash {
+      lists: [([0; HASH_LEN], 0); MAX_DEPTH],
+      depth: 0,
+      blob: None,
+      hash: None,
+    }
+  }
+
+  /// Whether the next item can start, i.e. there is no blob waiting for
+  /// data and the outermost item is not complete.
+  fn expecting(&self) -> bool {
+    self.blob.is_none() && self.hash.is_none()
+  }
+
+  /// Starts a list of len items.
+  pub fn list(&mut self, len: usize) -> Result<(), Error> {
+    if !self.expecting() {
+      return Err(Error::Stream);
+    }
+    let acc = tag(b"list", len);
+    if len == 0 {
+      self.done(acc);
+      return Ok(());
+    }
+    if self.depth == MAX_DEPTH {
+      return Err(Error::Depth);
+    }
+    self.lists[self.depth] = (acc, len);
+    self.depth += 1;
+    Ok(())
+  }
+
+  /// Starts a blob of len bytes, given to update.
+  pub fn blob(&mut self, len: usize) -> Result<(), Error> {
+    if !self.expecting() {
+      return Err(Error::Stream);
+    }
+    self.blob = Some(Blob {
+      tag: tag(b"blob", len),
+      data: Sha384::new(),
+      left: len,
+    });
+    self.update(&[])
+  }
+
+  /// Hashes the next bytes of the current blob.
+  pub fn update(&mut self, data: &[u8]) -> Result<(), Error> {
+    let mut blob = match self.blob.take() {
+      Some(blob) if data.len() <= blob.left => blob,
+      blob => {
+        self.blob = blob;
+        return Err(Error::Stream);
+      }
+    };
+    blob.data = blob.data.update(data);
+    blob.left -= data.len();
+    if blob.left > 0 {
+      self.blob = Some(blob);
+      return Ok(());
+    }
+
+    let hash = Sha384::new()
+      .update(&blob.tag)
+      .update(&blob.data.finalize())
+      .finalize();
+    self.done(hash);
+    Ok(())
+  }
+
+  /// Folds the hash of a complete item into the lists it closes.
+  fn done(&mut self, mut hash: [u8; HASH_LEN]) {
+    while self.depth > 0 {
+      let (acc, left) = &mut self.lists[self.depth - 1];
+      *acc = Sha384::new().update(acc).update(&hash).finalize();
+      *left -= 1;
+      if *left > 0 {
+        return;
+      }
+      hash = *acc;
+      self.depth -= 1;
+    }
+    self.hash = Some(hash);
+  }
+
+  /// The deep hash, once the outermost item is complete.
+  pub fn finalize(&self) -> Option<[u8; HASH_LEN]> {
+    self.hash
+  }
+}
+
+/// Where the stream is read from.
+pub trait Stream {
+  fn read(&mut self, buf: &mut [u8]);
+
+  /// Answers a header or chunk once it is taken in, so that the next one
+  /// can be sent.
+  fn ack(&mut self);
+}
+
+/// Reads one item, usually a list, in the stream format and returns its
+/// deep hash. On error the rest of the stream is left unread.
+pub fn read_stream<S: Stream>(stream: &mut S) -> Result<[u8; HASH_LEN], Error> {
+  let mut deep_hash = DeepHash::new();
+  let mut buf = [0u8; CHUNK_LEN];
+  loop {
+    if let Some(hash) = deep_hash.finalize() {
+      return Ok(hash);
+    }
+
+    let mut header = [0u8; 5];
+    stream.read(&mut header);
+    let len =
+      u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    match header[0] {
+      LIST => {
+        deep_hash.list(len)?;
+        stream.ack();
+      }
+      BLOB => {
+        deep_hash.blob(len)?;
+        stream.ack();
+        let mut left = len;
+        while left > 0 {
+          let chunk = &mut buf[..left.min(CHUNK_LEN)];
+          stream.read(chunk);
+          deep_hash.update(chunk)?;
+          stream.ack();
+          left -= chunk.len();
+        }
+      }
+      _ => return Err(Error::Stream),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::hex;
+
+  enum Item<'a> {
+    Blob(&'a [u8]),
+    List(Vec<Item<'a>>),
+  }
+
+  fn encode(item: &Item, out: &mut Vec<u8>) {
+    match item {
+      Item::Blob(data) => {
+        out.push(BLOB);
+        out.extend((data.len() as u32).to_be_bytes());
+        out.extend(*data);
+      }
+      Item::List(items) => {
+        out.push(LIST);
+        out.extend((items.len() as u32).to_be_bytes());
+        for item in items {
+          encode(item, out);
+        }
+      }
+    }
+  }
+
+  struct Bytes {
+    data: Vec<u8>,
+    at: usize,
+    acks: usize,
+  }
+
+  impl Stream for Bytes {
+    fn read(&mut self, buf: &mut [u8]) {
+      buf.copy_from_slice(&self.data[self.at..self.at + buf.len()]);
+      self.at += buf.len();
+    }
+
+    fn ack(&mut self) {
+      self.acks += 1;
+    }
+  }
+
+  fn stream(item: &Item) -> Bytes {
+    let mut data = Vec::new();
+    encode(item, &mut data);
+    Bytes {
+      data,
+      at: 0,
+      acks: 0,
+    }
+  }
+
+  #[test]
+  fn tags() {
+    assert_eq!(tag(b"blob", 0), Sha384::new().update(b"blob0").finalize());
+    assert_eq!(
+      tag(b"list", 1234567890),
+      Sha384::new().update(b"list1234567890").finalize()
+    );
+  }
+
+  #[test]
+  fn vectors() {
+    use Item::*;
+
+    // From a port of deepHash.ts of arweave-js to Python.
+    let data: Vec<u8> = (0..1300).map(|i| (i % 251) as u8).collect();
+    let owner: Vec<u8> = (0..64).collect();
+    let tags = List(vec![
+      List(vec![Blob(b"Content-Type"), Blob(b"text/plain")]),
+      List(vec![Blob(b"App"), Blob(b"arienai")]),
+    ]);
+    let tx = List(vec![
+      Blob(b"2"),
+      Blob(&owner),
+      Blob(b""),
+      Blob(b"1000"),
+      Blob(b"0"),
+      Blob(b""),
+      tags,
+      Blob(b"1300"),
+      Blob(&data),
+      List(vec![]),
+    ]);
+    let mut deep = Blob(b"x");
+    for _ in 0..MAX_DEPTH {
+      deep = List(vec![deep]);
+    }
+
+    let vectors = [
+      (Blob(b""), "fbf00cc444f5fea9dc3bedf62a13fba8ae87e7445fc910567a23bec4eb82fadb1143c433069314d8362983dc3c2e4a38"),
+      (Blob(b"hello"), "33ab2407a6c328c0bc1bbe5971f49af5c1908985f83c3d2bd89a9e221dd8b068dc61ce968ba3f9ab12d5361ba3944382"),
+      (List(vec![]), "a69e7d37fdc7f040a9ec16aae84de24fab4a653dac4de0bd247e36bab9fe45d9289c5a04a893c95285812f5cefc9707a"),
+      (tx, "df36ed276401589920ed041e79f7836e6d159786e1411e39470a52cc5b80b68c2d6123e27398553913f6fd235a3e40dc"),
+      (deep, "a1a7b4c04962490c68255aa51a81208f1eca425817831fab46641493f060b7cdda0edaef4a2eceb1c50312e1671c3f26"),
+    ];
+    for (item, expected) in vectors.iter() {
+      let mut stream = stream(item);
+      assert_eq!(read_stream(&mut stream).unwrap()[..], hex(expected)[..]);
+      assert_eq!(stream.at, stream.data.len());
+    }
+
+    // 17 headers and a chunk for every blob but the empty ones, three for
+    // the data.
+    let mut stream = stream(&vectors[3].0);
+    read_stream(&mut stream).unwrap();
+    assert_eq!(stream.acks, 17 + 9 + 3);
+  }
+
+  #[test]
+  fn errors() {
+    let mut deep = Item::Blob(b"x");
+    for _ in 0..MAX_DEPTH + 1 {
+      deep = Item::List(vec![deep]);
+    }
+    assert_eq!(read_stream(&mut stream(&deep)), Err(Error::Depth));
+
+    let mut stream = Bytes {
+      data: vec![0x02, 0, 0, 0, 0],
+      at: 0,
+      acks: 0,
+    };
+    assert_eq!(read_stream(&mut stream), Err(Error::Stream));
+
+    let mut deep_hash = DeepHash::new();
+    assert_eq!(deep_hash.update(b"x"), Err(Error::Stream));
+    deep_hash.blob(1).unwrap();
+    assert_eq!(deep_hash.list(0), Err(Error::Stream));
+    assert_eq!(deep_hash.update(b"xy"), Err(Error::Stream));
+    deep_hash.update(b"x").unwrap();
+    let x = Sha384::new().update(b"x").finalize();
+    assert_eq!(
+      deep_hash.finalize(),
+      Some(Sha384::new().update(&tag(b"blob", 1)).update(&x).finalize())
+    );
+  }
+}
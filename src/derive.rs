@@ -0,0 +1,322 @@
+//! Deterministic RSA keys from a BIP-39 seed, following the derivation of
+//! arweave-mnemonic-keys (through human-crypto-keys).
+//!
+//! That seeds an HMAC_DRBG with the 64 byte BIP-39 seed and hands it to
+//! node-forge's RSA key generation with the PRIMEINC prime search. This
+//! module follows the same steps:
+//!
+//! - A prime search reads bits / 8 + 1 bytes from the DRBG, drops the
+//!   first one, sets the top bit and moves up to the next value that is
+//!   1 mod 30. It then steps through the numbers coprime to 30 until one
+//!   is prime.
+//! - p and q are searched for one after the other and swapped so that
+//!   p > q. A new p is searched for if e | p - 1, a new q if e | q - 1 or
+//!   if n = p * q is short of the full size.
+//!
+//! Forge picks its Miller-Rabin witnesses with Math.random, not the
+//! seeded DRBG, so which witnesses are used has no effect on the result.
+//!
+//! The derived keys have not yet been checked against arweave-mnemonic-keys
+//! itself, so they are not known to match the Arweave web wallets. The
+//! vectors in the tests only pin down this implementation; the moduli
+//! printed by mnemonic_vectors.js have to replace them before a mnemonic
+//! from a web wallet can be relied on to restore the same account.
+
+use crate::drbg::HmacDrbg;
+use crate::prime;
+use crate::rsa;
+use crate::rsa::PrivateKey;
+use crate::rsa::PUBLIC_EXPONENT;
+use crypto_bigint::Limb;
+use crypto_bigint::LimbUInt;
+use crypto_bigint::UInt;
+use rand_core::CryptoRng;
+use rand_core::RngCore;
+
+/// Steps from 1 mod 30 through the residues coprime to 30.
+const GCD_30_DELTA: [u8; 8] = [6, 4, 2, 4, 2, 4, 6, 2];
+
+/// Bytes read for the largest prime, 2048 bits.
+const MAX_PRIME_BYTES: usize = 2048 / 8;
+
+/// Derives the key for the given BIP-39 seed. The key passes the same
+/// self-test as a generated one before it is returned.
+pub fn key_from_seed<const L: usize, const H: usize>(
+  seed: &[u8],
+) -> Result<PrivateKey<L, H>, rsa::Error> {
+  let mut drbg = HmacDrbg::new(seed, b"", b"");
+  // Only used for Miller-Rabin witnesses and blinding, never for anything
+  // that ends up in the key.
+  let mut rng = HmacDrbg::new(seed, b"", b"arienai witnesses");
+  forge_key(&mut drbg, &mut rng)
+}
+
+/// forge's RSA key generation, with the random starts of the prime
+/// searches read from drbg.
+fn forge_key<
+  D: RngCore,
+  R: RngCore + CryptoRng,
+  const L: usize,
+  const H: usize,
+>(
+  drbg: &mut D,
+  rng: &mut R,
+) -> Result<PrivateKey<L, H>, rsa::Error> {
+  let mut p: Option<UInt<H>> = None;
+  let mut q: Option<UInt<H>> = None;
+  loop {
+    let mut pp = match p {
+      Some(p) => p,
+      None => primeinc(drbg, rng),
+    };
+    let mut qq = match q {
+      Some(q) => q,
+      None => primeinc(drbg, rng),
+    };
+
+    if pp < qq {
+      core::mem::swap(&mut pp, &mut qq);
+    }
+    p = Some(pp);
+    q = Some(qq);
+
+    let p1 = pp.wrapping_sub(&UInt::ONE).to_uint_array();
+    if rsa::rem_limb(&p1, PUBLIC_EXPONENT) == 0 {
+      p = None;
+      continue;
+    }
+    let q1 = qq.wrapping_sub(&UInt::ONE).to_uint_array();
+    if rsa::rem_limb(&q1, PUBLIC_EXPONENT) == 0 {
+      q = None;
+      continue;
+    }
+
+    // n must have the full L * BIT_SIZE bits.
+    let (_, hi) = pp.mul_wide(&qq);
+    if hi.bits() != H * Limb::BIT_SIZE {
+      q = None;
+      continue;
+    }
+
+    let key = PrivateKey::from_primes(pp, qq).ok_or(rsa::Error::Fault)?;
+    key.self_test(rng)?;
+    return Ok(key);
+  }
+}
+
+/// forge.prime.generateProbablePrime with the PRIMEINC algorithm, for
+/// primes of H * BIT_SIZE bits.
+fn primeinc<D: RngCore, R: RngCore + CryptoRng, const H: usize>(
+  drbg: &mut D,
+  rng: &mut R,
+) -> UInt<H> {
+  // Not reset when a new start is drawn, as in forge.
+  let mut delta_idx = 0;
+  let mut num = random_start::<_, H>(drbg);
+  loop {
+    let n = match num {
+      Some(n) => n,
+      None => {
+        num = random_start::<_, H>(drbg);
+        continue;
+      }
+    };
+
+    if prime::trial_division(&n)
+      && prime::is_probable_prime(rng, &n, prime::MILLER_RABIN_ROUNDS)
+    {
+      return n;
+    }
+
+    let delta = GCD_30_DELTA[delta_idx % GCD_30_DELTA.len()];
+    delta_idx += 1;
+    num = checked_add(&n, delta as LimbUInt);
+  }
+}
+
+/// forge's generateRandom: a random number with the top bit set, moved up
+/// to the next value that is 1 mod 30. None if that carries out of H limbs.
+fn random_start<D: RngCore, const H: usize>(drbg: &mut D) -> Option<UInt<H>> {
+  // new BigInteger(bits, rng) reads bits / 8 + 1 bytes and, with bits a
+  // multiple of 8, clears the first one.
+  let len = H * Limb::BYTE_SIZE;
+  let mut bytes = [0u8; MAX_PRIME_BYTES + 1];
+  let bytes = &mut bytes[..len + 1];
+  drbg.fill_bytes(bytes);
+
+  let mut limbs = rsa::uint_from_be::<H>(&bytes[1..]).to_uint_array();
+  limbs[H - 1] |= 1 << (Limb::BIT_SIZE - 1);
+
+  let r = rsa::rem_limb(&limbs, 30);
+  checked_add(&UInt::from_uint_array(limbs), 31 - r)
+}
+
+/// x + y, or None if that does not fit.
+fn checked_add<const H: usize>(x: &UInt<H>, y: LimbUInt) -> Option<UInt<H>> {
+  let mut limbs = [0 as LimbUInt; H];
+  limbs[0] = y;
+  let (sum, carry) = x.adc(&UInt::from_uint_array(limbs), Limb::ZERO);
+  if carry.0 == 0 {
+    Some(sum)
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::mnemonic;
+  use crate::rsa::PrivateKey4096;
+  use crate::test_util::hex;
+  use rand_core::SeedableRng;
+  use rand_hc::Hc128Rng;
+
+  type U512 = UInt<{ 512 / Limb::BIT_SIZE }>;
+  type PrivateKey1024 =
+    PrivateKey<{ 1024 / Limb::BIT_SIZE }, { 512 / Limb::BIT_SIZE }>;
+
+  // Primes and starts for the forge quirks, found with a Python model of
+  // forge's PRIMEINC search. Those named G are 1 mod 30 and have the top
+  // two bits set, LO have only the top bit set and BAD is 1 mod 30 * e.
+  const X2: &str = concat!(
+    "a415c4c839a44721de85eb9025ac45a0aa8b230f3b05e392a6ea1c0d2f8b9e9d",
+    "e3d6e4b9d96e182dcd502d42af1ffe0de8d79f49af6d114c4a6f188a424e617b",
+  );
+  const P: &str = concat!(
+    "a415c4c839a44721de85eb9025ac45a0aa8b230f3b05e392a6ea1c0d2f8b9e9d",
+    "e3d6e4b9d96e182dcd502d42af1ffe0de8d79f49af6d114c4a6f188a424e6409",
+  );
+  const BAD: &str = concat!(
+    "da6bfd3397a2f0bf0dd90610617b75ee70a83df369c8a94e123ff253cfa54c59",
+    "c5b85c2b944a09cc23a9c0b7baefb328a6b020b9060b15aad831f4b9a8e66f53",
+  );
+  const G0: &str = concat!(
+    "d8a37b6a51e6ebefc190533eb7c0ccf4344204cfce9f9bf09ef8a7ad2a80b7a4",
+    "88d822e85af16e83af80344adb655efd11225208ab58f8744727a2c2b2d5536f",
+  );
+  const G1: &str = concat!(
+    "e2a9e614adaffa2f51eb57d51bbd054ee537e2a9822720078e6c376c23779da4",
+    "baa0219c57e47b97c0ed344ad199c198c1dbe247e1075cc8a9aa0a922ddf4a19",
+  );
+  const G2: &str = concat!(
+    "df78b68d208eaff70abb04bd5b9dfbb9a1113cd61dcaa532d6aa1f01ec0338c3",
+    "7d46c1f873ec26e558d087d4a998ea2e3d7d2f5e8ef4c1e85b190d4f48cbebed",
+  );
+  const LO0: &str = concat!(
+    "b47902d883455bbd0ed5df1696b13e9d01ec1978ac713a336a86dbbd00fadaf1",
+    "6c6bc771ff48894178b8b2c666fe4776f5a4e0681f37fbe6bcef0de257eebba3",
+  );
+  const LO1: &str = concat!(
+    "98eac0a7c24e7fcd60e79f3335522b5ffd7914285d09e1af41651fc79c3b8957",
+    "7f9b81df33c9a2706c1442ddf0cc12723f31db10c71dc06c4cdbcac0f339cfbd",
+  );
+
+  /// Hands out the bytes given to it in order, in place of the DRBG.
+  struct Script(Vec<u8>);
+
+  impl Script {
+    /// Queues the bytes from which random_start returns x, which must be
+    /// 1 mod 30 with the top bit set.
+    fn start_at(&mut self, x: &U512) {
+      self.push(&x.wrapping_sub(&UInt::from_u8(30)));
+    }
+
+    /// Queues the bytes of a start of x before it is moved to 1 mod 30.
+    fn push(&mut self, x: &U512) {
+      let mut bytes = [0u8; 512 / 8 + 1];
+      rsa::uint_to_be(x, &mut bytes[1..]);
+      self.0.extend_from_slice(&bytes);
+    }
+  }
+
+  impl RngCore for Script {
+    fn next_u32(&mut self) -> u32 {
+      rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+      rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+      let rest = self.0.split_off(dest.len());
+      dest.copy_from_slice(&self.0);
+      self.0 = rest;
+    }
+
+    fn try_fill_bytes(
+      &mut self,
+      dest: &mut [u8],
+    ) -> Result<(), rand_core::Error> {
+      self.fill_bytes(dest);
+      Ok(())
+    }
+  }
+
+  fn uint(s: &str) -> U512 {
+    rsa::uint_from_be(&hex(s))
+  }
+
+  #[test]
+  fn mnemonic() {
+    // Regression vectors of this implementation, see the module docs.
+    let vectors = [
+      (
+        "abandon amount liar amount expire adjust cage candy arch gather drum buyer",
+        "93fa2ddcd4b9c44e44a45b3360af6f9e74268f148782efd6d9c3ef9e0f835557f42f9ac248edccd547b5cbe6f2dd72d96161fbcee79f8cb106ce26113ff6b7a5571efe15b6862add3e0db4801cc19856e250b9a62ebc11cc6a7d6d98d29494f03667111113cda5e0ea6ad2cf0ba4e7eed5e2de1b24e1836effa4391e471ad5e797681191b7cfbb5b8dc64ca57711593d0398bb1b9146ae727d26a375a677b99cfe46b51b9094e658b4c37bf02eb03135538bf252f0b41c1690452b8abbf94c3ab54d5f39697494bfa2ef707d6818e76342ccf8b6acefdf5cb0cd0b549f2639f702af158ef81a32f344dd3231b4d046c78cfb502ad8a37fbfe0b40ed170100cbee50d6408960c0ad027ccd8ae35ae3e021693955a3c21187176542b2545cb3eb12fd82f94b7cc76611b3784e4b44e6231c515aa4d07bc24774b35d7dc9ce144dd58c580d958fb206ad7f3e0f817b796f98037cb1b2a77982b1d5bce4488d8908720ff7337392900c351834ff27df39c26ed8e78df6329dbe02e8d4bf96eb65b4310012a73c29458bde2f4ad47ca41874e056dd6078ac44282c6fa57c46b7b5ee1335d00f4dbf9b716301e05ee8ba4ac88ef56c63bfe874b3fc6847e0771cb70e7c3ed68f75eb86e4bf65b9ef48b0ca564d8ad644368784a91b0c27117e1ffcf18f0f9400084aa00b8911a29fbdf47e9f5021180e3181f93c5808e26332946d541",
+      ),
+      (
+        "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+        "85e5f4cd1be0ef5741f4e84b5f281e740bc47d99fc5595b3790b9a4fedb7e27124aae00e05c4ed42a61d868b67d7de6b0d19b70750768795cb7da1fb69d0f94b2e4ba4c3deb9a4008af79177f22be4c71011a093a63656caaa72e75f1fabccebe83206fbda07d9948e999037bce543345083ceef89465d07557565c7375704345fced2215a733a5c85498a4fc3eef3cb96c3c301104d5b4a8c11fd59f234c8680dda582456021ea9876a05c55df64cf9cdab719aeb010958247b0af24a052f0a98fb5bf04665182a33c9d355d3d521d02980b4bf21091577fcb37714f6633b0e5d34dab839443feb41fb3187732bc10c6f4e5be3f570ad2f8646aea2e78684fb43f64d22af1f25862a88aa9babe6b79f70078122d2405b8902ad67f9b74872f435d35ab46b28697e34247cd9b77f6525d2aa662e87531ba6eec7083d0186239f45f8128bbfb57b19ddcf64283464694d448a8f5db386b49a4732a76f351faf1d6dc08899789b1dcbe7a9d36ea0055d4ed9dd8a9c0fec9cd10e5f1aabcd254d9c6571e17fe5a9619cd50ac0d1e91fe984ea821ef39d0dc7e3962723f1a7bbb4672e84da99934dc1790410878e3f3f67aa6f7102d72d7e3176c05cd5ed9ab4c11d224984a11300078a7edbff9a8da15773e4115e2d73486c3449e5ef498cde8d2cfcf84129a68e33771128c53449f733efd584b3c09ec73665c15c96c73a048421",
+      ),
+    ];
+    for (words, expected) in vectors.iter() {
+      let seed = mnemonic::to_seed(words.as_bytes(), b"");
+      let key: PrivateKey4096 = key_from_seed(&seed).unwrap();
+
+      let mut n = [0u8; 512];
+      rsa::uint_to_be(&key.n, &mut n);
+      assert_eq!(&n[..], &hex(expected)[..]);
+    }
+  }
+
+  #[test]
+  fn delta_idx_carries_over() {
+    // 2**512 - 569 is the largest prime below 2**512. The search from the
+    // first start overflows after 148 steps and continues from the second
+    // start at GCD_30_DELTA[148 % 8], not at GCD_30_DELTA[0]. Starting over
+    // at GCD_30_DELTA[0] would find a different prime.
+    let mut rng = Hc128Rng::from_seed([0x13; 32]);
+    let mut script = Script(Vec::new());
+    script.push(&U512::MAX.wrapping_sub(&UInt::from_u16(567)));
+    script.push(&uint(X2));
+
+    let p: U512 = primeinc(&mut script, &mut rng);
+    assert_eq!(p, uint(P));
+    assert!(script.0.is_empty());
+  }
+
+  #[test]
+  fn regenerates_p_or_q() {
+    let mut rng = Hc128Rng::from_seed([0x13; 32]);
+    let key_for = |rng: &mut Hc128Rng, starts: &[&str]| {
+      let mut script = Script(Vec::new());
+      for x in starts {
+        script.start_at(&uint(x));
+      }
+      let key: PrivateKey1024 = forge_key(&mut script, rng).unwrap();
+      assert!(script.0.is_empty());
+      let crt = key.crt.as_ref().unwrap();
+      (crt.p, crt.q)
+    };
+
+    // BAD > G0, so BAD becomes p after the swap and p is searched for
+    // again.
+    assert_eq!(key_for(&mut rng, &[G0, BAD, G2]), (uint(G2), uint(G0)));
+    // G1 > BAD, so BAD becomes q and q is searched for again.
+    assert_eq!(key_for(&mut rng, &[G1, BAD, G2]), (uint(G1), uint(G2)));
+    // LO0 * LO1 has 1023 bits, so q = LO1 is searched for again.
+    assert_eq!(key_for(&mut rng, &[LO0, LO1, G0]), (uint(G0), uint(LO0)));
+  }
+}
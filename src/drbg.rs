@@ -0,0 +1,447 @@
+//! HMAC_DRBG with SHA-256, see NIST SP 800-90A, section 10.1.2.
+
+use crate::hmac::HmacSha256;
+use core::num::NonZeroU32;
+use rand_core::CryptoRng;
+use rand_core::RngCore;
+
+/// Most requests between reseeds that SP 800-90A allows.
+pub const RESEED_INTERVAL: u64 = 1 << 48;
+
+/// Longest request, 2^19 bits.
+pub const MAX_REQUEST: usize = 1 << 16;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  /// The reseed interval is used up, reseed before generating more.
+  Reseed,
+  /// More than MAX_REQUEST bytes were requested.
+  Size,
+}
+
+pub struct HmacDrbg {
+  k: [u8; 32],
+  v: [u8; 32],
+  /// Requests since the last reseed, plus one.
+  reseed_counter: u64,
+  reseed_interval: u64,
+}
+
+impl HmacDrbg {
+  /// Instantiates the DRBG from entropy || nonce || personalization.
+  pub fn new(entropy: &[u8], nonce: &[u8], personalization: &[u8]) -> Self {
+    // 10.1.2.3: Key = 0x00 00...00, V = 0x01 01...01
+    let mut drbg = HmacDrbg {
+      k: [0x00; 32],
+      v: [0x01; 32],
+      reseed_counter: 1,
+      reseed_interval: RESEED_INTERVAL,
+    };
+    drbg.update(&[entropy, nonce, personalization]);
+    drbg
+  }
+
+  /// Asks for a reseed after interval requests instead of
+  /// RESEED_INTERVAL.
+  pub fn reseed_interval(mut self, interval: u64) -> Self {
+    self.reseed_interval = interval.min(RESEED_INTERVAL);
+    self
+  }
+
+  /// HMAC_DRBG_Update (10.1.2.2), provided_data is the concatenation of
+  /// the parts.
+  fn update(&mut self, provided: &[&[u8]]) {
+    // 1. K = HMAC (K, V || 0x00 || provided_data)
+    let mut mac = HmacSha256::new(&self.k).update(&self.v).update(&[0x00]);
+    for p in provided {
+      mac = mac.update(p);
+    }
+    self.k = mac.finalize();
+    // 2. V = HMAC (K, V)
+    self.v = HmacSha256::new(&self.k).update(&self.v).finalize();
+
+    // 3. If (provided_data = Null), then return K and V.
+    if provided.iter().all(|p| p.is_empty()) {
+      return;
+    }
+
+    // 4. K = HMAC (K, V || 0x01 || provided_data)
+    let mut mac = HmacSha256::new(&self.k).update(&self.v).update(&[0x01]);
+    for p in provided {
+      mac = mac.update(p);
+    }
+    self.k = mac.finalize();
+    // 5. V = HMAC (K, V)
+    self.v = HmacSha256::new(&self.k).update(&self.v).finalize();
+  }
+
+  /// HMAC_DRBG_Reseed (10.1.2.4) from entropy || additional.
+  pub fn reseed(&mut self, entropy: &[u8], additional: &[u8]) {
+    self.update(&[entropy, additional]);
+    self.reseed_counter = 1;
+  }
+
+  /// HMAC_DRBG_Generate (10.1.2.5), filling out. Each call is one request,
+  /// so splitting a read into several calls gives different bytes.
+  pub fn generate(
+    &mut self,
+    additional: &[u8],
+    out: &mut [u8],
+  ) -> Result<(), Error> {
+    if out.len() > MAX_REQUEST {
+      return Err(Error::Size);
+    }
+    // 1. If reseed_counter > reseed_interval, then return an indication
+    //    that a reseed is required.
+    if self.reseed_counter > self.reseed_interval {
+      return Err(Error::Reseed);
+    }
+    // 2. If additional_input != Null, then
+    //    (Key, V) = HMAC_DRBG_Update (additional_input, Key, V)
+    if !additional.is_empty() {
+      self.update(&[additional]);
+    }
+
+    // 4. While (len (temp) < requested_number_of_bits) do:
+    //      V = HMAC (K, V)
+    //      temp = temp || V
+    for chunk in out.chunks_mut(32) {
+      self.v = HmacSha256::new(&self.k).update(&self.v).finalize();
+      chunk.copy_from_slice(&self.v[..chunk.len()]);
+    }
+
+    // 6. (Key, V) = HMAC_DRBG_Update (additional_input, Key, V)
+    self.update(&[additional]);
+    // 7. reseed_counter = reseed_counter + 1
+    self.reseed_counter += 1;
+    Ok(())
+  }
+
+  /// A generate request with prediction resistance (9.3.1): reseeds from
+  /// fresh entropy and additional first, so that out does not depend on
+  /// anything an attacker may have learnt of the state before.
+  pub fn generate_pr(
+    &mut self,
+    entropy: &[u8],
+    additional: &[u8],
+    out: &mut [u8],
+  ) -> Result<(), Error> {
+    if out.len() > MAX_REQUEST {
+      return Err(Error::Size);
+    }
+    self.reseed(entropy, additional);
+    self.generate(&[], out)
+  }
+}
+
+/// Error code of try_fill_bytes once the DRBG must be reseeded.
+const RESEED_CODE: u32 = rand_core::Error::CUSTOM_START;
+
+fn fill(drbg: &mut HmacDrbg, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+  for chunk in dest.chunks_mut(MAX_REQUEST) {
+    drbg
+      .generate(&[], chunk)
+      .map_err(|_| NonZeroU32::new(RESEED_CODE).unwrap())?;
+  }
+  Ok(())
+}
+
+/// Without additional input. Reads longer than MAX_REQUEST are split.
+impl RngCore for HmacDrbg {
+  fn next_u32(&mut self) -> u32 {
+    rand_core::impls::next_u32_via_fill(self)
+  }
+
+  fn next_u64(&mut self) -> u64 {
+    rand_core::impls::next_u64_via_fill(self)
+  }
+
+  /// Panics once the reseed interval is used up.
+  fn fill_bytes(&mut self, dest: &mut [u8]) {
+    fill(self, dest).expect("HMAC_DRBG reseed required")
+  }
+
+  fn try_fill_bytes(
+    &mut self,
+    dest: &mut [u8],
+  ) -> Result<(), rand_core::Error> {
+    fill(self, dest)
+  }
+}
+
+impl CryptoRng for HmacDrbg {}
+
+/// The source of entropy input for reseeding, each call giving 32 bytes
+/// of full entropy.
+pub trait Entropy {
+  fn entropy(&mut self) -> [u8; 32];
+}
+
+/// An HmacDrbg that reseeds itself from its entropy source whenever the
+/// reseed interval is used up, or on every request while prediction
+/// resistance is on.
+pub struct Reseeding<E: Entropy> {
+  pub drbg: HmacDrbg,
+  pub entropy: E,
+  pub prediction_resistance: bool,
+}
+
+impl<E: Entropy> Reseeding<E> {
+  pub fn new(drbg: HmacDrbg, entropy: E) -> Self {
+    Reseeding {
+      drbg,
+      entropy,
+      prediction_resistance: false,
+    }
+  }
+
+  /// Reseeds from the entropy source now, ahead of the interval.
+  pub fn reseed(&mut self) {
+    let entropy = self.entropy.entropy();
+    self.drbg.reseed(&entropy, &[]);
+  }
+
+  fn generate(&mut self, out: &mut [u8]) -> Result<(), Error> {
+    if self.prediction_resistance {
+      let entropy = self.entropy.entropy();
+      return self.drbg.generate_pr(&entropy, &[], out);
+    }
+    match self.drbg.generate(&[], out) {
+      Err(Error::Reseed) => {
+        self.reseed();
+        self.drbg.generate(&[], out)
+      }
+      result => result,
+    }
+  }
+}
+
+impl<E: Entropy> RngCore for Reseeding<E> {
+  fn next_u32(&mut self) -> u32 {
+    rand_core::impls::next_u32_via_fill(self)
+  }
+
+  fn next_u64(&mut self) -> u64 {
+    rand_core::impls::next_u64_via_fill(self)
+  }
+
+  fn fill_bytes(&mut self, dest: &mut [u8]) {
+    for chunk in dest.chunks_mut(MAX_REQUEST) {
+      // Requests are never too long and a reseed always succeeds.
+      self.generate(chunk).unwrap();
+    }
+  }
+
+  fn try_fill_bytes(
+    &mut self,
+    dest: &mut [u8],
+  ) -> Result<(), rand_core::Error> {
+    self.fill_bytes(dest);
+    Ok(())
+  }
+}
+
+impl<E: Entropy> CryptoRng for Reseeding<E> {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::hex;
+
+  #[test]
+  fn cavp() {
+    // HMAC_DRBG.rsp from the NIST CAVP drbgtestvectors, [SHA-256],
+    // PredictionResistance = False, no reseed. Instantiate, generate twice
+    // and return the second output.
+    let vectors = [
+      (
+        "ca851911349384bffe89de1cbdc46e6831e44d34a4fb935ee285dd14b71a7488",
+        "659ba96c601dc69fc902940805ec0ca8",
+        ["", ""],
+        "e528e9abf2dece54d47c7e75e5fe302149f817ea9fb4bee6f4199697d04d5b89d54fbb978a15b5c443c9ec21036d2460b6f73ebad0dc2aba6e624abf07745bc107694bb7547bb0995f70de25d6b29e2d3011bb19d27676c07162c8b5ccde0668961df86803482cb37ed6d5c0bb8d50cf1f50d476aa0458bdaba806f48be9dcb8",
+      ),
+      (
+        "d3cc4d1acf3dde0c4bd2290d262337042dc632948223d3a2eaab87da44295fbd",
+        "0109b0e729f457328aa18569a9224921",
+        [
+          "3c311848183c9a212a26f27f8c6647e40375e466a0857cc39c4e47575d53f1f6",
+          "fcb9abd19ccfbccef88c9c39bfb3dd7b1c12266c9808992e305bc3cff566e4e4",
+        ],
+        "9c7b758b212cd0fcecd5daa489821712e3cdea4467b560ef5ddc24ab47749a1f1ffdbbb118f4e62fcfca3371b8fbfc5b0646b83e06bfbbab5fac30ea09ea2bc76f1ea568c9be0444b2cc90517b20ca825f2d0eccd88e7175538b85d90ab390183ca6395535d34473af6b5a5b88f5a59ee7561573337ea819da0dcc3573a22974",
+      ),
+    ];
+    for (entropy, nonce, additional, expected) in vectors {
+      let mut drbg = HmacDrbg::new(&hex(entropy), &hex(nonce), b"");
+      let mut out = [0u8; 128];
+      drbg.generate(&hex(additional[0]), &mut out).unwrap();
+      drbg.generate(&hex(additional[1]), &mut out).unwrap();
+      assert_eq!(out[..], hex(expected)[..]);
+    }
+  }
+
+  #[test]
+  fn cavp_prediction_resistance() {
+    // HMAC_DRBG.rsp from the NIST CAVP drbgtestvectors (CAVS 14.3),
+    // [SHA-256], PredictionResistance = True. Instantiate, generate twice
+    // with prediction resistance, each from its EntropyInputPR and
+    // AdditionalInput, and return the second output.
+    let vectors = [
+      (
+        "9969e54b4703ff31785b879a7e5c0eae0d3e309559e9fe96b0676d49d591ea4d",
+        "07d20d46d064757d3023cac2376127ab",
+        "",
+        [
+          "c60f2999100f738c10f74792676a3fc4a262d13721798046e29a295181569f54",
+          "c11d4524c9071bd3096015fcf7bc24a607f22fa065c937658a2a77a8699089f4",
+        ],
+        ["", ""],
+        "abc015856094803a938dffd20da94843870ef935b82cfec17706b8f551b8385044235dd44b599f94b39be78dd476e0cf11309c995a7334e0a78b37bc9586235086fa3b637ba91cf8fb65efa22a589c137531aa7b2d4e2607aac27292b01c698e6e01ae679eb87c01a89c7422d4372d6d754ababb4bf896fcb1cd09d692d0283f",
+      ),
+      (
+        "4294671d493dc085b5184607d7de2ff2b6aceb734a1b026f6cfee7c5a90f03da",
+        "d071544e599235d5eb38b64b551d2a6e",
+        "63bc769ae1d95a98bde870e4db7776297041d37c8a5c688d4e024b78d83f4d78",
+        [
+          "db9b4790b62336fbb9a684b82947065393eeef8f57bd2477141ad17e776dac34",
+          "4a9abe80f6f522f29878bedf8245b27940a76471006fb4a4110beb4decb6c341",
+        ],
+        [
+          "28848becd3f47696f124f4b14853a456156f69be583a7d4682cff8d44b39e1d3",
+          "8bfce0b7132661c3cd78175d83926f643e36f7608eec2c5dac3ddcbacc8c2182",
+        ],
+        "e580dc969194b2b18a97478aef9d1a72390aff14562747bf080d741527a6655ce7fc135325b457483a9f9c70f91165a811cf4524b50d51199a0df3bd60d12abac27d0bf6618e6b114e05420352e23f3603dfe8a225dc19b3d1fff1dc245dc6b1df24c741744bec3f9437dbbf222df84881a457a589e7815ef132f686b760f012",
+      ),
+    ];
+    for (entropy, nonce, personalization, entropy_pr, additional, expected) in
+      vectors
+    {
+      let mut drbg =
+        HmacDrbg::new(&hex(entropy), &hex(nonce), &hex(personalization));
+      let mut out = [0u8; 128];
+      for (e, a) in entropy_pr.iter().zip(additional.iter()) {
+        drbg.generate_pr(&hex(e), &hex(a), &mut out).unwrap();
+      }
+      assert_eq!(out[..], hex(expected)[..]);
+    }
+  }
+
+  #[test]
+  fn cavp_reseed() {
+    // HMAC_DRBG.rsp from the NIST CAVP drbgtestvectors (CAVS 14.3),
+    // [SHA-256], PredictionResistance = False with a reseed. Instantiate,
+    // reseed from EntropyInputReseed and AdditionalInputReseed, generate
+    // twice and return the second output.
+    let vectors = [
+      (
+        "06032cd5eed33f39265f49ecb142c511da9aff2af71203bffaf34a9ca5bd9c0d",
+        "0e66f71edc43e42a45ad3c6fc6cdc4df",
+        "",
+        "01920a4e669ed3a85ae8a33b35a74ad7fb2a6bb4cf395ce00334a9c9a5a5d552",
+        "",
+        ["", ""],
+        "76fc79fe9b50beccc991a11b5635783a83536add03c157fb30645e611c2898bb2b1bc215000209208cd506cb28da2a51bdb03826aaf2bd2335d576d519160842e7158ad0949d1a9ec3e66ea1b1a064b005de914eac2e9d4f2d72a8616a80225422918250ff66a41bd2f864a6a38cc5b6499dc43f7f2bd09e1e0f8f5885935124",
+      ),
+      (
+        "cdb0d9117cc6dbc9ef9dcb06a97579841d72dc18b2d46a1cb61e314012bdf416",
+        "d0c0d01d156016d0eb6b7e9c7c3c8da8",
+        "6f0fb9eab3f9ea7ab0a719bfa879bf0aaed683307fda0c6d73ce018b6e34faaa",
+        "8ec6f7d5a8e2e88f43986f70b86e050d07c84b931bcf18e601c5a3eee3064c82",
+        "1ab4ca9014fa98a55938316de8ba5a68c629b0741bdd058c4d70c91cda5099b3",
+        [
+          "16e2d0721b58d839a122852abd3bf2c942a31c84d82fca74211871880d7162ff",
+          "53686f042a7b087d5d2eca0d2a96de131f275ed7151189f7ca52deaa78b79fb2",
+        ],
+        "dda04a2ca7b8147af1548f5d086591ca4fd951a345ce52b3cd49d47e84aa31a183e31fbc42a1ff1d95afec7143c8008c97bc2a9c091df0a763848391f68cb4a366ad89857ac725a53b303ddea767be8dc5f605b1b95f6d24c9f06be65a973a089320b3cc42569dcfd4b92b62a993785b0301b3fc452445656fce22664827b88f",
+      ),
+    ];
+    for (
+      entropy,
+      nonce,
+      personalization,
+      entropy_reseed,
+      additional_reseed,
+      additional,
+      expected,
+    ) in vectors
+    {
+      let mut drbg =
+        HmacDrbg::new(&hex(entropy), &hex(nonce), &hex(personalization));
+      drbg.reseed(&hex(entropy_reseed), &hex(additional_reseed));
+      let mut out = [0u8; 128];
+      drbg.generate(&hex(additional[0]), &mut out).unwrap();
+      drbg.generate(&hex(additional[1]), &mut out).unwrap();
+      assert_eq!(out[..], hex(expected)[..]);
+    }
+  }
+
+  #[test]
+  fn reseed_and_prediction_resistance() {
+    // From the HMAC-DRBG of OpenSSL 3.5, with TEST-RAND as its parent,
+    // mixing reseeds and prediction resistance in one instance.
+    let entropy: Vec<u8> = (0..128).collect();
+    let nonce: Vec<u8> = (100..116).collect();
+    let mut drbg = HmacDrbg::new(&entropy[..32], &nonce, b"arienai test");
+    drbg.reseed(&entropy[32..64], b"reseed");
+
+    let mut out = [0u8; 64];
+    drbg.generate(b"first", &mut out).unwrap();
+    assert_eq!(out[..], hex("ec1ed7050feab698806f8827c39f8d1160c9e9d2129a3c30d2adbce6bb0158d30efa057ad38e1eb7e597c037fb39ccd30e3e3e7ee2ada8e527c2ed3685da06e7")[..]);
+    drbg
+      .generate_pr(&entropy[64..96], b"second", &mut out)
+      .unwrap();
+    assert_eq!(out[..], hex("815d4e06f6e6140374f812bc4ba24b309e3df46714b3384fe33ce73c6141330eb9632ab87e21abdcaae783e888d0f738353cf91c97129e148cc98df9dab6fc6e")[..]);
+    drbg.generate_pr(&entropy[96..], b"", &mut out).unwrap();
+    assert_eq!(out[..], hex("ef2ec4c420c1366a84e3e86257277838ac09ff3c5889b5e25fc573a7cb97d87db0bfa0cb132880e3e7a3ca124a8fdd0db3ed6e63b94b7ae3e3df83b58e807e1e")[..]);
+  }
+
+  #[test]
+  fn reseed_interval() {
+    let mut drbg = HmacDrbg::new(b"drbg test", b"", b"").reseed_interval(2);
+    let mut out = [0u8; 32];
+    drbg.generate(&[], &mut out).unwrap();
+    drbg.generate(&[], &mut out).unwrap();
+    assert_eq!(drbg.generate(&[], &mut out), Err(Error::Reseed));
+    assert!(drbg.try_fill_bytes(&mut out).is_err());
+    drbg.reseed(b"more entropy", &[]);
+    drbg.generate(&[], &mut out).unwrap();
+
+    let mut long = vec![0u8; MAX_REQUEST + 1];
+    assert_eq!(drbg.generate(&[], &mut long), Err(Error::Size));
+  }
+
+  struct Counter(u8);
+
+  impl Entropy for Counter {
+    fn entropy(&mut self) -> [u8; 32] {
+      self.0 += 1;
+      [self.0; 32]
+    }
+  }
+
+  #[test]
+  fn reseeding() {
+    let drbg = HmacDrbg::new(b"drbg test", b"", b"").reseed_interval(2);
+    let mut rng = Reseeding::new(drbg, Counter(0));
+    let mut expected = HmacDrbg::new(b"drbg test", b"", b"");
+    let (mut a, mut b) = ([0u8; 32], [0u8; 32]);
+
+    // Reseeds on the third request.
+    for _ in 0..2 {
+      rng.fill_bytes(&mut a);
+      expected.generate(&[], &mut b).unwrap();
+      assert_eq!(a, b);
+    }
+    rng.fill_bytes(&mut a);
+    expected.reseed(&[1; 32], &[]);
+    expected.generate(&[], &mut b).unwrap();
+    assert_eq!(a, b);
+    assert_eq!(rng.entropy.0, 1);
+
+    // And on every request with prediction resistance.
+    rng.prediction_resistance = true;
+    rng.fill_bytes(&mut a);
+    expected.generate_pr(&[2; 32], &[], &mut b).unwrap();
+    assert_eq!(a, b);
+    assert_eq!(rng.entropy.0, 2);
+  }
+}
@@ -0,0 +1,202 @@
+//! Seeding from the noise sources of the device. Raw samples are only
+//! assumed to hold a fraction of a bit of entropy each, so many of them
+//! are conditioned into a seed with SHA-256.
+//!
+//! HealthTests runs the continuous tests of NIST SP 800-90B, section 4.4,
+//! on the raw samples of a source, with cutoffs for the assumed entropy
+//! and a false positive rate of 2^-20. A failure is latched until reset.
+
+use sha2_const::Sha256;
+
+/// Samples taken from each source. At half a bit of entropy per sample,
+/// each source alone gives the 256 bits of a seed.
+pub const SAMPLES: usize = 512;
+
+/// Repetition Count Test cutoff, 1 + ceil(20 / 0.5).
+pub const RCT_CUTOFF: usize = 41;
+
+/// Adaptive Proportion Test window for non-binary samples.
+pub const APT_WINDOW: usize = 512;
+
+/// Adaptive Proportion Test cutoff for the window at half a bit of
+/// entropy per sample, 1 + CRITBINOM(512, 2^-0.5, 1 - 2^-20).
+pub const APT_CUTOFF: usize = 410;
+
+/// Samples tested, and thrown away, at start-up.
+pub const STARTUP_SAMPLES: usize = 1024;
+
+/// A source of raw noise samples.
+pub trait Source {
+  fn sample(&mut self) -> u8;
+}
+
+/// A source with the continuous health tests run on every sample.
+pub struct HealthTests<S: Source> {
+  source: S,
+  failed: bool,
+  /// Last sample and how many times in a row it came up.
+  rct: (u8, usize),
+  /// First sample of the window, how many times it came up and the
+  /// samples seen in the window.
+  apt: (u8, usize, usize),
+}
+
+impl<S: Source> HealthTests<S> {
+  /// Runs the start-up tests on source.
+  pub fn new(source: S) -> Self {
+    let mut tested = HealthTests {
+      source,
+      failed: false,
+      rct: (0, 0),
+      apt: (0, 0, APT_WINDOW),
+    };
+    for _ in 0..STARTUP_SAMPLES {
+      tested.sample();
+    }
+    tested
+  }
+
+  /// Whether a test has failed since start-up.
+  pub fn failed(&self) -> bool {
+    self.failed
+  }
+
+  fn repetition_count(&mut self, sample: u8) -> bool {
+    let (last, count) = &mut self.rct;
+    if *count > 0 && sample == *last {
+      *count += 1;
+    } else {
+      *last = sample;
+      *count = 1;
+    }
+    *count < RCT_CUTOFF
+  }
+
+  fn adaptive_proportion(&mut self, sample: u8) -> bool {
+    let (first, count, seen) = &mut self.apt;
+    if *seen == APT_WINDOW {
+      *first = sample;
+      *count = 1;
+      *seen = 1;
+      return true;
+    }
+
+    *seen += 1;
+    if sample == *first {
+      *count += 1;
+    }
+    *count < APT_CUTOFF
+  }
+}
+
+impl<S: Source> Source for HealthTests<S> {
+  fn sample(&mut self) -> u8 {
+    let sample = self.source.sample();
+    let rct = self.repetition_count(sample);
+    let apt = self.adaptive_proportion(sample);
+    self.failed |= !rct || !apt;
+    sample
+  }
+}
+
+/// SHA-256 over SAMPLES samples of each source in turn.
+pub fn seed(sources: &mut [&mut dyn Source]) -> [u8; 32] {
+  let mut hash = Sha256::new();
+  for source in sources.iter_mut() {
+    let mut samples = [0u8; 64];
+    for _ in 0..SAMPLES / samples.len() {
+      for s in samples.iter_mut() {
+        *s = source.sample();
+      }
+      hash = hash.update(&samples);
+    }
+  }
+  hash.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  struct Counter(u8);
+
+  impl Source for Counter {
+    fn sample(&mut self) -> u8 {
+      self.0 = self.0.wrapping_add(1);
+      self.0
+    }
+  }
+
+  struct Constant(u8);
+
+  impl Source for Constant {
+    fn sample(&mut self) -> u8 {
+      self.0
+    }
+  }
+
+  /// Cycles through 1 to 200, then repeats 0 n times.
+  struct Stuck(usize, usize);
+
+  impl Source for Stuck {
+    fn sample(&mut self) -> u8 {
+      self.1 = (self.1 + 1) % (200 + self.0);
+      if self.1 < 200 {
+        self.1 as u8 + 1
+      } else {
+        0
+      }
+    }
+  }
+
+  /// 0 but for every n-th sample, which is the counter instead.
+  struct Biased(usize, Counter);
+
+  impl Source for Biased {
+    fn sample(&mut self) -> u8 {
+      let counter = self.1.sample();
+      if counter as usize % self.0 == 0 {
+        counter
+      } else {
+        0
+      }
+    }
+  }
+
+  fn run<S: Source>(source: S) -> bool {
+    let mut tested = HealthTests::new(source);
+    for _ in 0..10 * APT_WINDOW {
+      tested.sample();
+    }
+    tested.failed()
+  }
+
+  #[test]
+  fn health_tests() {
+    assert!(!run(Counter(0)));
+
+    // Caught by the start-up tests.
+    assert!(HealthTests::new(Constant(0x42)).failed());
+
+    // Stuck for as long as the cutoff, and for one sample less.
+    assert!(run(Stuck(RCT_CUTOFF, 0)));
+    assert!(!run(Stuck(RCT_CUTOFF - 1, 0)));
+
+    // Never more than 9 in a row, but 0 is 90% of the samples, more than
+    // the APT cutoff allows. 67% is not.
+    assert!(run(Biased(10, Counter(0))));
+    assert!(!run(Biased(3, Counter(0))));
+  }
+
+  #[test]
+  fn conditioning() {
+    let (mut a, mut b) = (Counter(0), Counter(7));
+    let seed = seed(&mut [&mut a, &mut b]);
+
+    let samples: Vec<u8> = (1..=SAMPLES)
+      .map(|i| i as u8)
+      .chain((1..=SAMPLES).map(|i| (i + 7) as u8))
+      .collect();
+    assert_eq!(seed, Sha256::new().update(&samples).finalize());
+  }
+}
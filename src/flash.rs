@@ -0,0 +1,123 @@
+//! Access to the flash pages reserved for persistent state. Offsets are
+//! relative to the start of the reserved region.
+//!
+//! Like the GD32VF103 flash, erasing sets a whole page to 0xFF and
+//! programming can only clear bits, one half-word at a time.
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  /// Offset or length outside the region, or not half-word aligned.
+  Bounds,
+  /// The controller reported an error while programming or erasing.
+  Program,
+}
+
+pub trait Flash {
+  /// Erase granularity in bytes.
+  const PAGE_SIZE: usize;
+
+  /// Size of the region in bytes, a multiple of PAGE_SIZE.
+  fn size(&self) -> usize;
+
+  fn read(&self, offset: usize, buf: &mut [u8]);
+
+  /// Erases the page starting at offset.
+  fn erase_page(&mut self, offset: usize) -> Result<(), Error>;
+
+  /// Programs data at offset. Both must be half-word aligned and the bytes
+  /// erased beforehand.
+  fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error>;
+}
+
+/// RAM-backed flash for host tests. Operations can be made to stop part
+/// way through, to simulate a power loss.
+#[cfg(test)]
+pub struct RamFlash<const N: usize> {
+  pub data: [u8; N],
+  /// Half-words that can still be programmed or pages that can still be
+  /// erased before the power is cut. None for no limit.
+  pub budget: Option<usize>,
+}
+
+#[cfg(test)]
+impl<const N: usize> RamFlash<N> {
+  pub fn new() -> Self {
+    RamFlash {
+      data: [0xFF; N],
+      budget: None,
+    }
+  }
+
+  /// Uses up one unit of the budget. False once the power is cut.
+  fn spend(&mut self) -> bool {
+    match &mut self.budget {
+      Some(0) => false,
+      Some(budget) => {
+        *budget -= 1;
+        true
+      }
+      None => true,
+    }
+  }
+}
+
+#[cfg(test)]
+impl<const N: usize> Default for RamFlash<N> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+impl<const N: usize> Flash for RamFlash<N> {
+  const PAGE_SIZE: usize = 1024;
+
+  fn size(&self) -> usize {
+    N
+  }
+
+  fn read(&self, offset: usize, buf: &mut [u8]) {
+    buf.copy_from_slice(&self.data[offset..offset + buf.len()]);
+  }
+
+  fn erase_page(&mut self, offset: usize) -> Result<(), Error> {
+    if offset % Self::PAGE_SIZE != 0 || offset >= N {
+      return Err(Error::Bounds);
+    }
+
+    // An interrupted erase leaves the page partly erased.
+    let powered = self.spend();
+    let len = if powered {
+      Self::PAGE_SIZE
+    } else {
+      Self::PAGE_SIZE / 2
+    };
+    for b in self.data[offset..offset + len].iter_mut() {
+      *b = 0xFF;
+    }
+
+    if powered {
+      Ok(())
+    } else {
+      Err(Error::Program)
+    }
+  }
+
+  fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error> {
+    if offset % 2 != 0 || data.len() % 2 != 0 || offset + data.len() > N {
+      return Err(Error::Bounds);
+    }
+
+    for (i, half) in data.chunks(2).enumerate() {
+      let at = offset + 2 * i;
+      if self.data[at..at + 2] != [0xFF, 0xFF] {
+        return Err(Error::Program);
+      }
+      if !self.spend() {
+        return Err(Error::Program);
+      }
+      self.data[at..at + 2].copy_from_slice(half);
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,120 @@
+//! The flash memory controller, driving the pages reserved for the key
+//! store in memory.x.
+
+use arienai::flash;
+use arienai::flash::Flash;
+use longan_nano::hal::pac::FMC;
+
+const KEY0: u32 = 0x4567_0123;
+const KEY1: u32 = 0xCDEF_89AB;
+
+// FMC_CTL0
+const PG: u32 = 1 << 0;
+const PER: u32 = 1 << 1;
+const START: u32 = 1 << 6;
+const LK: u32 = 1 << 7;
+
+// FMC_STAT0
+const BUSY: u32 = 1 << 0;
+const PGERR: u32 = 1 << 2;
+const WPERR: u32 = 1 << 4;
+const ENDF: u32 = 1 << 5;
+
+extern "C" {
+  static _skeystore: u32;
+  static _ekeystore: u32;
+}
+
+pub struct Fmc {
+  fmc: FMC,
+  start: usize,
+  size: usize,
+}
+
+impl Fmc {
+  pub fn new(fmc: FMC) -> Self {
+    let (start, end) = unsafe {
+      (
+        &_skeystore as *const u32 as usize,
+        &_ekeystore as *const u32 as usize,
+      )
+    };
+    Fmc {
+      fmc,
+      start,
+      size: end - start,
+    }
+  }
+
+  /// Runs op with the controller unlocked, then waits for it to finish
+  /// and checks the error flags.
+  fn unlocked<F: FnOnce(&FMC)>(&mut self, op: F) -> Result<(), flash::Error> {
+    let fmc = &self.fmc;
+    if fmc.ctl0.read().bits() & LK != 0 {
+      fmc.key0.write(|w| unsafe { w.bits(KEY0) });
+      fmc.key0.write(|w| unsafe { w.bits(KEY1) });
+    }
+
+    op(fmc);
+    while fmc.stat0.read().bits() & BUSY != 0 {}
+
+    let stat = fmc.stat0.read().bits();
+    // Status flags are cleared by writing 1.
+    fmc
+      .stat0
+      .write(|w| unsafe { w.bits(stat & (PGERR | WPERR | ENDF)) });
+    fmc.ctl0.write(|w| unsafe { w.bits(LK) });
+
+    if stat & (PGERR | WPERR) != 0 {
+      Err(flash::Error::Program)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl Flash for Fmc {
+  const PAGE_SIZE: usize = 1024;
+
+  fn size(&self) -> usize {
+    self.size
+  }
+
+  fn read(&self, offset: usize, buf: &mut [u8]) {
+    let addr = self.start + offset;
+    for (i, b) in buf.iter_mut().enumerate() {
+      *b = unsafe { core::ptr::read_volatile((addr + i) as *const u8) };
+    }
+  }
+
+  fn erase_page(&mut self, offset: usize) -> Result<(), flash::Error> {
+    if offset % Self::PAGE_SIZE != 0 || offset >= self.size {
+      return Err(flash::Error::Bounds);
+    }
+
+    let addr = (self.start + offset) as u32;
+    self.unlocked(|fmc| {
+      fmc.ctl0.write(|w| unsafe { w.bits(PER) });
+      fmc.addr0.write(|w| unsafe { w.bits(addr) });
+      fmc.ctl0.write(|w| unsafe { w.bits(PER | START) });
+    })
+  }
+
+  fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), flash::Error> {
+    if offset % 2 != 0 || data.len() % 2 != 0 || offset + data.len() > self.size
+    {
+      return Err(flash::Error::Bounds);
+    }
+
+    let addr = self.start + offset;
+    for (i, half) in data.chunks(2).enumerate() {
+      let ptr = (addr + 2 * i) as *mut u16;
+      let half = u16::from_le_bytes([half[0], half[1]]);
+      self.unlocked(|fmc| {
+        fmc.ctl0.write(|w| unsafe { w.bits(PG) });
+        unsafe { core::ptr::write_volatile(ptr, half) };
+      })?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,110 @@
+//! HMAC [RFC 2104] and PBKDF2 [RFC 8018] over the SHA-2 functions from
+//! sha2-const, with the same by-value builder API.
+
+use sha2_const::Sha256;
+use sha2_const::Sha512;
+
+macro_rules! hmac {
+  ($(#[$meta:meta])* $name:ident, $hash:ident, $block:expr, $len:expr) => {
+    $(#[$meta])*
+    #[derive(Clone)]
+    pub struct $name {
+      inner: $hash,
+      outer: $hash,
+    }
+
+    impl $name {
+      /// Keys longer than the block size are hashed first.
+      pub fn new(key: &[u8]) -> Self {
+        let mut k = [0u8; $block];
+        if key.len() > $block {
+          k[..$len].copy_from_slice(&$hash::new().update(key).finalize());
+        } else {
+          k[..key.len()].copy_from_slice(key);
+        }
+
+        let mut ipad = [0x36; $block];
+        let mut opad = [0x5C; $block];
+        for ((i, o), k) in ipad.iter_mut().zip(opad.iter_mut()).zip(k.iter()) {
+          *i ^= k;
+          *o ^= k;
+        }
+
+        $name {
+          inner: $hash::new().update(&ipad),
+          outer: $hash::new().update(&opad),
+        }
+      }
+
+      pub fn update(self, data: &[u8]) -> Self {
+        $name {
+          inner: self.inner.update(data),
+          outer: self.outer,
+        }
+      }
+
+      pub fn finalize(self) -> [u8; $len] {
+        let inner = self.inner.finalize();
+        self.outer.update(&inner).finalize()
+      }
+    }
+  };
+}
+
+hmac! {
+  /// HMAC-SHA-256
+  HmacSha256, Sha256, 64, 32
+}
+
+hmac! {
+  /// HMAC-SHA-512
+  HmacSha512, Sha512, 128, 64
+}
+
+macro_rules! pbkdf2 {
+  ($(#[$meta:meta])* $name:ident, $hmac:ident, $len:expr) => {
+    $(#[$meta])*
+    pub fn $name(
+      password: &[u8],
+      salt: &[&[u8]],
+      iterations: u32,
+      out: &mut [u8],
+    ) {
+      // The pads of the password are hashed once, every iteration starts
+      // from a copy of their states.
+      let prf = $hmac::new(password);
+
+      for (i, block) in out.chunks_mut($len).enumerate() {
+        // U_1 = PRF (P, S || INT (i))
+        let mut mac = prf.clone();
+        for s in salt {
+          mac = mac.update(s);
+        }
+        let mut u = mac.update(&(i as u32 + 1).to_be_bytes()).finalize();
+
+        // T_i = U_1 \xor U_2 \xor ... \xor U_c
+        let mut t = u;
+        for _ in 1..iterations {
+          u = prf.clone().update(&u).finalize();
+          for (t, u) in t.iter_mut().zip(u.iter()) {
+            *t ^= u;
+          }
+        }
+
+        block.copy_from_slice(&t[..block.len()]);
+      }
+    }
+  };
+}
+
+pbkdf2! {
+  /// PBKDF2 with HMAC-SHA-256 as the PRF. The salt is the concatenation of
+  /// the given parts. Fills all of out.
+  pbkdf2_hmac_sha256, HmacSha256, 32
+}
+
+pbkdf2! {
+  /// PBKDF2 with HMAC-SHA-512 as the PRF. The salt is the concatenation of
+  /// the given parts. Fills all of out.
+  pbkdf2_hmac_sha512, HmacSha512, 64
+}
@@ -0,0 +1,884 @@
+//! Persistent key storage in the flash pages reserved in memory.x.
+//!
+//! Every slot has two pages. A new record goes to the page that does not
+//! hold the current one and is committed by programming its magic last,
+//! after which the old page is erased. A power loss before the commit
+//! leaves the old record in place and a torn new one that fails its magic
+//! or CRC check. If both pages hold a valid record, because the power was
+//! lost before the old page was erased, the higher sequence number wins.
+//!
+//! Record layout, integers little-endian:
+//!
+//! | offset   | size | field                            |
+//! |----------|------|----------------------------------|
+//! | 0        | 4    | magic, "AKEY"                    |
+//! | 4        | 1    | version                          |
+//! | 5        | 1    | slot                             |
+//! | 6        | 2    | payload length, even             |
+//! | 8        | 4    | sequence number                  |
+//! | 12       | len  | payload                          |
+//! | 12 + len | 4    | CRC-32 of the bytes 0 .. 12 + len |
+//!
+//! A key is stored as its account and n, in the clear, followed by p
+//! sealed under the PIN. q is n / p, as p || q next to n would not fit in
+//! a page at 4096 bits. The account and n are authenticated along with
+//! the slot:
+//!
+//! | offset  | size | field                                    |
+//! |---------|------|------------------------------------------|
+//! | 0       | 32   | SHA-256 of n, the raw Arweave address    |
+//! | 32      | 16   | label, printable ASCII padded with zeros |
+//! | 48      | 2k   | n, big-endian                            |
+//! | 48 + 2k | ...  | sealed p, k bytes                        |
+//!
+//! All keys are sealed under the same, device-wide, PIN. Version 1
+//! records, which sealed p || q and did not hold n, are not read.
+//!
+//! The last page of the region holds the PIN retry counter. Each attempt
+//! programs the next of its first MAX_ATTEMPTS half-words to zero before
+//! the PIN is checked, and only a right PIN erases the page again. A
+//! half-word that is not 0xFFFF, e.g. after an interrupted write, counts
+//! as used.
+//!
+//! The page before it holds the DRBG seed file, "SEED", SEED_LEN bytes of
+//! seed and a CRC-32 of both, committed by its magic like a record. It is
+//! rewritten in place, so a power loss while it is refreshed loses it.
+
+use crate::flash;
+use crate::flash::Flash;
+use crate::owner;
+use crate::pin;
+use crate::pin::PinKey;
+use crate::rsa;
+use crate::rsa::PrivateKey;
+use crypto_bigint::Limb;
+use crypto_bigint::UInt;
+use rand_core::CryptoRng;
+use rand_core::RngCore;
+use zeroize::Zeroize;
+
+const MAGIC: [u8; 4] = *b"AKEY";
+const SEED_MAGIC: [u8; 4] = *b"SEED";
+const VERSION: u8 = 2;
+const HEADER_LEN: usize = 12;
+const CRC_LEN: usize = 4;
+
+/// Wrong PINs in a row after which the store is wiped.
+pub const MAX_ATTEMPTS: usize = 10;
+
+/// Largest payload, so that a record fits in a 1 KB page.
+pub const MAX_PAYLOAD: usize = 1024 - HEADER_LEN - CRC_LEN;
+
+pub const MAX_LABEL_LEN: usize = 16;
+const ACCOUNT_LEN: usize = 32 + MAX_LABEL_LEN;
+
+pub const SEED_LEN: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  Flash(flash::Error),
+  /// There is no such slot in the reserved region.
+  Slot,
+  /// The slot holds no valid record.
+  Empty,
+  /// Payload longer than MAX_PAYLOAD or of odd length.
+  Size,
+  /// The record is not a key of the requested size, or the key has no CRT
+  /// parameters to store.
+  Key,
+  /// The label is too long or not printable ASCII.
+  Label,
+  /// The key does not decrypt under the PIN.
+  Pin,
+  /// The last PIN attempt failed and the store was wiped.
+  Wiped,
+}
+
+impl From<flash::Error> for Error {
+  fn from(e: flash::Error) -> Self {
+    Error::Flash(e)
+  }
+}
+
+/// CRC-32 (IEEE 802.3) of the concatenation of the parts.
+fn crc32(parts: &[&[u8]]) -> u32 {
+  let mut crc = !0u32;
+  for b in parts.iter().flat_map(|p| p.iter()) {
+    crc ^= *b as u32;
+    for _ in 0..8 {
+      crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+    }
+  }
+  !crc
+}
+
+/// Labels are up to MAX_LABEL_LEN bytes of printable ASCII, to fit the
+/// screen.
+pub fn valid_label(label: &[u8]) -> bool {
+  label.len() <= MAX_LABEL_LEN
+    && label.iter().all(|b| b.is_ascii_graphic() || *b == b' ')
+}
+
+/// The public part of a stored key.
+pub struct Account {
+  /// SHA-256 of the big-endian modulus. Its base64url encoding is the
+  /// Arweave address.
+  pub address: [u8; 32],
+  label: [u8; MAX_LABEL_LEN],
+}
+
+impl Account {
+  pub fn new<const L: usize, const H: usize>(
+    key: &PrivateKey<L, H>,
+    label: &[u8],
+  ) -> Result<Self, Error> {
+    if !valid_label(label) {
+      return Err(Error::Label);
+    }
+
+    let mut account = Account {
+      address: owner::address_hash(&key.n),
+      label: [0; MAX_LABEL_LEN],
+    };
+    account.label[..label.len()].copy_from_slice(label);
+    Ok(account)
+  }
+
+  pub fn label(&self) -> &str {
+    let len = self.label.iter().position(|b| *b == 0);
+    let label = &self.label[..len.unwrap_or(MAX_LABEL_LEN)];
+    // Checked to be ASCII when stored and authenticated since.
+    core::str::from_utf8(label).unwrap_or("")
+  }
+
+  fn to_bytes(&self) -> [u8; ACCOUNT_LEN] {
+    let mut bytes = [0u8; ACCOUNT_LEN];
+    bytes[..32].copy_from_slice(&self.address);
+    bytes[32..].copy_from_slice(&self.label);
+    bytes
+  }
+
+  fn from_bytes(bytes: &[u8]) -> Self {
+    let mut account = Account {
+      address: [0; 32],
+      label: [0; MAX_LABEL_LEN],
+    };
+    account.address.copy_from_slice(&bytes[..32]);
+    account.label.copy_from_slice(&bytes[32..ACCOUNT_LEN]);
+    account
+  }
+}
+
+/// The slot, account and n, authenticated along with the sealed key.
+fn aad(slot: usize, clear: &[u8]) -> [u8; 1 + MAX_CLEAR_LEN] {
+  let mut aad = [0u8; 1 + MAX_CLEAR_LEN];
+  aad[0] = slot as u8;
+  aad[1..1 + clear.len()].copy_from_slice(clear);
+  aad
+}
+
+/// Longest clear part of a record, the account and a 4096-bit n.
+const MAX_CLEAR_LEN: usize = ACCOUNT_LEN + 4096 / 8;
+
+/// Length of the clear part of a record of len bytes, the account and n.
+/// The sealed p is half as long as n.
+fn clear_len(len: usize) -> Option<usize> {
+  let keys = len.checked_sub(ACCOUNT_LEN + pin::OVERHEAD)?;
+  if keys % 3 != 0 || keys / 3 * 2 > MAX_CLEAR_LEN - ACCOUNT_LEN {
+    return None;
+  }
+  Some(ACCOUNT_LEN + keys / 3 * 2)
+}
+
+pub struct KeyStore<F: Flash> {
+  flash: F,
+}
+
+impl<F: Flash> KeyStore<F> {
+  pub fn new(flash: F) -> Self {
+    KeyStore { flash }
+  }
+
+  /// Number of slots in the region, without the seed and counter pages.
+  pub fn slots(&self) -> usize {
+    (self.flash.size() - 2 * F::PAGE_SIZE) / (2 * F::PAGE_SIZE)
+  }
+
+  fn seed_page(&self) -> usize {
+    self.flash.size() - 2 * F::PAGE_SIZE
+  }
+
+  fn counter_page(&self) -> usize {
+    self.flash.size() - F::PAGE_SIZE
+  }
+
+  /// Offset of page i, 0 or 1, of slot.
+  fn page(slot: usize, i: usize) -> usize {
+    (2 * slot + i) * F::PAGE_SIZE
+  }
+
+  /// Validates the record in page i of slot and reads its payload into
+  /// buf. Returns the sequence number and payload length.
+  fn check(
+    &self,
+    slot: usize,
+    i: usize,
+    buf: &mut [u8; MAX_PAYLOAD],
+  ) -> Option<(u32, usize)> {
+    let at = Self::page(slot, i);
+    let mut header = [0u8; HEADER_LEN];
+    self.flash.read(at, &mut header);
+
+    let len = u16::from_le_bytes([header[6], header[7]]) as usize;
+    if header[..4] != MAGIC
+      || header[4] != VERSION
+      || header[5] as usize != slot
+      || len > MAX_PAYLOAD
+      || len % 2 != 0
+    {
+      return None;
+    }
+
+    self.flash.read(at + HEADER_LEN, &mut buf[..len]);
+    let mut crc = [0u8; CRC_LEN];
+    self.flash.read(at + HEADER_LEN + len, &mut crc);
+    if u32::from_le_bytes(crc) != crc32(&[&header, &buf[..len]]) {
+      return None;
+    }
+
+    let seq =
+      u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+    Some((seq, len))
+  }
+
+  /// The newest valid record of slot, with its payload read into buf.
+  /// Returns the page, sequence number and payload length.
+  fn current(
+    &self,
+    slot: usize,
+    buf: &mut [u8; MAX_PAYLOAD],
+  ) -> Option<(usize, u32, usize)> {
+    let mut other = [0u8; MAX_PAYLOAD];
+    match (self.check(slot, 0, buf), self.check(slot, 1, &mut other)) {
+      (Some((a, _)), Some((b, len))) if b > a => {
+        buf[..len].copy_from_slice(&other[..len]);
+        Some((1, b, len))
+      }
+      (Some((a, len)), _) => Some((0, a, len)),
+      (None, Some((b, len))) => {
+        buf[..len].copy_from_slice(&other[..len]);
+        Some((1, b, len))
+      }
+      (None, None) => None,
+    }
+  }
+
+  /// Reads the payload of slot into buf and returns its length.
+  pub fn read(
+    &self,
+    slot: usize,
+    buf: &mut [u8; MAX_PAYLOAD],
+  ) -> Result<usize, Error> {
+    if slot >= self.slots() {
+      return Err(Error::Slot);
+    }
+
+    match self.current(slot, buf) {
+      Some((_, _, len)) => Ok(len),
+      None => Err(Error::Empty),
+    }
+  }
+
+  /// Replaces the payload of slot. On error the previous payload is kept,
+  /// unless the new one was already committed.
+  pub fn write(&mut self, slot: usize, payload: &[u8]) -> Result<(), Error> {
+    if slot >= self.slots() {
+      return Err(Error::Slot);
+    }
+    if payload.len() > MAX_PAYLOAD || payload.len() % 2 != 0 {
+      return Err(Error::Size);
+    }
+
+    let mut buf = [0u8; MAX_PAYLOAD];
+    let (old, seq) = match self.current(slot, &mut buf) {
+      Some((i, seq, _)) => (Some(i), seq.wrapping_add(1)),
+      None => (None, 0),
+    };
+    let new = match old {
+      Some(i) => 1 - i,
+      None => 0,
+    };
+    let at = Self::page(slot, new);
+
+    let mut header = [0u8; HEADER_LEN];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4] = VERSION;
+    header[5] = slot as u8;
+    header[6..8].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+    header[8..12].copy_from_slice(&seq.to_le_bytes());
+    let crc = crc32(&[&header, payload]);
+
+    // Everything but the magic, then the magic to commit.
+    self.flash.erase_page(at)?;
+    self.flash.write(at + 4, &header[4..])?;
+    self.flash.write(at + HEADER_LEN, payload)?;
+    self
+      .flash
+      .write(at + HEADER_LEN + payload.len(), &crc.to_le_bytes())?;
+    self.flash.write(at, &MAGIC)?;
+
+    match self.check(slot, new, &mut buf) {
+      Some((s, len)) if s == seq && buf[..len] == *payload => {}
+      _ => return Err(Error::Flash(flash::Error::Program)),
+    }
+
+    if let Some(i) = old {
+      self.flash.erase_page(Self::page(slot, i))?;
+    }
+    Ok(())
+  }
+
+  /// PIN attempts left before the store is wiped.
+  pub fn attempts_left(&self) -> usize {
+    let at = self.counter_page();
+    let mut used = 0;
+    for i in 0..MAX_ATTEMPTS {
+      let mut half = [0u8; 2];
+      self.flash.read(at + 2 * i, &mut half);
+      if half != [0xFF, 0xFF] {
+        used += 1;
+      }
+    }
+    MAX_ATTEMPTS - used
+  }
+
+  /// Records a PIN attempt, returns the attempts left after it.
+  fn use_attempt(&mut self) -> Result<usize, Error> {
+    let left = self.attempts_left();
+    if left == 0 {
+      return Ok(0);
+    }
+
+    let at = self.counter_page() + 2 * (MAX_ATTEMPTS - left);
+    self.flash.write(at, &[0, 0])?;
+    Ok(left - 1)
+  }
+
+  fn reset_attempts(&mut self) -> Result<(), Error> {
+    if self.attempts_left() < MAX_ATTEMPTS {
+      self.flash.erase_page(self.counter_page())?;
+    }
+    Ok(())
+  }
+
+  /// The DRBG seed file, None if it was never written or its last refresh
+  /// was cut short.
+  pub fn seed(&self) -> Option<[u8; SEED_LEN]> {
+    let at = self.seed_page();
+    let mut magic = [0u8; 4];
+    let mut seed = [0u8; SEED_LEN];
+    let mut crc = [0u8; CRC_LEN];
+    self.flash.read(at, &mut magic);
+    self.flash.read(at + 4, &mut seed);
+    self.flash.read(at + 4 + SEED_LEN, &mut crc);
+
+    if magic != SEED_MAGIC || u32::from_le_bytes(crc) != crc32(&[&magic, &seed])
+    {
+      return None;
+    }
+    Some(seed)
+  }
+
+  /// Replaces the DRBG seed file.
+  pub fn save_seed(&mut self, seed: &[u8; SEED_LEN]) -> Result<(), Error> {
+    let at = self.seed_page();
+    let crc = crc32(&[&SEED_MAGIC, seed]);
+    self.flash.erase_page(at)?;
+    self.flash.write(at + 4, seed)?;
+    self.flash.write(at + 4 + SEED_LEN, &crc.to_le_bytes())?;
+    self.flash.write(at, &SEED_MAGIC)?;
+
+    match self.seed() {
+      Some(s) if s == *seed => Ok(()),
+      _ => Err(Error::Flash(flash::Error::Program)),
+    }
+  }
+
+  /// Erases every slot, then resets the retry counter. If this is cut
+  /// short, the counter is still used up and the next unlock wipes again.
+  pub fn wipe(&mut self) -> Result<(), Error> {
+    for page in 0..2 * self.slots() {
+      self.flash.erase_page(page * F::PAGE_SIZE)?;
+    }
+    self.reset_attempts()
+  }
+
+  /// The first slot that holds a record.
+  fn first(&self) -> Result<usize, Error> {
+    let mut buf = [0u8; MAX_PAYLOAD];
+    (0..self.slots())
+      .find(|slot| self.read(*slot, &mut buf).is_ok())
+      .ok_or(Error::Empty)
+  }
+
+  /// Opens the key in the record of slot in place.
+  fn open(
+    &self,
+    slot: usize,
+    pin: &PinKey,
+    record: &mut [u8],
+  ) -> Result<(), Error> {
+    let clear = clear_len(record.len()).ok_or(Error::Key)?;
+    let (clear, sealed) = record.split_at_mut(clear);
+    pin
+      .open(&aad(slot, clear)[..1 + clear.len()], sealed)
+      .map_err(|_| Error::Pin)
+  }
+
+  /// Checks pin against the key in slot, counting the attempt against the
+  /// retry counter. The attempt is recorded before the PIN is checked and
+  /// only given back if the PIN is right. If it was the last one, the
+  /// store is wiped. On success the record is in buf, opened, and its
+  /// length is returned.
+  fn attempt(
+    &mut self,
+    slot: usize,
+    pin: &PinKey,
+    buf: &mut [u8; MAX_PAYLOAD],
+  ) -> Result<usize, Error> {
+    if self.attempts_left() == 0 {
+      self.wipe()?;
+      return Err(Error::Wiped);
+    }
+
+    // An empty slot does not cost an attempt.
+    let len = self.read(slot, buf)?;
+
+    let left = self.use_attempt()?;
+    match self.open(slot, pin, &mut buf[..len]) {
+      Ok(()) => {
+        self.reset_attempts()?;
+        Ok(len)
+      }
+      Err(Error::Pin) if left == 0 => {
+        self.wipe()?;
+        Err(Error::Wiped)
+      }
+      Err(e) => Err(e),
+    }
+  }
+
+  /// Checks the device PIN against the first key, see attempt. Empty if
+  /// there is no key yet.
+  pub fn unlock(&mut self, pin: &PinKey) -> Result<(), Error> {
+    let slot = self.first()?;
+    let mut buf = [0u8; MAX_PAYLOAD];
+    let checked = self.attempt(slot, pin, &mut buf);
+    buf.zeroize();
+    checked.map(|_| ())
+  }
+
+  /// Re-encrypts every key under a new PIN, one slot at a time from the
+  /// last, so the first key, which unlock checks, is changed last. The old
+  /// PIN is checked like in unlock. If this is interrupted before the
+  /// first key is written, the old PIN still unlocks and running it again
+  /// with the same PINs finishes it.
+  pub fn change_pin<R: RngCore + CryptoRng>(
+    &mut self,
+    rng: &mut R,
+    old: &PinKey,
+    new: &PinKey,
+  ) -> Result<(), Error> {
+    let first = self.first()?;
+    let mut buf = [0u8; MAX_PAYLOAD];
+    self.attempt(first, old, &mut buf)?;
+
+    let result = self.reseal(rng, old, new, &mut buf);
+    buf.zeroize();
+    result
+  }
+
+  fn reseal<R: RngCore + CryptoRng>(
+    &mut self,
+    rng: &mut R,
+    old: &PinKey,
+    new: &PinKey,
+    buf: &mut [u8; MAX_PAYLOAD],
+  ) -> Result<(), Error> {
+    for slot in (0..self.slots()).rev() {
+      let len = match self.read(slot, buf) {
+        Ok(len) => len,
+        Err(Error::Empty) => continue,
+        Err(e) => return Err(e),
+      };
+      let record = &mut buf[..len];
+      if self.open(slot, old, record).is_err() {
+        // Changed before an interruption.
+        self.open(slot, new, record)?;
+        continue;
+      }
+
+      // Opened above, so the length is valid.
+      let clear = clear_len(record.len()).ok_or(Error::Key)?;
+      let (clear, sealed) = record.split_at_mut(clear);
+      new.seal(rng, &aad(slot, clear)[..1 + clear.len()], sealed);
+      self.write(slot, record)?;
+    }
+    Ok(())
+  }
+
+  /// The account of the key in slot.
+  pub fn account(&self, slot: usize) -> Result<Account, Error> {
+    let mut buf = [0u8; MAX_PAYLOAD];
+    let len = self.read(slot, &mut buf)?;
+    clear_len(len).ok_or(Error::Key)?;
+    Ok(Account::from_bytes(&buf))
+  }
+
+  /// The modulus of the key in slot, which is stored in the clear and can
+  /// be read while locked.
+  pub fn modulus<const L: usize>(&self, slot: usize) -> Result<UInt<L>, Error> {
+    let mut buf = [0u8; MAX_PAYLOAD];
+    let len = self.read(slot, &mut buf)?;
+    let n_len = L * Limb::BYTE_SIZE;
+    if clear_len(len) != Some(ACCOUNT_LEN + n_len) {
+      return Err(Error::Key);
+    }
+    Ok(rsa::uint_from_be(&buf[ACCOUNT_LEN..ACCOUNT_LEN + n_len]))
+  }
+
+  /// Loads the key in slot, decrypting it with pin. Only n and p are
+  /// stored, the rest of the key is computed from them. The PIN is not
+  /// counted against the retry counter, check it with unlock first.
+  pub fn load_key<const L: usize, const H: usize>(
+    &self,
+    slot: usize,
+    pin: &PinKey,
+  ) -> Result<PrivateKey<L, H>, Error> {
+    let mut buf = [0u8; MAX_PAYLOAD];
+    let len = self.read(slot, &mut buf)?;
+    let k = H * Limb::BYTE_SIZE;
+    let clear = ACCOUNT_LEN + 2 * k;
+    if len != clear + k + pin::OVERHEAD {
+      return Err(Error::Key);
+    }
+
+    self.open(slot, pin, &mut buf[..len])?;
+    let n = rsa::uint_from_be::<L>(&buf[ACCOUNT_LEN..clear]);
+    let mut p = rsa::uint_from_be::<H>(pin::data(&mut buf[clear..len]));
+    let mut q = rsa::cofactor(&n, &p);
+    buf.zeroize();
+
+    let key = PrivateKey::from_primes(p, q).ok_or(Error::Key);
+    p.zeroize();
+    q.zeroize();
+    match key {
+      Ok(key) if key.n == n => Ok(key),
+      _ => Err(Error::Key),
+    }
+  }
+
+  /// Stores key in slot as its account, n and p, encrypted with pin, and
+  /// resets the retry counter. The PIN must be the one the other keys
+  /// are under.
+  pub fn save_key<R: RngCore + CryptoRng, const L: usize, const H: usize>(
+    &mut self,
+    rng: &mut R,
+    slot: usize,
+    key: &PrivateKey<L, H>,
+    label: &[u8],
+    pin: &PinKey,
+  ) -> Result<(), Error> {
+    let crt = key.crt.as_ref().ok_or(Error::Key)?;
+    let account = Account::new(key, label)?.to_bytes();
+    let k = H * Limb::BYTE_SIZE;
+    let clear = ACCOUNT_LEN + 2 * k;
+    let len = clear + k + pin::OVERHEAD;
+    if len > MAX_PAYLOAD || clear > MAX_CLEAR_LEN {
+      return Err(Error::Size);
+    }
+
+    let mut buf = [0u8; MAX_PAYLOAD];
+    let (head, sealed) = buf[..len].split_at_mut(clear);
+    head[..ACCOUNT_LEN].copy_from_slice(&account);
+    rsa::uint_to_be(&key.n, &mut head[ACCOUNT_LEN..]);
+    rsa::uint_to_be(&crt.p, pin::data(sealed));
+    pin.seal(rng, &aad(slot, head)[..1 + clear], sealed);
+
+    self.write(slot, &buf[..len])?;
+    self.reset_attempts()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::flash::RamFlash;
+  use rand_core::SeedableRng;
+  use rand_hc::Hc128Rng;
+  use sha2_const::Sha256;
+
+  /// Two slots, the seed file and the counter page.
+  type Store = KeyStore<RamFlash<{ 6 * 1024 }>>;
+
+  type Key = PrivateKey<{ 1024 / Limb::BIT_SIZE }, { 512 / Limb::BIT_SIZE }>;
+
+  /// The RNG of a test and a key generated from it.
+  fn setup() -> (Hc128Rng, Key) {
+    let mut rng = Hc128Rng::from_seed([0x15; 32]);
+    let key = Key::generate(&mut rng).unwrap();
+    (rng, key)
+  }
+
+  #[test]
+  fn crc() {
+    assert_eq!(crc32(&[b"1234", b"56789"]), 0xCBF4_3926);
+  }
+
+  #[test]
+  fn read_write() {
+    let mut store = Store::new(RamFlash::new());
+    let mut buf = [0u8; MAX_PAYLOAD];
+    assert_eq!(store.slots(), 2);
+    assert_eq!(store.read(0, &mut buf), Err(Error::Empty));
+    assert_eq!(store.read(2, &mut buf), Err(Error::Slot));
+
+    for (i, payload) in [&b"first"[..], b"second", b"third!"].iter().enumerate()
+    {
+      let payload = &payload[..payload.len() & !1];
+      store.write(0, payload).unwrap();
+      store.write(1, &[i as u8; 4]).unwrap();
+
+      let len = store.read(0, &mut buf).unwrap();
+      assert_eq!(&buf[..len], payload);
+      let len = store.read(1, &mut buf).unwrap();
+      assert_eq!(&buf[..len], &[i as u8; 4]);
+    }
+
+    assert_eq!(store.write(0, b"odd"), Err(Error::Size));
+    assert_eq!(store.write(0, &[0; MAX_PAYLOAD + 2]), Err(Error::Size));
+  }
+
+  #[test]
+  fn corrupt_record() {
+    let mut store = Store::new(RamFlash::new());
+    let mut buf = [0u8; MAX_PAYLOAD];
+    store.write(0, b"old!").unwrap();
+    store.write(0, b"new!").unwrap();
+
+    // The newest record is in page 1; flipping a payload bit there must
+    // not fall back to the erased page 0.
+    store.flash.data[1024 + HEADER_LEN] ^= 1;
+    assert_eq!(store.read(0, &mut buf), Err(Error::Empty));
+
+    // A write after that still works.
+    store.write(0, b"next").unwrap();
+    let len = store.read(0, &mut buf).unwrap();
+    assert_eq!(&buf[..len], b"next");
+  }
+
+  #[test]
+  fn power_loss() {
+    let payload = [0xA5; 512];
+    for budget in 0.. {
+      let mut store = Store::new(RamFlash::new());
+      store.write(0, b"old key!").unwrap();
+
+      store.flash.budget = Some(budget);
+      let result = store.write(0, &payload);
+      store.flash.budget = None;
+
+      // After the power comes back, the slot holds either record.
+      let mut buf = [0u8; MAX_PAYLOAD];
+      let len = store.read(0, &mut buf).unwrap();
+      if result.is_ok() {
+        assert_eq!(&buf[..len], &payload[..]);
+      } else {
+        assert!(&buf[..len] == b"old key!" || buf[..len] == payload[..]);
+      }
+
+      // And can be written again.
+      store.write(0, b"next").unwrap();
+      let len = store.read(0, &mut buf).unwrap();
+      assert_eq!(&buf[..len], b"next");
+
+      if result.is_ok() {
+        break;
+      }
+    }
+  }
+
+  #[test]
+  fn seed_file() {
+    let mut store = Store::new(RamFlash::new());
+    assert_eq!(store.seed(), None);
+    store.save_seed(&[1; SEED_LEN]).unwrap();
+    assert_eq!(store.seed(), Some([1; SEED_LEN]));
+
+    // Cut short, the old seed is gone but a torn one is never read.
+    for budget in 0.. {
+      store.flash.budget = Some(budget);
+      let result = store.save_seed(&[2; SEED_LEN]);
+      store.flash.budget = None;
+      match result {
+        Ok(()) => break,
+        Err(_) => assert_eq!(store.seed(), None),
+      }
+    }
+    assert_eq!(store.seed(), Some([2; SEED_LEN]));
+
+    // Not touched by the slots or a wipe.
+    store.write(1, b"next").unwrap();
+    store.wipe().unwrap();
+    assert_eq!(store.seed(), Some([2; SEED_LEN]));
+  }
+
+  #[test]
+  fn key() {
+    type Small = PrivateKey<{ 512 / Limb::BIT_SIZE }, { 256 / Limb::BIT_SIZE }>;
+    let (mut rng, key) = setup();
+    let pin = PinKey::derive(b"1234", b"device").unwrap();
+
+    let mut store = Store::new(RamFlash::new());
+    let empty: Result<Key, _> = store.load_key(0, &pin);
+    assert_eq!(empty.err(), Some(Error::Empty));
+    store
+      .save_key(&mut rng, 1, &key, b"treasury", &pin)
+      .unwrap();
+
+    let loaded: Key = store.load_key(1, &pin).unwrap();
+    assert!(loaded.n == key.n);
+    loaded.self_test(&mut rng).unwrap();
+
+    let account = store.account(1).unwrap();
+    assert_eq!(account.label(), "treasury");
+    let mut n = [0u8; 1024 / 8];
+    rsa::uint_to_be(&key.n, &mut n);
+    assert_eq!(account.address, Sha256::new().update(&n).finalize());
+    // The modulus is read without the PIN.
+    assert!(store.modulus(1).unwrap() == key.n);
+    let small = store.modulus::<{ 512 / Limb::BIT_SIZE }>(1);
+    assert_eq!(small.err(), Some(Error::Key));
+
+    let wrong = PinKey::derive(b"4321", b"device").unwrap();
+    let locked: Result<Key, _> = store.load_key(1, &wrong);
+    assert_eq!(locked.err(), Some(Error::Pin));
+
+    let small: Result<Small, _> = store.load_key(1, &pin);
+    assert_eq!(small.err(), Some(Error::Key));
+
+    // Neither p nor q is in flash in the clear.
+    let crt = key.crt.as_ref().unwrap();
+    let mut p = [0u8; 512 / 8];
+    rsa::uint_to_be(&crt.p, &mut p);
+    assert!(!store.flash.data.windows(8).any(|w| w == &p[..8]));
+
+    // The label is authenticated.
+    let mut buf = [0u8; MAX_PAYLOAD];
+    let len = store.read(1, &mut buf).unwrap();
+    buf[32] ^= 1;
+    store.write(1, &buf[..len]).unwrap();
+    let modified: Result<Key, _> = store.load_key(1, &pin);
+    assert_eq!(modified.err(), Some(Error::Pin));
+
+    // So is n.
+    buf[32] ^= 1;
+    buf[ACCOUNT_LEN] ^= 1;
+    store.write(1, &buf[..len]).unwrap();
+    let modified: Result<Key, _> = store.load_key(1, &pin);
+    assert_eq!(modified.err(), Some(Error::Pin));
+
+    for label in [&b"seventeen chars!!"[..], b"tab\t", "\u{e9}".as_bytes()] {
+      let saved = store.save_key(&mut rng, 0, &key, label, &pin);
+      assert_eq!(saved, Err(Error::Label));
+    }
+  }
+
+  #[test]
+  fn retry_counter() {
+    let (mut rng, key) = setup();
+    let pin = PinKey::derive(b"1234", b"device").unwrap();
+    let wrong = PinKey::derive(b"4321", b"device").unwrap();
+
+    let mut store = Store::new(RamFlash::new());
+    // No key, no attempt used.
+    assert_eq!(store.unlock(&wrong), Err(Error::Empty));
+    assert_eq!(store.attempts_left(), MAX_ATTEMPTS);
+
+    store
+      .save_key(&mut rng, 1, &key, b"uploader", &pin)
+      .unwrap();
+    for left in (1..MAX_ATTEMPTS).rev() {
+      assert_eq!(store.unlock(&wrong), Err(Error::Pin));
+      assert_eq!(store.attempts_left(), left);
+    }
+
+    // The right PIN gives all attempts back.
+    store.unlock(&pin).unwrap();
+    assert_eq!(store.attempts_left(), MAX_ATTEMPTS);
+
+    // So does a new PIN.
+    let new = PinKey::derive(b"5678", b"device").unwrap();
+    assert_eq!(store.unlock(&wrong), Err(Error::Pin));
+    store.change_pin(&mut rng, &pin, &new).unwrap();
+    assert_eq!(store.attempts_left(), MAX_ATTEMPTS);
+    assert_eq!(store.unlock(&pin), Err(Error::Pin));
+    store.unlock(&new).unwrap();
+    let loaded: Key = store.load_key(1, &new).unwrap();
+    assert!(loaded.n == key.n);
+
+    for _ in 1..MAX_ATTEMPTS {
+      assert_eq!(store.unlock(&wrong), Err(Error::Pin));
+    }
+
+    // The power is cut after the last attempt is recorded, before the
+    // wipe. Coming back, the right PIN does not help any more.
+    store.flash.budget = Some(1);
+    assert!(matches!(store.unlock(&wrong), Err(Error::Flash(_))));
+    store.flash.budget = None;
+    assert_eq!(store.attempts_left(), 0);
+
+    assert_eq!(store.unlock(&new), Err(Error::Wiped));
+    let mut buf = [0u8; MAX_PAYLOAD];
+    assert_eq!(store.read(1, &mut buf), Err(Error::Empty));
+    assert_eq!(store.attempts_left(), MAX_ATTEMPTS);
+  }
+
+  #[test]
+  fn change_pin() {
+    let (mut rng, key) = setup();
+    let keys = [key, Key::generate(&mut rng).unwrap()];
+    let old = PinKey::derive(b"1234", b"device").unwrap();
+    let new = PinKey::derive(b"5678", b"device").unwrap();
+
+    let mut store = Store::new(RamFlash::new());
+    assert_eq!(store.change_pin(&mut rng, &old, &new), Err(Error::Empty));
+    for (slot, key) in keys.iter().enumerate() {
+      store.save_key(&mut rng, slot, key, b"", &old).unwrap();
+    }
+
+    // Cut the power at every point, then finish with the same PINs.
+    for budget in 0.. {
+      let mut interrupted = Store::new(RamFlash {
+        data: store.flash.data,
+        budget: Some(budget),
+      });
+      let result = interrupted.change_pin(&mut rng, &old, &new);
+      interrupted.flash.budget = None;
+      if interrupted.unlock(&old).is_ok() {
+        interrupted.change_pin(&mut rng, &old, &new).unwrap();
+      }
+      interrupted.unlock(&new).unwrap();
+
+      for (slot, key) in keys.iter().enumerate() {
+        let loaded: Key = interrupted.load_key(slot, &new).unwrap();
+        assert!(loaded.n == key.n);
+      }
+      assert_eq!(interrupted.attempts_left(), MAX_ATTEMPTS);
+
+      if result.is_ok() {
+        break;
+      }
+    }
+  }
+}
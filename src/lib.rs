@@ -0,0 +1,35 @@
+//! The platform independent parts of the firmware, kept in a library so
+//! that they can be tested on the host with `make test`.
+
+#![cfg_attr(feature = "riscv-asm", feature(asm))]
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod base64;
+pub mod deephash;
+pub mod derive;
+pub mod drbg;
+pub mod entropy;
+pub mod flash;
+pub mod hmac;
+pub mod keystore;
+pub mod mnemonic;
+pub mod msg;
+pub mod owner;
+pub mod pin;
+pub mod prime;
+pub mod rsa;
+#[cfg(all(feature = "riscv-asm", target_arch = "riscv32"))]
+mod rv32;
+
+#[cfg(test)]
+mod test_util {
+  /// Decodes a hex string, for test vectors.
+  pub fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+      .step_by(2)
+      .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+      .collect()
+  }
+}
@@ -4,23 +4,44 @@
 
 extern crate alloc;
 
+mod button;
+mod fmc;
 mod heap;
-mod msg;
-mod rsa;
+mod noise;
 mod uart;
 
+use arienai::deephash;
+use arienai::derive;
+use arienai::drbg;
+use arienai::drbg::Entropy;
+use arienai::drbg::HmacDrbg;
+use arienai::keystore;
+use arienai::keystore::KeyStore;
+use arienai::mnemonic;
+use arienai::msg;
+use arienai::owner;
+use arienai::pin;
+use arienai::rsa;
+
 use rand::Rng;
-use rand_core::SeedableRng;
-use rand_hc::Hc128Rng;
+use rand_core::CryptoRng;
+use rand_core::RngCore;
 use riscv::asm;
 use riscv::interrupt;
+use riscv::register::mcycle;
 use riscv_rt::entry;
+use sha2_const::Sha256;
 
+use alloc::format;
+use alloc::string::String;
 use core::alloc::Layout;
 use core::convert::TryFrom;
 use core::panic::PanicInfo;
+use zeroize::Zeroize;
 
 use msg::Message;
+use msg::OwnerFormat;
+use msg::Scheme;
 
 use crypto_bigint::Encoding;
 use embedded_graphics::image::Image;
@@ -44,6 +65,16 @@ use longan_nano::{lcd, lcd_pins};
 
 const ARWEAVE_LOGO: &[u8] = include_bytes!("verto.raw");
 
+/// Core clock cycles per second at 108 MHz.
+const CYCLES_PER_SECOND: u64 = 108_000_000;
+
+/// How long the device stays unlocked after Unlock or the last use of a
+/// key by Sign, SignWith, SignMessage, SignDeepHash or Decrypt.
+const SESSION_TIMEOUT_SECONDS: u64 = 60;
+
+/// Requests of the DRBG between reseeds from the noise sources.
+const RESEED_INTERVAL: u64 = 1024;
+
 static mut STDOUT: Option<uart::UART> = None;
 
 #[entry]
@@ -78,79 +109,49 @@ fn main() -> ! {
 
   let (tx, rx) = serial.split();
 
-  // XXX: Replace with your private key.
-
-  let n = crypto_bigint::U4096::from_be_bytes([
-    179, 191, 18, 125, 114, 171, 181, 58, 71, 241, 14, 101, 93, 155, 45, 208,
-    179, 241, 73, 89, 229, 21, 228, 200, 114, 57, 136, 3, 78, 206, 137, 126,
-    108, 197, 243, 99, 41, 57, 185, 184, 72, 31, 219, 192, 214, 70, 135, 192,
-    75, 217, 197, 60, 36, 140, 184, 48, 52, 22, 186, 79, 8, 221, 147, 134, 155,
-    115, 120, 148, 169, 240, 21, 138, 187, 41, 250, 100, 97, 255, 173, 175, 4,
-    134, 60, 73, 20, 136, 5, 173, 195, 137, 223, 23, 239, 200, 124, 237, 58,
-    176, 67, 46, 209, 195, 191, 43, 8, 81, 94, 205, 232, 218, 209, 73, 37, 46,
-    227, 250, 29, 207, 122, 172, 201, 232, 21, 72, 37, 4, 193, 79, 22, 87, 108,
-    14, 125, 72, 195, 220, 80, 223, 161, 142, 233, 172, 104, 183, 55, 59, 205,
-    222, 101, 45, 219, 211, 110, 213, 153, 189, 234, 91, 223, 70, 196, 24, 126,
-    176, 184, 169, 80, 98, 34, 129, 136, 12, 4, 193, 235, 222, 195, 131, 12,
-    251, 92, 41, 92, 2, 105, 59, 103, 54, 8, 61, 87, 79, 143, 54, 154, 33, 114,
-    169, 182, 205, 143, 199, 212, 196, 145, 213, 102, 185, 208, 22, 83, 22,
-    176, 67, 252, 141, 68, 124, 88, 62, 131, 138, 105, 149, 60, 0, 22, 153,
-    100, 115, 249, 190, 55, 33, 140, 237, 232, 246, 249, 181, 205, 54, 62, 223,
-    186, 210, 21, 154, 235, 60, 34, 59, 222, 219, 190, 217, 124, 77, 64, 52,
-    102, 11, 159, 98, 26, 195, 134, 168, 60, 28, 119, 27, 6, 253, 70, 228, 214,
-    30, 75, 39, 67, 255, 76, 3, 192, 147, 39, 136, 88, 113, 140, 128, 24, 124,
-    142, 61, 202, 122, 230, 128, 12, 243, 137, 90, 176, 227, 221, 184, 81, 86,
-    172, 179, 47, 255, 106, 24, 202, 73, 229, 222, 53, 201, 136, 141, 152, 198,
-    33, 111, 157, 221, 247, 231, 208, 75, 208, 25, 115, 228, 185, 221, 231, 42,
-    155, 59, 107, 242, 224, 218, 4, 127, 28, 23, 244, 92, 31, 48, 183, 196, 86,
-    41, 28, 182, 73, 215, 128, 169, 179, 123, 15, 132, 89, 147, 113, 127, 50,
-    125, 126, 95, 70, 255, 175, 118, 229, 34, 126, 31, 1, 12, 94, 244, 94, 198,
-    202, 83, 237, 15, 11, 218, 9, 243, 161, 15, 2, 35, 7, 172, 36, 84, 194,
-    232, 18, 213, 224, 163, 10, 201, 250, 4, 128, 0, 55, 1, 60, 0, 80, 193, 30,
-    166, 111, 230, 216, 21, 13, 128, 87, 215, 130, 35, 248, 54, 186, 15, 228,
-    172, 207, 237, 244, 250, 236, 106, 49, 3, 75, 61, 58, 77, 255, 184, 50,
-    232, 58, 254, 122, 46, 189, 175, 163, 164, 224, 229, 44, 174, 155, 244, 69,
-    89, 13, 91, 131, 128, 170, 201, 91, 210, 147, 105, 126, 154, 183, 92, 180,
-    202, 190, 253, 70, 222, 211, 232, 209, 182, 9, 82, 24, 111, 123, 26, 77,
-    20, 137, 16, 128, 175,
-  ]);
-  let d = crypto_bigint::U4096::from_be_bytes([
-    34, 139, 63, 15, 114, 225, 67, 3, 255, 93, 121, 105, 203, 178, 141, 252,
-    133, 8, 131, 19, 78, 174, 133, 120, 108, 83, 88, 43, 98, 146, 216, 227,
-    190, 29, 208, 231, 166, 189, 156, 78, 169, 53, 206, 50, 226, 59, 77, 205,
-    140, 6, 63, 28, 142, 221, 168, 108, 67, 38, 119, 9, 199, 103, 101, 249,
-    193, 152, 80, 125, 41, 167, 165, 76, 51, 42, 31, 31, 249, 161, 124, 140,
-    157, 46, 251, 25, 4, 100, 27, 203, 72, 64, 15, 234, 246, 191, 46, 27, 29,
-    99, 80, 150, 7, 228, 57, 178, 24, 120, 34, 227, 41, 180, 27, 242, 149, 189,
-    204, 60, 126, 76, 40, 132, 90, 141, 74, 193, 193, 179, 135, 63, 30, 201,
-    16, 80, 60, 141, 166, 110, 137, 240, 96, 137, 41, 169, 99, 186, 138, 87,
-    232, 249, 171, 178, 67, 131, 255, 209, 247, 41, 3, 136, 66, 129, 196, 31,
-    253, 1, 19, 140, 30, 145, 173, 149, 253, 66, 106, 11, 166, 187, 250, 17,
-    14, 134, 164, 48, 162, 169, 39, 246, 45, 160, 185, 182, 168, 55, 247, 11,
-    4, 86, 23, 70, 60, 134, 134, 242, 93, 150, 165, 124, 64, 176, 199, 39, 243,
-    49, 242, 16, 46, 210, 43, 110, 70, 59, 69, 102, 109, 98, 43, 212, 204, 131,
-    16, 70, 37, 162, 3, 208, 99, 216, 57, 36, 117, 219, 21, 164, 46, 51, 43,
-    33, 66, 219, 178, 7, 173, 128, 154, 20, 219, 57, 120, 100, 182, 149, 31,
-    143, 135, 243, 39, 138, 41, 211, 47, 72, 47, 79, 255, 39, 114, 242, 189,
-    98, 245, 124, 119, 199, 135, 67, 118, 240, 192, 5, 176, 65, 146, 183, 93,
-    243, 46, 30, 251, 145, 53, 85, 85, 205, 141, 112, 99, 242, 54, 23, 57, 237,
-    190, 192, 207, 236, 244, 106, 236, 65, 232, 251, 131, 107, 17, 77, 197, 76,
-    165, 210, 129, 29, 205, 136, 251, 119, 157, 42, 175, 137, 23, 141, 132,
-    123, 195, 134, 199, 209, 31, 170, 243, 151, 250, 243, 191, 127, 108, 161,
-    219, 110, 80, 126, 65, 199, 15, 84, 205, 148, 186, 130, 6, 127, 9, 208, 28,
-    31, 16, 155, 198, 91, 25, 130, 153, 139, 25, 52, 159, 133, 227, 155, 223,
-    49, 125, 177, 126, 200, 221, 51, 225, 72, 240, 61, 177, 137, 165, 80, 74,
-    65, 129, 61, 152, 169, 51, 38, 48, 44, 213, 250, 48, 114, 47, 189, 81, 182,
-    220, 236, 239, 95, 121, 83, 90, 56, 133, 164, 240, 23, 49, 119, 160, 139,
-    200, 6, 228, 29, 204, 246, 172, 89, 51, 141, 151, 201, 114, 249, 194, 198,
-    157, 223, 192, 160, 50, 97, 198, 66, 90, 153, 154, 69, 3, 250, 127, 207,
-    162, 160, 7, 164, 53, 42, 109, 175, 228, 196, 207, 159, 113, 109, 217, 119,
-    76, 64, 64, 186, 220, 38, 159, 115, 191, 98, 80, 235, 68, 241, 72, 163,
-    212, 59, 205, 219, 139, 218, 139, 205, 251, 121,
-  ]);
+  let mut store = KeyStore::new(fmc::Fmc::new(dp.FMC));
+
+  // The DRBG is instantiated from the noise sources, with the seed file
+  // left by the last boot as the nonce and the device ID as the
+  // personalization string, and reseeds from the sources as it goes. If
+  // either source fails its health tests, nothing that needs randomness
+  // is done until reset.
+  let mut sources = noise::Noise::new(dp.ADC0, dp.RTC, &dp.PMU);
+  let mut seed = sources.entropy();
+  let nonce = store.seed();
+  let drbg = HmacDrbg::new(
+    &seed,
+    nonce.as_ref().map_or(&[][..], |nonce| &nonce[..]),
+    &device_salt(),
+  )
+  .reseed_interval(RESEED_INTERVAL);
+  seed.zeroize();
+  let mut rng = drbg::Reseeding::new(drbg, sources);
+
+  // Refreshed before anything else is drawn, so that no two boots use the
+  // same seed file. Losing it only costs the next boot its nonce.
+  let mut next = [0u8; keystore::SEED_LEN];
+  rng.fill_bytes(&mut next);
+  store.save_seed(&next).ok();
+  next.zeroize();
+
+  // The account keys live encrypted in the key store, all under the device
+  // PIN. Between Unlock and Lock the PIN key is kept in unlocked and the
+  // last key used in key, as loading one takes a while. Both are dropped
+  // once SESSION_TIMEOUT_SECONDS pass without using a key. A timeout rather
+  // than a lock after every reply, so that a batch of signatures does not
+  // need the PIN, and its PBKDF2, for each one. Every request that needs a
+  // key fails otherwise.
+  let mut unlocked: Option<pin::PinKey> = None;
+  let mut key: Option<(usize, rsa::PrivateKey4096)> = None;
+  let mut key_used_at = 0;
 
   let lcd_pins = lcd_pins!(gpioa, gpiob);
   let mut lcd = lcd::configure(dp.SPI0, lcd_pins, &mut afio, &mut rcu);
+
+  let button = button::Button {
+    pin: gpioa.pa8.into_pull_up_input(),
+  };
   let (width, height) = (lcd.size().width as i32, lcd.size().height as i32);
 
   // Clear screen
@@ -170,6 +171,11 @@ fn main() -> ! {
     .text_color(Rgb565::BLACK)
     .background_color(Rgb565::GREEN)
     .build();
+  if rng.entropy.failed() {
+    Text::new("RNG fault", Point::new(40, 35), style)
+      .draw(&mut lcd)
+      .unwrap();
+  }
 
   interrupt::free(|_| unsafe {
     STDOUT.replace(uart::UART { tx, rx });
@@ -177,31 +183,132 @@ fn main() -> ! {
 
   unsafe {
     loop {
+      if unlocked.is_some()
+        && mcycle::read64() - key_used_at
+          > SESSION_TIMEOUT_SECONDS * CYCLES_PER_SECOND
+      {
+        // Dropping the PIN key and the key clears them.
+        unlocked = None;
+        key = None;
+        Text::new("Locked", Point::new(40, 35), style)
+          .draw(&mut lcd)
+          .unwrap();
+      }
+
       if let Some(uart) = STDOUT.as_mut() {
         if let Some(byte) = uart.read_byte() {
           match Message::try_from(byte) {
-            Ok(Message::Sign) => {
+            Ok(
+              msg @ (Message::Sign
+              | Message::SignWith
+              | Message::SignMessage
+              | Message::SignDeepHash),
+            ) => {
               // Text::new("Recv", Point::new(40, 35), style)
               // .draw(&mut lcd)
               // .unwrap();
-              let mut digest = [0u8; 256 / 8];
-              uart.read(&mut digest);
+              let mut slot = [0u8; 1];
+              uart.read(&mut slot);
+              let slot = slot[0] as usize;
+              let scheme = match msg {
+                Message::SignWith => read_scheme(uart),
+                _ => Some(Scheme::PssSha256),
+              };
+
+              // SignMessage sends the length of its message here and the
+              // message itself once the key is ready, SignDeepHash only its
+              // stream. The whole request is read before an unknown scheme
+              // is answered, so that none of it is taken for an opcode.
+              let mut digest = [0u8; msg::DIGEST_FIELD_LEN];
+              let mut len = [0u8; 4];
+              match msg {
+                Message::SignWith => uart.read(&mut digest),
+                Message::SignMessage => uart.read(&mut len),
+                Message::SignDeepHash => {}
+                _ => uart.read(&mut digest[..Scheme::PssSha256.digest_len()]),
+              }
+              let scheme = match scheme {
+                Some(scheme) => scheme,
+                None => {
+                  uart.write(b'E');
+                  continue;
+                }
+              };
+              let digest = &mut digest[..scheme.digest_len()];
 
-              let mut rng = Hc128Rng::from_seed([0; 32]);
+              if rng.entropy.failed() {
+                Text::new("RNG fault", Point::new(40, 35), style)
+                  .draw(&mut lcd)
+                  .unwrap();
+                uart.write(b'N');
+                continue;
+              }
+              let key = match account_key(&store, &unlocked, &mut key, slot) {
+                Some(key) => key,
+                None => {
+                  Text::new("No key", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(b'E');
+                  continue;
+                }
+              };
+              key_used_at = mcycle::read64();
+              if let Ok(account) = store.account(slot) {
+                Text::new(
+                  &format!("{:<16}", account.label()),
+                  Point::new(40, 55),
+                  style,
+                )
+                .draw(&mut lcd)
+                .unwrap();
+              }
+              match msg {
+                Message::SignMessage => {
+                  Text::new("Hashing", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(0);
+                  let len = u32::from_be_bytes(len) as usize;
+                  digest.copy_from_slice(&hash_message(uart, len));
+                }
+                Message::SignDeepHash => {
+                  Text::new("Hashing", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(0);
+                  match deephash::read_stream(uart) {
+                    Ok(hash) => digest
+                      .copy_from_slice(&Sha256::new().update(&hash).finalize()),
+                    Err(_) => {
+                      Text::new("Invalid", Point::new(40, 35), style)
+                        .draw(&mut lcd)
+                        .unwrap();
+                      uart.write(b'E');
+                      continue;
+                    }
+                  }
+                }
+                _ => {}
+              }
 
-              let mut salt = [0u8; 32];
-              rng.fill(&mut salt[..]);
               Text::new("Signing", Point::new(40, 35), style)
                 .draw(&mut lcd)
                 .unwrap();
-              // 256 bytes
-              match rsa::sign_pss_with_salt(
-                &digest,
-                &salt,
-                &d.to_uint_array(),
-                &n,
-              ) {
-                Ok(signature) => {
+              // Fresh entropy for the salt and the blinding of every
+              // signature. One reseed, as prediction resistance would take
+              // one for every request of the signature.
+              rng.reseed();
+              let mut signature = [0u8; 512];
+              let result = sign(&mut rng, scheme, digest, key, &mut signature);
+              match result {
+                Ok(()) if rng.entropy.failed() => {
+                  Text::new("RNG fault", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(b'N');
+                }
+                Ok(()) => {
                   Text::new("Sending", Point::new(40, 35), style)
                     .draw(&mut lcd)
                     .unwrap();
@@ -210,6 +317,12 @@ fn main() -> ! {
                     uart.write(b);
                   }
                 }
+                Err(rsa::Error::Fault) => {
+                  Text::new("Fault", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(b'F');
+                }
                 Err(e) => {
                   Text::new("Error", Point::new(40, 35), style)
                     .draw(&mut lcd)
@@ -218,23 +331,618 @@ fn main() -> ! {
                 }
               }
             }
-            Ok(Message::Verify) => {
-              // let mut digest = [0u8; 256 / 8];
-              // uart.read(&mut digest);
+            Ok(msg @ (Message::Verify | Message::VerifyWith)) => {
+              let scheme = match msg {
+                Message::VerifyWith => read_scheme(uart),
+                _ => Some(Scheme::PssSha256),
+              };
+
+              let mut digest = [0u8; msg::DIGEST_FIELD_LEN];
+              match msg {
+                Message::VerifyWith => uart.read(&mut digest),
+                _ => uart.read(&mut digest[..Scheme::PssSha256.digest_len()]),
+              }
+
+              let mut signature = [0u8; 512];
+              uart.read(&mut signature);
 
-              // let mut signature = [0u8; 256];
-              // uart.read(&mut signature);
+              // 0x00: slot of an account follows, 0x01: 512 byte modulus
+              // follows.
+              let mut source = [0u8; 1];
+              uart.read(&mut source);
+              let mut slot = [0u8; 1];
+              let mut modulus = [0u8; 512];
+              match source[0] {
+                0x00 => uart.read(&mut slot),
+                0x01 => uart.read(&mut modulus),
+                _ => {}
+              }
 
-              // let rng = Hc128Rng::from_seed([0; 32]);
-              // let padding = PaddingScheme::new_pss_with_salt::<Sha256, _>(rng, 32);
+              // Only answered once the whole request is read, like
+              // SignWith.
+              let scheme = match scheme {
+                Some(scheme) => scheme,
+                None => {
+                  uart.write(b'E');
+                  continue;
+                }
+              };
+              let digest = &digest[..scheme.digest_len()];
 
-              // let verification =
-              //   signing_key.verify(padding, &digest, &signature).is_ok();
+              // An account key is only there while unlocked, so that a
+              // locked device is not taken for a bad signature.
+              let verification = match source[0] {
+                0x00 => {
+                  let slot = slot[0] as usize;
+                  account_key(&store, &unlocked, &mut key, slot)
+                    .map(|key| verify(scheme, digest, &signature, key.monty()))
+                }
+                0x01 => {
+                  let host_n = crypto_bigint::U4096::from_be_bytes(modulus);
+                  Some(match rsa::MontgomeryParams::new(&host_n) {
+                    Some(monty) => verify(scheme, digest, &signature, &monty),
+                    None => false,
+                  })
+                }
+                _ => None,
+              };
 
-              // uart.write(if verification { 1 } else { 0 });
+              uart.write(match verification {
+                Some(true) => 1,
+                Some(false) => 0,
+                None => b'E',
+              });
+            }
+            Ok(Message::Decrypt) => {
+              let mut slot = [0u8; 1];
+              uart.read(&mut slot);
+              let slot = slot[0] as usize;
+              let mut ciphertext = [0u8; 512];
+              uart.read(&mut ciphertext);
+
+              // The RNG blinds the private key operation.
+              if rng.entropy.failed() {
+                Text::new("RNG fault", Point::new(40, 35), style)
+                  .draw(&mut lcd)
+                  .unwrap();
+                uart.write(b'N');
+                continue;
+              }
+              let key = match account_key(&store, &unlocked, &mut key, slot) {
+                Some(key) => key,
+                None => {
+                  Text::new("No key", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(b'E');
+                  continue;
+                }
+              };
+              key_used_at = mcycle::read64();
+              if let Ok(account) = store.account(slot) {
+                Text::new(
+                  &format!("{:<16}", account.label()),
+                  Point::new(40, 55),
+                  style,
+                )
+                .draw(&mut lcd)
+                .unwrap();
+              }
+
+              Text::new("Decrypt?", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+              if !button.confirm() {
+                Text::new("Rejected", Point::new(40, 35), style)
+                  .draw(&mut lcd)
+                  .unwrap();
+                uart.write(b'R');
+                continue;
+              }
+
+              let mut plaintext = [0u8; 512];
+              match rsa::decrypt_oaep(
+                &mut rng,
+                b"",
+                &ciphertext,
+                key,
+                &mut plaintext,
+              ) {
+                Ok(_) if rng.entropy.failed() => {
+                  Text::new("RNG fault", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(b'N');
+                }
+                Ok(len) => {
+                  Text::new("Sending", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+
+                  uart.write((len >> 8) as u8);
+                  uart.write(len as u8);
+                  for b in &plaintext[..len] {
+                    uart.write(*b);
+                  }
+                }
+                Err(_) => {
+                  Text::new("Error", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(b'E');
+                }
+              }
+            }
+            Ok(Message::GenerateKey) => {
+              let mut label = [0u8; 255];
+              let (slot, label) = read_account(uart, &mut label);
+              let pin_key = match read_pin(uart) {
+                Some(pin_key)
+                  if slot < store.slots() && keystore::valid_label(label) =>
+                {
+                  pin_key
+                }
+                _ => {
+                  uart.write(b'E');
+                  continue;
+                }
+              };
+              let label = core::str::from_utf8(label).unwrap();
+              // A new key starts from fresh entropy.
+              rng.reseed();
+              if rng.entropy.failed() {
+                Text::new("RNG fault", Point::new(40, 35), style)
+                  .draw(&mut lcd)
+                  .unwrap();
+                uart.write(b'N');
+                continue;
+              }
+              if let Err(e) = check_new_pin(&mut store, &pin_key) {
+                unlocked = None;
+                key = None;
+                let status = pin_error(uart, &store, e);
+                Text::new(&status, Point::new(40, 35), style)
+                  .draw(&mut lcd)
+                  .unwrap();
+                continue;
+              }
+
+              Text::new(&format!("{:<16}", label), Point::new(40, 55), style)
+                .draw(&mut lcd)
+                .unwrap();
+              Text::new("Replace?", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+              if !button.confirm() {
+                Text::new("Rejected", Point::new(40, 35), style)
+                  .draw(&mut lcd)
+                  .unwrap();
+                uart.write(b'R');
+                continue;
+              }
+
+              // Makes room for the new key.
+              key = None;
+              Text::new("Generating", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+              let new_key = match rsa::PrivateKey4096::generate(&mut rng) {
+                // The primes may have been drawn after a health test
+                // failed, so the key is dropped unsaved.
+                Ok(_) if rng.entropy.failed() => {
+                  Text::new("RNG fault", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(b'N');
+                  continue;
+                }
+                Ok(new_key)
+                  if store
+                    .save_key(
+                      &mut rng,
+                      slot,
+                      &new_key,
+                      label.as_bytes(),
+                      &pin_key,
+                    )
+                    .is_ok() =>
+                {
+                  new_key
+                }
+                _ => {
+                  Text::new("Fault", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(b'F');
+                  continue;
+                }
+              };
+
+              Text::new("Sending", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+              for b in new_key.n.to_be_bytes() {
+                uart.write(b);
+              }
+              key = Some((slot, new_key));
+              unlocked = Some(pin_key);
+              key_used_at = mcycle::read64();
+            }
+            Ok(Message::RestoreMnemonic) => {
+              let mut label = [0u8; 255];
+              let (slot, label) = read_account(uart, &mut label);
+              let mut len = [0u8; 1];
+              uart.read(&mut len);
+              let mut phrase = [0u8; 255];
+              let phrase = &mut phrase[..len[0] as usize];
+              uart.read(phrase);
+              let pin_key = read_pin(uart);
+
+              let mut entropy = [0u8; mnemonic::MAX_ENTROPY_LEN];
+              let decoded = mnemonic::decode(phrase, &mut entropy);
+              entropy.zeroize();
+              let pin_key = match (decoded, pin_key) {
+                (Ok(_), Some(pin_key))
+                  if slot < store.slots() && keystore::valid_label(label) =>
+                {
+                  pin_key
+                }
+                _ => {
+                  phrase.zeroize();
+                  Text::new("Error", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(b'E');
+                  continue;
+                }
+              };
+              let label = core::str::from_utf8(label).unwrap();
+              if let Err(e) = check_new_pin(&mut store, &pin_key) {
+                phrase.zeroize();
+                unlocked = None;
+                key = None;
+                let status = pin_error(uart, &store, e);
+                Text::new(&status, Point::new(40, 35), style)
+                  .draw(&mut lcd)
+                  .unwrap();
+                continue;
+              }
+
+              Text::new(&format!("{:<16}", label), Point::new(40, 55), style)
+                .draw(&mut lcd)
+                .unwrap();
+              Text::new("Restore?", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+              if !button.confirm() {
+                phrase.zeroize();
+                Text::new("Rejected", Point::new(40, 35), style)
+                  .draw(&mut lcd)
+                  .unwrap();
+                uart.write(b'R');
+                continue;
+              }
+
+              key = None;
+              Text::new("Deriving", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+              let mut seed = mnemonic::to_seed(phrase, b"");
+              phrase.zeroize();
+              let new_key = derive::key_from_seed(&seed);
+              seed.zeroize();
+
+              let new_key = match new_key {
+                Ok(new_key)
+                  if store
+                    .save_key(
+                      &mut rng,
+                      slot,
+                      &new_key,
+                      label.as_bytes(),
+                      &pin_key,
+                    )
+                    .is_ok() =>
+                {
+                  new_key
+                }
+                _ => {
+                  Text::new("Fault", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(b'F');
+                  continue;
+                }
+              };
+
+              Text::new("Sending", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+              for b in new_key.n.to_be_bytes() {
+                uart.write(b);
+              }
+              key = Some((slot, new_key));
+              unlocked = Some(pin_key);
+              key_used_at = mcycle::read64();
+            }
+            Ok(Message::ImportKey) => {
+              let mut label = [0u8; 255];
+              let (slot, label) = read_account(uart, &mut label);
+              let mut n = [0u8; 512];
+              let mut e = [0u8; 4];
+              let mut d = [0u8; 512];
+              uart.read(&mut n);
+              uart.read(&mut e);
+              uart.read(&mut d);
+              let mut crt = [[0u8; 256]; 5];
+              for param in crt.iter_mut() {
+                uart.read(param);
+              }
+              let pin_key = match read_pin(uart) {
+                Some(pin_key)
+                  if slot < store.slots() && keystore::valid_label(label) =>
+                {
+                  pin_key
+                }
+                _ => {
+                  d.zeroize();
+                  crt.zeroize();
+                  uart.write(b'E');
+                  continue;
+                }
+              };
+              let label = core::str::from_utf8(label).unwrap();
+              if let Err(e) = check_new_pin(&mut store, &pin_key) {
+                d.zeroize();
+                crt.zeroize();
+                unlocked = None;
+                key = None;
+                let status = pin_error(uart, &store, e);
+                Text::new(&status, Point::new(40, 35), style)
+                  .draw(&mut lcd)
+                  .unwrap();
+                continue;
+              }
+
+              key = None;
+              Text::new("Checking", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+
+              let new_key = rsa::PrivateKey4096::from_components(
+                &mut rng,
+                crypto_bigint::U4096::from_be_bytes(n),
+                u32::from_be_bytes(e),
+                crypto_bigint::U4096::from_be_bytes(d),
+                crypto_bigint::U2048::from_be_bytes(crt[0]),
+                crypto_bigint::U2048::from_be_bytes(crt[1]),
+                crypto_bigint::U2048::from_be_bytes(crt[2]),
+                crypto_bigint::U2048::from_be_bytes(crt[3]),
+                crypto_bigint::U2048::from_be_bytes(crt[4]),
+              );
+              d.zeroize();
+              crt.zeroize();
+              let new_key = match new_key {
+                Ok(new_key) => new_key,
+                Err(e) => {
+                  Text::new("Bad key", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(e as u8);
+                  continue;
+                }
+              };
+
+              Text::new(&format!("{:<16}", label), Point::new(40, 55), style)
+                .draw(&mut lcd)
+                .unwrap();
+              Text::new("Import?", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+              if !button.confirm() {
+                Text::new("Rejected", Point::new(40, 35), style)
+                  .draw(&mut lcd)
+                  .unwrap();
+                uart.write(b'R');
+                continue;
+              }
+
+              if store
+                .save_key(&mut rng, slot, &new_key, label.as_bytes(), &pin_key)
+                .is_err()
+              {
+                Text::new("Fault", Point::new(40, 35), style)
+                  .draw(&mut lcd)
+                  .unwrap();
+                uart.write(b'F');
+                continue;
+              }
+
+              key = Some((slot, new_key));
+              unlocked = Some(pin_key);
+              key_used_at = mcycle::read64();
+              Text::new("Imported", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+              uart.write(0x00);
+            }
+            Ok(Message::Unlock) => {
+              let pin_key = match read_pin(uart) {
+                Some(pin_key) => pin_key,
+                None => {
+                  uart.write(b'E');
+                  continue;
+                }
+              };
+
+              Text::new("Unlocking", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+              match store.unlock(&pin_key) {
+                Ok(()) => {
+                  unlocked = Some(pin_key);
+                  key_used_at = mcycle::read64();
+                  Text::new("Unlocked", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(0x00);
+                }
+                Err(e) => {
+                  unlocked = None;
+                  key = None;
+                  let status = pin_error(uart, &store, e);
+                  Text::new(&status, Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                }
+              }
+            }
+            Ok(Message::ChangePin) => {
+              let old = read_pin(uart);
+              let new = read_pin(uart);
+              let (old, new) = match (old, new) {
+                (Some(old), Some(new)) => (old, new),
+                _ => {
+                  uart.write(b'E');
+                  continue;
+                }
+              };
+
+              Text::new("Changing PIN", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+              match store.change_pin(&mut rng, &old, &new) {
+                Ok(()) => {
+                  unlocked = Some(new);
+                  key_used_at = mcycle::read64();
+                  Text::new("PIN changed", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(0x00);
+                }
+                Err(e) => {
+                  unlocked = None;
+                  key = None;
+                  let status = pin_error(uart, &store, e);
+                  Text::new(&status, Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                }
+              }
+            }
+            Ok(Message::Lock) => {
+              // Dropping the keys clears them.
+              unlocked = None;
+              key = None;
+              Text::new("Locked", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+              uart.write(0x00);
+            }
+            Ok(Message::ListAccounts) => {
+              uart.write(store.slots() as u8);
+              for slot in 0..store.slots() {
+                let account = match store.account(slot) {
+                  Ok(account) => account,
+                  Err(_) => {
+                    uart.write(0x00);
+                    continue;
+                  }
+                };
+
+                uart.write(0x01);
+                for b in account.address {
+                  uart.write(b);
+                }
+                let mut label = [0u8; keystore::MAX_LABEL_LEN];
+                label[..account.label().len()]
+                  .copy_from_slice(account.label().as_bytes());
+                for b in label {
+                  uart.write(b);
+                }
+              }
+            }
+            Ok(Message::GetAddress) => {
+              let mut slot = [0u8; 1];
+              uart.read(&mut slot);
+              let slot = slot[0] as usize;
+
+              // The address hash is stored in the clear, so this works
+              // while locked. No private key is used, so it does not
+              // count as use for the session timeout.
+              let account = match store.account(slot) {
+                Ok(account) => account,
+                Err(_) => {
+                  Text::new("No key", Point::new(40, 35), style)
+                    .draw(&mut lcd)
+                    .unwrap();
+                  uart.write(b'E');
+                  continue;
+                }
+              };
+
+              let address = owner::address(&account.address);
+              // Three lines of up to 17 characters next to the logo.
+              Rectangle::new(
+                Point::new(40, 0),
+                Size::new(width as u32 - 40, height as u32),
+              )
+              .into_styled(PrimitiveStyle::with_fill(Rgb565::BLACK))
+              .draw(&mut lcd)
+              .unwrap();
+              for (i, line) in address.chunks(17).enumerate() {
+                // base64url is ASCII.
+                let line = core::str::from_utf8(line).unwrap();
+                Text::new(line, Point::new(40, 21 + 14 * i as i32), style)
+                  .draw(&mut lcd)
+                  .unwrap();
+              }
+
+              for b in address {
+                uart.write(b);
+              }
+            }
+            Ok(Message::GetOwner) => {
+              let mut request = [0u8; 2];
+              uart.read(&mut request);
+              let slot = request[0] as usize;
+              // The modulus is stored in the clear, so this works while
+              // locked, and like GetAddress it leaves key_used_at alone.
+              let modulus: Result<crypto_bigint::U4096, _> =
+                store.modulus(slot);
+              let (format, n) =
+                match (OwnerFormat::try_from(request[1]), modulus) {
+                  (Ok(format), Ok(n)) => (format, n),
+                  _ => {
+                    Text::new("No key", Point::new(40, 35), style)
+                      .draw(&mut lcd)
+                      .unwrap();
+                    uart.write(b'E');
+                    continue;
+                  }
+                };
+
+              Text::new("Sending", Point::new(40, 35), style)
+                .draw(&mut lcd)
+                .unwrap();
+              let n = n.to_be_bytes();
+              let mut encoded = [0u8; owner::MAX_LEN];
+              let len = match format {
+                OwnerFormat::Raw => {
+                  for b in n {
+                    uart.write(b);
+                  }
+                  continue;
+                }
+                OwnerFormat::Jwk => owner::jwk(&n, &mut encoded),
+                OwnerFormat::Spki => owner::spki(&n, &mut encoded),
+              };
+              uart.write((len >> 8) as u8);
+              uart.write(len as u8);
+              for b in &encoded[..len] {
+                uart.write(*b);
+              }
             }
-            Ok(Message::GetAddress) => {}
-            Ok(Message::GetOwner) => {}
             Err(_) => {}
           }
         }
@@ -243,6 +951,166 @@ fn main() -> ! {
   }
 }
 
+/// The key of the account in slot, loaded into key unless it is already
+/// there. None if the device is locked or the slot holds no key.
+fn account_key<'a>(
+  store: &KeyStore<fmc::Fmc>,
+  unlocked: &Option<pin::PinKey>,
+  key: &'a mut Option<(usize, rsa::PrivateKey4096)>,
+  slot: usize,
+) -> Option<&'a rsa::PrivateKey4096> {
+  let pin_key = unlocked.as_ref()?;
+  if !matches!(*key, Some((loaded, _)) if loaded == slot) {
+    // Only one key fits in RAM next to the rest.
+    *key = None;
+    *key = Some((slot, store.load_key(slot, pin_key).ok()?));
+  }
+  key.as_ref().map(|(_, key)| key)
+}
+
+/// Checks the PIN given with a new key against the keys already stored,
+/// as they are all under the same PIN. Any PIN goes for the first key.
+fn check_new_pin(
+  store: &mut KeyStore<fmc::Fmc>,
+  pin_key: &pin::PinKey,
+) -> Result<(), keystore::Error> {
+  match store.unlock(pin_key) {
+    Err(keystore::Error::Empty) => Ok(()),
+    checked => checked,
+  }
+}
+
+/// Answers a failed PIN check, returns the status to show. A wrong PIN is
+/// answered with 'P' and the attempts left before the key store is wiped.
+fn pin_error(
+  uart: &mut uart::UART,
+  store: &KeyStore<fmc::Fmc>,
+  e: keystore::Error,
+) -> String {
+  let (reply, status) = match e {
+    keystore::Error::Pin => {
+      let left = store.attempts_left();
+      unsafe { uart.write(b'P') };
+      (left as u8, format!("{} tries left", left))
+    }
+    keystore::Error::Wiped => (b'W', String::from("Wiped")),
+    keystore::Error::Empty => (b'E', String::from("No key")),
+    _ => (b'F', String::from("Fault")),
+  };
+  unsafe { uart.write(reply) };
+  status
+}
+
+/// Reads the slot and label of a new account, a slot byte followed by a
+/// length byte and the label.
+fn read_account<'a>(
+  uart: &mut uart::UART,
+  label: &'a mut [u8; 255],
+) -> (usize, &'a [u8]) {
+  let mut slot = [0u8; 1];
+  let mut len = [0u8; 1];
+  let label = unsafe {
+    uart.read(&mut slot);
+    uart.read(&mut len);
+    let label = &mut label[..len[0] as usize];
+    uart.read(label);
+    label
+  };
+  (slot[0] as usize, &*label)
+}
+
+/// Reads a PIN, a length byte followed by the PIN, and derives the key the
+/// keys are encrypted with. None if the PIN is too short or too long.
+/// The PIN is cleared here and the PinKey when it is dropped, at the end
+/// of the request that read it unless it unlocks the device.
+fn read_pin(uart: &mut uart::UART) -> Option<pin::PinKey> {
+  let mut len = [0u8; 1];
+  let mut pin = [0u8; 255];
+  let pin = &mut pin[..];
+  unsafe {
+    uart.read(&mut len);
+    uart.read(&mut pin[..len[0] as usize]);
+  }
+
+  let pin_key = pin::PinKey::derive(&pin[..len[0] as usize], &device_salt());
+  pin.zeroize();
+  pin_key.ok()
+}
+
+/// The 96-bit unique ID of the chip, the per-device salt of the PIN.
+fn device_salt() -> [u8; 12] {
+  const UID: usize = 0x1FFF_F7E8;
+  let mut uid = [0u8; 12];
+  for (i, b) in uid.iter_mut().enumerate() {
+    *b = unsafe { core::ptr::read_volatile((UID + i) as *const u8) };
+  }
+  uid
+}
+
+/// Reads the len bytes of a SignMessage message, answering every chunk,
+/// and returns their SHA-256.
+fn hash_message(uart: &mut uart::UART, len: usize) -> [u8; 32] {
+  let mut hash = Sha256::new();
+  let mut buf = [0u8; msg::CHUNK_LEN];
+  let mut left = len;
+  while left > 0 {
+    let chunk = &mut buf[..left.min(msg::CHUNK_LEN)];
+    unsafe { uart.read(chunk) };
+    hash = hash.update(chunk);
+    unsafe { uart.write(0) };
+    left -= chunk.len();
+  }
+  hash.finalize()
+}
+
+/// Reads the scheme byte that follows SignWith and VerifyWith.
+fn read_scheme(uart: &mut uart::UART) -> Option<Scheme> {
+  let mut scheme = [0u8; 1];
+  unsafe { uart.read(&mut scheme) };
+  Scheme::try_from(scheme[0]).ok()
+}
+
+/// Signs digest with key under the given scheme.
+fn sign<R: RngCore + CryptoRng, const L: usize, const H: usize>(
+  rng: &mut R,
+  scheme: Scheme,
+  digest: &[u8],
+  key: &rsa::PrivateKey<L, H>,
+  signature: &mut [u8],
+) -> Result<(), rsa::Error> {
+  match scheme {
+    Scheme::PssSha256 => {
+      let mut salt = [0u8; 32];
+      rng.fill(&mut salt[..]);
+      rsa::sign_pss_with_salt(rng, digest, &salt, key, signature)
+    }
+    Scheme::Pkcs1v15Sha256 => {
+      rsa::sign_pkcs1v15(rng, rsa::Hash::Sha256, digest, key, signature)
+    }
+    Scheme::Pkcs1v15Sha512 => {
+      rsa::sign_pkcs1v15(rng, rsa::Hash::Sha512, digest, key, signature)
+    }
+  }
+}
+
+/// Checks signature over digest under the given scheme and public key.
+fn verify<const L: usize>(
+  scheme: Scheme,
+  digest: &[u8],
+  signature: &[u8],
+  monty: &rsa::MontgomeryParams<L>,
+) -> bool {
+  match scheme {
+    Scheme::PssSha256 => rsa::verify_pss(digest, signature, 32, monty).is_ok(),
+    Scheme::Pkcs1v15Sha256 => {
+      rsa::verify_pkcs1v15(rsa::Hash::Sha256, digest, signature, monty).is_ok()
+    }
+    Scheme::Pkcs1v15Sha512 => {
+      rsa::verify_pkcs1v15(rsa::Hash::Sha512, digest, signature, monty).is_ok()
+    }
+  }
+}
+
 #[alloc_error_handler]
 fn alloc_error(_layout: Layout) -> ! {
   interrupt::free(|_| unsafe {
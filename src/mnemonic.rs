@@ -0,0 +1,187 @@
+//! BIP-39 mnemonic sentences with the English wordlist.
+//! See https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki
+
+use crate::hmac::pbkdf2_hmac_sha512;
+use sha2_const::Sha256;
+
+/// The BIP-39 English wordlist, one word per line.
+const WORDLIST: &str = include_str!("english.txt");
+
+/// Longest sentence: 24 words of at most 8 letters, separated by spaces.
+pub const MAX_PHRASE_LEN: usize = 24 * 8 + 23;
+
+/// Longest entropy, for 24 words.
+pub const MAX_ENTROPY_LEN: usize = 256 / 8;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  /// Entropy is not 16, 20, 24, 28 or 32 bytes, or the sentence is not 12,
+  /// 15, 18, 21 or 24 words.
+  Length,
+  /// A word that is not in the wordlist.
+  UnknownWord,
+  /// The checksum bits do not match the entropy.
+  Checksum,
+}
+
+fn word(index: usize) -> &'static str {
+  WORDLIST.lines().nth(index).unwrap()
+}
+
+fn index_of(word: &[u8]) -> Option<usize> {
+  WORDLIST.lines().position(|w| w.as_bytes() == word)
+}
+
+/// Bit i of data, counting from the most significant bit of data[0].
+fn bit(data: &[u8], i: usize) -> bool {
+  data[i / 8] & (0x80 >> (i % 8)) != 0
+}
+
+/// Writes the sentence for entropy to out, words separated by single
+/// spaces, and returns its length.
+pub fn encode(
+  entropy: &[u8],
+  out: &mut [u8; MAX_PHRASE_LEN],
+) -> Result<usize, Error> {
+  let ent = entropy.len() * 8;
+  if !(128..=256).contains(&ent) || ent % 32 != 0 {
+    return Err(Error::Length);
+  }
+
+  // The checksum is the first ENT / 32 bits of SHA256(entropy), appended
+  // to the entropy.
+  let mut data = [0u8; MAX_ENTROPY_LEN + 1];
+  data[..entropy.len()].copy_from_slice(entropy);
+  data[entropy.len()] = Sha256::new().update(entropy).finalize()[0];
+
+  let mut len = 0;
+  for w in 0..(ent + ent / 32) / 11 {
+    let mut index = 0;
+    for i in 0..11 {
+      index = (index << 1) | bit(&data, w * 11 + i) as usize;
+    }
+
+    if w > 0 {
+      out[len] = b' ';
+      len += 1;
+    }
+    let word = word(index).as_bytes();
+    out[len..len + word.len()].copy_from_slice(word);
+    len += word.len();
+  }
+
+  Ok(len)
+}
+
+/// Recovers the entropy from a sentence with words separated by single
+/// spaces, checking the checksum. Returns the length of the entropy.
+pub fn decode(
+  phrase: &[u8],
+  entropy: &mut [u8; MAX_ENTROPY_LEN],
+) -> Result<usize, Error> {
+  let words = phrase.split(|b| *b == b' ').count();
+  if !(12..=24).contains(&words) || words % 3 != 0 {
+    return Err(Error::Length);
+  }
+
+  let mut data = [0u8; MAX_ENTROPY_LEN + 1];
+  for (w, word) in phrase.split(|b| *b == b' ').enumerate() {
+    let index = index_of(word).ok_or(Error::UnknownWord)?;
+    for i in 0..11 {
+      if index & (1 << (10 - i)) != 0 {
+        let j = w * 11 + i;
+        data[j / 8] |= 0x80 >> (j % 8);
+      }
+    }
+  }
+
+  // words * 11 = ENT + ENT / 32
+  let ent = words * 11 * 32 / 33;
+  let len = ent / 8;
+  let cs = ent / 32;
+
+  let hash = Sha256::new().update(&data[..len]).finalize();
+  let mask = (0xFF00u16 >> cs) as u8;
+  if data[len] & mask != hash[0] & mask {
+    return Err(Error::Checksum);
+  }
+
+  entropy[..len].copy_from_slice(&data[..len]);
+  Ok(len)
+}
+
+/// The 64 byte BIP-39 seed: PBKDF2-HMAC-SHA512 with 2048 iterations over
+/// the sentence, salted with "mnemonic" || passphrase. Both are expected
+/// in NFKD form already, which holds for any ASCII string.
+pub fn to_seed(phrase: &[u8], passphrase: &[u8]) -> [u8; 64] {
+  let mut seed = [0u8; 64];
+  pbkdf2_hmac_sha512(phrase, &[&b"mnemonic"[..], passphrase], 2048, &mut seed);
+  seed
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::hex;
+
+  // From the reference test vectors, with passphrase "TREZOR".
+  // https://github.com/trezor/python-mnemonic/blob/master/vectors.json
+  const VECTORS: [(&str, &str, &str); 3] = [
+    (
+      "00000000000000000000000000000000",
+      "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+      "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+    ),
+    (
+      "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+      "legal winner thank year wave sausage worth useful legal winner thank yellow",
+      "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607",
+    ),
+    (
+      "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+      "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote",
+      "dd48c104698c30cfe2b6142103248622fb7bb0ff692eebb00089b32d22484e1613912f0a5b694407be899ffd31ed3992c456cdf60f5d4564b8ba3f05a69890ad",
+    ),
+  ];
+
+  #[test]
+  fn wordlist() {
+    assert_eq!(WORDLIST.lines().count(), 2048);
+    assert_eq!(word(0), "abandon");
+    assert_eq!(word(2047), "zoo");
+  }
+
+  #[test]
+  fn vectors() {
+    for (entropy, phrase, seed) in VECTORS.iter() {
+      let entropy = hex(entropy);
+
+      let mut out = [0u8; MAX_PHRASE_LEN];
+      let len = encode(&entropy, &mut out).unwrap();
+      assert_eq!(&out[..len], phrase.as_bytes());
+
+      let mut decoded = [0u8; MAX_ENTROPY_LEN];
+      let len = decode(phrase.as_bytes(), &mut decoded).unwrap();
+      assert_eq!(&decoded[..len], &entropy[..]);
+
+      assert_eq!(&to_seed(phrase.as_bytes(), b"TREZOR")[..], &hex(seed)[..]);
+    }
+  }
+
+  #[test]
+  fn invalid() {
+    let mut entropy = [0u8; MAX_ENTROPY_LEN];
+    assert_eq!(
+      decode(b"abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon", &mut entropy),
+      Err(Error::Checksum)
+    );
+    assert_eq!(
+      decode(b"abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon arienai", &mut entropy),
+      Err(Error::UnknownWord)
+    );
+    assert_eq!(decode(b"abandon about", &mut entropy), Err(Error::Length));
+
+    let mut out = [0u8; MAX_PHRASE_LEN];
+    assert_eq!(encode(&[0u8; 15], &mut out), Err(Error::Length));
+  }
+}
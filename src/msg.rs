@@ -23,9 +23,162 @@ macro_rules! repr_u8 {
 repr_u8! {
   #[repr(u8)]
   pub enum Message {
+    /// RSA-PSS (SHA-256) signature of a 32 byte digest with an account. The
+    /// opcode is followed by the slot of the account, which is shown on
+    /// the screen, then the digest. Answers with the 512 byte signature,
+    /// 'E' if the device is locked or the slot holds no key, 'F' if a fault
+    /// was caught in the signature or 'N' if the noise sources have failed
+    /// their health tests.
     Sign = 0x01,
+    /// Checks a signature over a digest, against an account, given by a
+    /// 0x00 byte and its slot, or against a 0x01 byte and a 512 byte
+    /// modulus. Answers with 1 if it verifies and 0 otherwise, or 'E' if
+    /// the device is locked, the slot holds no key or the source is
+    /// unknown.
     Verify = 0x02,
+    /// Exports the public key of an account. The opcode is followed by the
+    /// slot of the account, then an OwnerFormat. Answers with the 512 byte
+    /// modulus for Raw, the length as two big-endian bytes followed by the
+    /// encoding otherwise, or a single 'E' if the slot holds no key or the
+    /// format is unknown. The modulus is stored in the clear, so this works
+    /// while the device is locked.
     GetOwner = 0x03,
+    /// Shows the Arweave address of an account on the screen and answers
+    /// with it, 43 base64url characters. The opcode is followed by the slot
+    /// of the account. The address is stored in the clear, so this works
+    /// while the device is locked. Answers with a single 'E' if the slot
+    /// holds no key.
     GetAddress = 0x04,
+    /// Sign, with the scheme given in the byte after the slot. The digest
+    /// follows in a field of DIGEST_FIELD_LEN bytes, padded with zeros, so
+    /// that the request has the same length whatever the scheme. Answers
+    /// as Sign, and with 'E' if the scheme is unknown.
+    SignWith = 0x05,
+    /// Verify, with the scheme given in the byte after the opcode and the
+    /// digest in a field of DIGEST_FIELD_LEN bytes as in SignWith. Answers
+    /// as Verify, and with 'E' if the scheme is unknown.
+    VerifyWith = 0x06,
+    /// RSA-OAEP (SHA-256, empty label) decryption of a 512 byte ciphertext
+    /// with an account, once approved with the button. The opcode is
+    /// followed by the slot of the account, then the ciphertext. Answers
+    /// with the plaintext length as two big-endian bytes followed by the
+    /// plaintext, or a single 'R' if the user rejected, 'E' if the
+    /// ciphertext is invalid or there is no key and 'N' if the noise
+    /// sources have failed their health tests.
+    Decrypt = 0x07,
+    /// Replaces the key of an account with a freshly generated RSA-4096
+    /// key, once approved with the button. The opcode is followed by the
+    /// account, see ListAccounts, as the slot, the length of the label in
+    /// one byte and the label, then the device PIN, see Unlock. Answers
+    /// with the 512 byte modulus, 'E' if the account or PIN is invalid, 'R'
+    /// if the user rejected, 'F' if the new key failed its self-test or
+    /// could not be stored or 'N' if the noise sources have failed, before
+    /// or while it was generated. A wrong PIN is answered like in Unlock.
+    GenerateKey = 0x08,
+    /// Replaces the key of an account with the one derived from a BIP-39
+    /// mnemonic, see `derive`, once approved with the button. The opcode is
+    /// followed by the account as in GenerateKey, the length of the sentence in
+    /// one byte and the sentence, lowercase words separated by single spaces,
+    /// then the device PIN. Answers with the 512 byte modulus, 'E' if the
+    /// account, sentence or PIN is invalid, 'R' if the user rejected or 'F' if
+    /// the key failed its self-test or could not be stored. A wrong PIN is
+    /// answered like in Unlock.
+    RestoreMnemonic = 0x09,
+    /// Replaces the key of an account with one given in full, once it
+    /// passes the consistency checks and is approved with the button. The
+    /// opcode is followed by the account as in GenerateKey, the big-endian
+    /// JWK members n (512 bytes), e (4 bytes), d (512 bytes), p, q, dp, dq
+    /// and qi (256 bytes each), then the device PIN. Answers with a single
+    /// byte, 0x00 if the key was imported, 'E' if the account or PIN is
+    /// invalid, 'R' if the user rejected, 'F' if it could not be stored or
+    /// one of the `rsa::KeyError` codes. A wrong PIN is answered like in
+    /// Unlock.
+    ImportKey = 0x0A,
+    /// Unlocks the accounts. The opcode is followed by the device PIN, one
+    /// length byte and 4 to 32 bytes, which all keys are encrypted under.
+    /// Until then, signing, decrypting and verifying against an account
+    /// fail. A new key leaves the device unlocked, a failed Unlock locks
+    /// it, and so do Lock and 60 seconds after the last Sign, SignWith,
+    /// SignMessage, SignDeepHash or Decrypt. Answers with 0x00, 'P' and the
+    /// attempts left if the PIN is wrong, 'W' if that was the last attempt
+    /// and the keys were wiped, 'E' if there is no key or the PIN is
+    /// invalid or 'F' if the key store failed.
+    Unlock = 0x0B,
+    /// Clears the PIN and decrypted keys from RAM. Answers with 0x00.
+    Lock = 0x0C,
+    /// Re-encrypts every key under a new PIN, leaving the device unlocked.
+    /// The opcode is followed by the old PIN, then the new one. The old PIN
+    /// counts as an attempt and is answered like Unlock. If it is
+    /// interrupted, sending it again with the same PINs finishes it.
+    ChangePin = 0x0D,
+    /// Lists the accounts, also while locked. Answers with the number of
+    /// slots, then for each slot 0x00 if it is empty, or 0x01, the SHA-256
+    /// of the modulus, whose base64url encoding is the Arweave address, and
+    /// the label padded with zeros to 16 bytes.
+    ListAccounts = 0x0E,
+    /// Sign, but of a message of any length, which the device hashes with
+    /// SHA-256 itself. The opcode is followed by the slot of the account
+    /// and the length of the message as four big-endian bytes. Answers
+    /// with 0x00 once it is ready for the message, or 'E' or 'N' as Sign
+    /// does. The message then follows in chunks of CHUNK_LEN bytes, the
+    /// last one possibly shorter, each answered with 0x00 once hashed.
+    /// Answers with the 512 byte signature after the last chunk.
+    SignMessage = 0x0F,
+    /// Sign, but of the deep hash of an Arweave v2 transaction or ANS-104
+    /// data item, which the device computes itself and signs as Arweave
+    /// does, with PSS over its SHA-256. The opcode is followed by the slot
+    /// of the account. Answers with 0x00 once it is ready, or 'E' or 'N' as
+    /// Sign does. The items then follow in the stream format of deephash,
+    /// every header and chunk answered with 0x00. Answers with the 512 byte
+    /// signature after the last item, or 'E' as soon as the stream is
+    /// invalid.
+    SignDeepHash = 0x10,
+  }
+}
+
+/// Size of the chunks of SignMessage. The UART has no flow control, so
+/// the host waits for each chunk to be answered before sending the next.
+pub const CHUNK_LEN: usize = 512;
+
+/// Size of the digest field of SignWith and VerifyWith, the longest
+/// digest of any Scheme.
+pub const DIGEST_FIELD_LEN: usize = 512 / 8;
+
+repr_u8! {
+  /// Signature scheme for SignWith and VerifyWith. Sign and Verify always
+  /// use PssSha256.
+  #[repr(u8)]
+  #[derive(Clone, Copy)]
+  pub enum Scheme {
+    /// RSASSA-PSS with SHA-256 and a 32 byte salt, as used by Arweave.
+    PssSha256 = 0x00,
+    /// RSASSA-PKCS1-v1_5 with SHA-256, e.g. RS256 or rsa-sha2-256.
+    Pkcs1v15Sha256 = 0x01,
+    /// RSASSA-PKCS1-v1_5 with SHA-512, e.g. rsa-sha2-512.
+    Pkcs1v15Sha512 = 0x02,
+  }
+}
+
+impl Scheme {
+  /// Size of the digest, at the start of the digest field.
+  pub fn digest_len(self) -> usize {
+    match self {
+      Scheme::PssSha256 | Scheme::Pkcs1v15Sha256 => 256 / 8,
+      Scheme::Pkcs1v15Sha512 => 512 / 8,
+    }
+  }
+}
+
+repr_u8! {
+  /// Encoding of the public key for GetOwner.
+  #[repr(u8)]
+  #[derive(Clone, Copy)]
+  pub enum OwnerFormat {
+    /// The big-endian modulus, the owner field of Arweave transactions.
+    Raw = 0x00,
+    /// A public JWK, as JSON.
+    Jwk = 0x01,
+    /// A DER SubjectPublicKeyInfo.
+    Spki = 0x02,
   }
 }
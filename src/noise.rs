@@ -0,0 +1,175 @@
+//! The noise sources of the GD32VF103 for the entropy module: the internal
+//! temperature sensor read by ADC0, and the jitter of the IRC40K RC
+//! oscillator, which clocks the RTC, against the core clock.
+
+use arienai::drbg;
+use arienai::entropy;
+use arienai::entropy::HealthTests;
+use arienai::entropy::Source;
+use longan_nano::hal::pac;
+use riscv::asm;
+use riscv::register::mcycle;
+
+// RCU_CFG0
+const ADCPSC_DIV8: u32 = 0b11 << 14;
+const ADCPSC_2: u32 = 1 << 28;
+
+// RCU_APB2EN
+const ADC0EN: u32 = 1 << 9;
+
+// RCU_APB1EN
+const BKPIEN: u32 = 1 << 27;
+const PMUEN: u32 = 1 << 28;
+
+// RCU_RSTSCK
+const IRC40KEN: u32 = 1 << 0;
+const IRC40KSTB: u32 = 1 << 1;
+
+// RCU_BDCTL
+const RTCSRC: u32 = 0b11 << 8;
+const RTCSRC_IRC40K: u32 = 0b10 << 8;
+const RTCEN: u32 = 1 << 15;
+
+// PMU_CTL
+const BKPWEN: u32 = 1 << 8;
+
+// RTC_CTL
+const RSYNF: u32 = 1 << 3;
+
+// ADC_CTL1
+const ADCON: u32 = 1 << 0;
+const CLB: u32 = 1 << 2;
+const ETSRC_SWRCST: u32 = 0b111 << 17;
+const ETERC: u32 = 1 << 20;
+const SWRCST: u32 = 1 << 22;
+const TSVREN: u32 = 1 << 23;
+
+// ADC_STAT
+const EOC: u32 = 1 << 1;
+
+/// ADC channel of the temperature sensor.
+const TEMPERATURE: u32 = 16;
+
+pub struct Temperature {
+  adc: pac::ADC0,
+}
+
+impl Temperature {
+  pub fn new(adc: pac::ADC0) -> Self {
+    // The HAL owns the RCU, but leaves these bits alone.
+    let rcu = unsafe { &*pac::RCU::ptr() };
+    rcu
+      .apb2en
+      .modify(|r, w| unsafe { w.bits(r.bits() | ADC0EN) });
+    // 108 MHz APB2 / 8, under the 14 MHz limit of the ADC.
+    rcu
+      .cfg0
+      .modify(|r, w| unsafe { w.bits(r.bits() & !ADCPSC_2 | ADCPSC_DIV8) });
+
+    // A single regular conversion of the temperature sensor, started by
+    // software, with the shortest sampling time.
+    adc.rsq0.write(|w| unsafe { w.bits(0) });
+    adc.rsq2.write(|w| unsafe { w.bits(TEMPERATURE) });
+    adc
+      .ctl1
+      .write(|w| unsafe { w.bits(ADCON | ETSRC_SWRCST | ETERC | TSVREN) });
+
+    // At least 14 ADC clocks between power on and calibration.
+    asm::delay(1000);
+    adc.ctl1.modify(|r, w| unsafe { w.bits(r.bits() | CLB) });
+    while adc.ctl1.read().bits() & CLB != 0 {}
+
+    Temperature { adc }
+  }
+}
+
+impl Source for Temperature {
+  /// The low byte of a 12-bit conversion.
+  fn sample(&mut self) -> u8 {
+    let adc = &self.adc;
+    adc.ctl1.modify(|r, w| unsafe { w.bits(r.bits() | SWRCST) });
+    while adc.stat.read().bits() & EOC == 0 {}
+    // Reading the result clears EOC.
+    adc.rdata.read().bits() as u8
+  }
+}
+
+pub struct Jitter {
+  rtc: pac::RTC,
+}
+
+impl Jitter {
+  pub fn new(rtc: pac::RTC, pmu: &pac::PMU) -> Self {
+    let rcu = unsafe { &*pac::RCU::ptr() };
+    rcu
+      .apb1en
+      .modify(|r, w| unsafe { w.bits(r.bits() | PMUEN | BKPIEN) });
+    pmu.ctl.modify(|r, w| unsafe { w.bits(r.bits() | BKPWEN) });
+
+    rcu
+      .rstsck
+      .modify(|r, w| unsafe { w.bits(r.bits() | IRC40KEN) });
+    while rcu.rstsck.read().bits() & IRC40KSTB == 0 {}
+
+    // The RTC clock can only be selected once per backup domain reset. If
+    // it is already running from another clock, that jitters against the
+    // core clock as well.
+    if rcu.bdctl.read().bits() & RTCSRC == 0 {
+      rcu
+        .bdctl
+        .modify(|r, w| unsafe { w.bits(r.bits() | RTCSRC_IRC40K) });
+    }
+    rcu.bdctl.modify(|r, w| unsafe { w.bits(r.bits() | RTCEN) });
+
+    // Wait for the RTC registers to be readable.
+    rtc.ctl.modify(|r, w| unsafe { w.bits(r.bits() & !RSYNF) });
+    while rtc.ctl.read().bits() & RSYNF == 0 {}
+
+    Jitter { rtc }
+  }
+
+  /// Waits for the next tick of the RTC prescaler, at 40 kHz.
+  fn tick(&self) {
+    let div = self.rtc.divl.read().bits();
+    while self.rtc.divl.read().bits() == div {}
+  }
+}
+
+impl Source for Jitter {
+  /// The low byte of the number of core clock cycles in one RTC tick,
+  /// about 2700 at 108 MHz.
+  fn sample(&mut self) -> u8 {
+    self.tick();
+    let start = mcycle::read();
+    self.tick();
+    mcycle::read().wrapping_sub(start) as u8
+  }
+}
+
+/// Both sources, each under the health tests, as the entropy input of the
+/// DRBG.
+pub struct Noise {
+  temperature: HealthTests<Temperature>,
+  jitter: HealthTests<Jitter>,
+}
+
+impl Noise {
+  /// Starts both sources and runs their start-up tests.
+  pub fn new(adc: pac::ADC0, rtc: pac::RTC, pmu: &pac::PMU) -> Self {
+    Noise {
+      temperature: HealthTests::new(Temperature::new(adc)),
+      jitter: HealthTests::new(Jitter::new(rtc, pmu)),
+    }
+  }
+
+  /// Whether either source has failed a health test since boot.
+  pub fn failed(&self) -> bool {
+    self.temperature.failed() || self.jitter.failed()
+  }
+}
+
+impl drbg::Entropy for Noise {
+  fn entropy(&mut self) -> [u8; 32] {
+    entropy::seed(&mut [&mut self.temperature, &mut self.jitter])
+  }
+}
@@ -0,0 +1,187 @@
+//! Encodings of the public key, the owner of Arweave transactions, and of
+//! its address. The modulus is given as big-endian bytes, the exponent is
+//! always PUBLIC_EXPONENT.
+
+use crate::base64;
+use crate::rsa::PUBLIC_EXPONENT;
+use crypto_bigint::UInt;
+use sha2_const::Sha256;
+
+/// AlgorithmIdentifier { rsaEncryption, NULL }.
+const RSA_ENCRYPTION: [u8; 15] = [
+  0x30, 0x0D, 0x06, 0x09, 0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x01,
+  0x05, 0x00,
+];
+
+/// Length of the JWK of a 4096-bit key, the longest encoding.
+pub const MAX_LEN: usize = 31 + base64::encoded_len(512);
+
+/// Length of an Arweave address.
+pub const ADDRESS_LEN: usize = base64::encoded_len(32);
+
+/// SHA-256 of the big-endian modulus, hashed a limb at a time.
+pub fn address_hash<const L: usize>(n: &UInt<L>) -> [u8; 32] {
+  let mut hash = Sha256::new();
+  for limb in n.limbs().iter().rev() {
+    hash = hash.update(&limb.0.to_be_bytes());
+  }
+  hash.finalize()
+}
+
+/// The Arweave address for the SHA-256 of a modulus, its base64url
+/// encoding.
+pub fn address(hash: &[u8; 32]) -> [u8; ADDRESS_LEN] {
+  let mut address = [0u8; ADDRESS_LEN];
+  base64::encode(hash, &mut address);
+  address
+}
+
+/// data without its leading zero bytes.
+fn minimal(data: &[u8]) -> &[u8] {
+  let zeros = data.iter().take_while(|b| **b == 0).count();
+  &data[zeros..]
+}
+
+fn exponent() -> [u8; 4] {
+  (PUBLIC_EXPONENT as u32).to_be_bytes()
+}
+
+/// Writes the public JWK, with the members in the order of RFC 7638 so
+/// that its SHA-256 is the thumbprint. Returns its length. out must hold
+/// MAX_LEN bytes for a 4096-bit key.
+pub fn jwk(n: &[u8], out: &mut [u8]) -> usize {
+  let e = exponent();
+  let mut at = text(b"{\"e\":\"", out);
+  at += base64::encode(minimal(&e), &mut out[at..]);
+  at += text(b"\",\"kty\":\"RSA\",\"n\":\"", &mut out[at..]);
+  at += base64::encode(minimal(n), &mut out[at..]);
+  at + text(b"\"}", &mut out[at..])
+}
+
+fn text(text: &[u8], out: &mut [u8]) -> usize {
+  out[..text.len()].copy_from_slice(text);
+  text.len()
+}
+
+/// Length of a DER length field.
+fn length_len(len: usize) -> usize {
+  match len {
+    0..=0x7F => 1,
+    0x80..=0xFF => 2,
+    _ => 3,
+  }
+}
+
+/// Writes a DER tag and length, returns their length.
+fn header(tag: u8, len: usize, out: &mut [u8]) -> usize {
+  out[0] = tag;
+  match length_len(len) {
+    1 => out[1] = len as u8,
+    2 => out[1..3].copy_from_slice(&[0x81, len as u8]),
+    _ => {
+      out[1] = 0x82;
+      out[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+    }
+  }
+  1 + length_len(len)
+}
+
+/// Length of the contents of a DER INTEGER of the unsigned value.
+fn integer_len(value: &[u8]) -> usize {
+  // A zero byte in front keeps the value positive.
+  value.len() + (value[0] >> 7) as usize
+}
+
+fn integer(value: &[u8], out: &mut [u8]) -> usize {
+  let len = integer_len(value);
+  let at = header(0x02, len, out);
+  out[at..at + len - value.len()].fill(0);
+  out[at + len - value.len()..at + len].copy_from_slice(value);
+  at + len
+}
+
+/// Writes the DER SubjectPublicKeyInfo (RFC 3279, section 2.3.1). Returns
+/// its length, 550 bytes for a 4096-bit key.
+pub fn spki(n: &[u8], out: &mut [u8]) -> usize {
+  let e = exponent();
+  let (n, e) = (minimal(n), minimal(&e));
+
+  let tlv_len = |len| 1 + length_len(len) + len;
+  let key_len = tlv_len(integer_len(n)) + tlv_len(integer_len(e));
+  // No unused bits in front of the RSAPublicKey.
+  let bits_len = 1 + tlv_len(key_len);
+  let spki_len = RSA_ENCRYPTION.len() + tlv_len(bits_len);
+
+  let mut at = header(0x30, spki_len, out);
+  out[at..at + RSA_ENCRYPTION.len()].copy_from_slice(&RSA_ENCRYPTION);
+  at += RSA_ENCRYPTION.len();
+  at += header(0x03, bits_len, &mut out[at..]);
+  out[at] = 0;
+  at += 1;
+  at += header(0x30, key_len, &mut out[at..]);
+  at += integer(n, &mut out[at..]);
+  at += integer(e, &mut out[at..]);
+  at
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::hex;
+
+  const N: &str = "c0ffee0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdefcafe0b";
+
+  #[test]
+  fn arweave_address() {
+    let mut n = [0u8; 512];
+    n[512 - 62..].copy_from_slice(&hex(N));
+    let hash = address_hash(&crypto_bigint::U4096::from_be_slice(&n));
+    assert_eq!(hash, Sha256::new().update(&n).finalize());
+
+    // base64url(SHA-256(n)) from Python.
+    assert_eq!(
+      &address(&hash),
+      b"yoiiZ86aZ7B5-sALPFRnhkbZOC88cXC7sIwbbbUtOGc"
+    );
+  }
+
+  #[test]
+  fn jwk_members() {
+    let mut n = vec![0; 2];
+    n.extend(hex(N));
+    let mut out = [0u8; MAX_LEN];
+    let len = jwk(&n, &mut out);
+    assert_eq!(
+      core::str::from_utf8(&out[..len]).unwrap(),
+      "{\"e\":\"AQAB\",\"kty\":\"RSA\",\"n\":\"wP_uASNFZ4mrze8BI0VniavN7wEjRWeJq83vASNFZ4mrze8BI0VniavN7wEjRWeJq83vASNFZ4mrze_K_gs\"}",
+    );
+
+    let len = jwk(&[0xC5; 512], &mut out);
+    assert_eq!(len, MAX_LEN);
+  }
+
+  #[test]
+  fn spki_der() {
+    // From the Python cryptography package.
+    let mut out = [0u8; 1024];
+    let len = spki(&hex(N), &mut out);
+    assert_eq!(
+      out[..len],
+      hex(&format!(
+        "305a300d06092a864886f70d01010105000349003046023f00{}0203010001",
+        N
+      ))[..]
+    );
+
+    // Long form lengths.
+    let len = spki(&[0xC5; 512], &mut out);
+    assert_eq!(len, 550);
+    assert_eq!(
+      out[..33],
+      hex("30820222300d06092a864886f70d01010105000382020f003082020a0282020100")
+        [..]
+    );
+    assert_eq!(out[33..545], [0xC5; 512][..]);
+    assert_eq!(out[545..len], hex("0203010001")[..]);
+  }
+}
@@ -0,0 +1,207 @@
+//! Encryption of private keys at rest under a PIN.
+//!
+//! The key encryption key is PBKDF2-HMAC-SHA256 over the PIN with a per
+//! device salt. Data is sealed with ChaCha20-Poly1305 under a fresh
+//! nonce, which is kept in front of the ciphertext:
+//!
+//!   nonce (12 bytes) || ciphertext || tag (16 bytes)
+
+use crate::hmac::pbkdf2_hmac_sha256;
+use crate::hmac::HmacSha256;
+use chacha20poly1305::aead::AeadInPlace;
+use chacha20poly1305::aead::NewAead;
+use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::Key;
+use chacha20poly1305::Nonce;
+use chacha20poly1305::Tag;
+use rand_core::CryptoRng;
+use rand_core::RngCore;
+use zeroize::Zeroize;
+
+/// Slows down guessing on a dumped flash image. Takes about a second on
+/// the device.
+pub const PBKDF2_ITERATIONS: u32 = 10_000;
+
+pub const MIN_PIN_LEN: usize = 4;
+pub const MAX_PIN_LEN: usize = 32;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Bytes added to the data by sealing.
+pub const OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  /// The PIN is shorter than MIN_PIN_LEN or longer than MAX_PIN_LEN.
+  Length,
+  /// The tag does not verify, because the PIN is wrong or the data was
+  /// modified.
+  Pin,
+}
+
+/// Key encryption key derived from a PIN. Cleared on drop.
+pub struct PinKey([u8; 32]);
+
+impl Drop for PinKey {
+  fn drop(&mut self) {
+    self.0.zeroize();
+  }
+}
+
+impl PinKey {
+  pub fn derive(pin: &[u8], salt: &[u8]) -> Result<Self, Error> {
+    if !(MIN_PIN_LEN..=MAX_PIN_LEN).contains(&pin.len()) {
+      return Err(Error::Length);
+    }
+
+    let mut key = [0u8; 32];
+    pbkdf2_hmac_sha256(pin, &[salt], PBKDF2_ITERATIONS, &mut key);
+    Ok(PinKey(key))
+  }
+
+  /// Seals sealed[NONCE_LEN..sealed.len() - TAG_LEN] in place, filling in
+  /// the nonce and tag around it. aad is authenticated but not stored.
+  pub fn seal<R: RngCore + CryptoRng>(
+    &self,
+    rng: &mut R,
+    aad: &[u8],
+    sealed: &mut [u8],
+  ) {
+    let (nonce, rest) = sealed.split_at_mut(NONCE_LEN);
+    let (data, tag) = rest.split_at_mut(rest.len() - TAG_LEN);
+
+    // HMAC (K_nonce, random || data), so that the nonce does not repeat
+    // for different data even if the RNG does. K_nonce = HMAC (K, "nonce")
+    // keeps the cipher key out of the HMAC.
+    let mut random = [0u8; 32];
+    rng.fill_bytes(&mut random);
+    let mut nonce_key = HmacSha256::new(&self.0).update(b"nonce").finalize();
+    let mac = HmacSha256::new(&nonce_key).update(&random).update(data);
+    nonce.copy_from_slice(&mac.finalize()[..NONCE_LEN]);
+    nonce_key.zeroize();
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.0));
+    // Only fails for data longer than the cipher can handle.
+    let t = cipher
+      .encrypt_in_place_detached(Nonce::from_slice(nonce), aad, data)
+      .unwrap();
+    tag.copy_from_slice(&t);
+  }
+
+  /// Opens sealed in place. On success the data is in
+  /// sealed[NONCE_LEN..sealed.len() - TAG_LEN], on error it is left
+  /// encrypted.
+  pub fn open(&self, aad: &[u8], sealed: &mut [u8]) -> Result<(), Error> {
+    if sealed.len() < OVERHEAD {
+      return Err(Error::Pin);
+    }
+
+    let (nonce, rest) = sealed.split_at_mut(NONCE_LEN);
+    let (data, tag) = rest.split_at_mut(rest.len() - TAG_LEN);
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.0));
+    cipher
+      .decrypt_in_place_detached(
+        Nonce::from_slice(nonce),
+        aad,
+        data,
+        Tag::from_slice(tag),
+      )
+      .map_err(|_| Error::Pin)
+  }
+}
+
+/// The range of sealed that holds the data.
+pub fn data(sealed: &mut [u8]) -> &mut [u8] {
+  let len = sealed.len();
+  &mut sealed[NONCE_LEN..len - TAG_LEN]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand_core::SeedableRng;
+  use rand_hc::Hc128Rng;
+
+  #[test]
+  fn pbkdf2() {
+    // RFC 7914, section 11
+    let mut out = [0u8; 64];
+    pbkdf2_hmac_sha256(b"passwd", &[b"salt"], 1, &mut out);
+    assert_eq!(
+      out[..],
+      [
+        0x55, 0xac, 0x04, 0x6e, 0x56, 0xe3, 0x08, 0x9f, 0xec, 0x16, 0x91, 0xc2,
+        0x25, 0x44, 0xb6, 0x05, 0xf9, 0x41, 0x85, 0x21, 0x6d, 0xde, 0x04, 0x65,
+        0xe6, 0x8b, 0x9d, 0x57, 0xc2, 0x0d, 0xac, 0xbc, 0x49, 0xca, 0x9c, 0xcc,
+        0xf1, 0x79, 0xb6, 0x45, 0x99, 0x16, 0x64, 0xb3, 0x9d, 0x77, 0xef, 0x31,
+        0x7c, 0x71, 0xb8, 0x45, 0xb1, 0xe3, 0x0b, 0xd5, 0x09, 0x11, 0x20, 0x41,
+        0xd3, 0xa1, 0x97, 0x83,
+      ][..]
+    );
+
+    pbkdf2_hmac_sha256(b"Password", &[b"NaCl"], 80000, &mut out);
+    assert_eq!(
+      out[..],
+      [
+        0x4d, 0xdc, 0xd8, 0xf6, 0x0b, 0x98, 0xbe, 0x21, 0x83, 0x0c, 0xee, 0x5e,
+        0xf2, 0x27, 0x01, 0xf9, 0x64, 0x1a, 0x44, 0x18, 0xd0, 0x4c, 0x04, 0x14,
+        0xae, 0xff, 0x08, 0x87, 0x6b, 0x34, 0xab, 0x56, 0xa1, 0xd4, 0x25, 0xa1,
+        0x22, 0x58, 0x33, 0x54, 0x9a, 0xdb, 0x84, 0x1b, 0x51, 0xc9, 0xb3, 0x17,
+        0x6a, 0x27, 0x2b, 0xde, 0xbb, 0xa1, 0xd0, 0x78, 0x47, 0x8f, 0x62, 0xb3,
+        0x97, 0xf3, 0x3c, 0x8d,
+      ][..]
+    );
+  }
+
+  #[test]
+  fn seal_open() {
+    let mut rng = Hc128Rng::from_seed([0x16; 32]);
+    let key = PinKey::derive(b"1234", b"device").unwrap();
+
+    let mut sealed = [0u8; 5 + OVERHEAD];
+    data(&mut sealed).copy_from_slice(b"hello");
+    key.seal(&mut rng, b"aad", &mut sealed);
+    assert_ne!(data(&mut sealed), b"hello");
+
+    let mut copy = sealed;
+    key.open(b"aad", &mut copy).unwrap();
+    assert_eq!(data(&mut copy), b"hello");
+
+    // Wrong PIN, wrong salt, wrong aad and a modified ciphertext.
+    let wrong = PinKey::derive(b"1235", b"device").unwrap();
+    assert_eq!(wrong.open(b"aad", &mut sealed.clone()), Err(Error::Pin));
+    let wrong = PinKey::derive(b"1234", b"other").unwrap();
+    assert_eq!(wrong.open(b"aad", &mut sealed.clone()), Err(Error::Pin));
+    assert_eq!(key.open(b"aaD", &mut sealed.clone()), Err(Error::Pin));
+    sealed[NONCE_LEN] ^= 1;
+    assert_eq!(key.open(b"aad", &mut sealed), Err(Error::Pin));
+
+    assert_eq!(PinKey::derive(b"123", b"").err(), Some(Error::Length));
+    assert_eq!(PinKey::derive(&[b'1'; 33], b"").err(), Some(Error::Length));
+  }
+
+  #[test]
+  fn nonce() {
+    let mut rng = Hc128Rng::from_seed([0x16; 32]);
+    let key = PinKey::derive(b"1234", b"device").unwrap();
+
+    let mut random = [0u8; 32];
+    rng.clone().fill_bytes(&mut random);
+    let mut sealed = [0u8; 5 + OVERHEAD];
+    data(&mut sealed).copy_from_slice(b"hello");
+    key.seal(&mut rng, b"", &mut sealed);
+
+    // Keyed with HMAC (K, "nonce"), not with K itself.
+    let nonce_key = HmacSha256::new(&key.0).update(b"nonce").finalize();
+    let mac = HmacSha256::new(&nonce_key).update(&random).update(b"hello");
+    assert_eq!(sealed[..NONCE_LEN], mac.finalize()[..NONCE_LEN]);
+
+    // A different nonce for the same data under a fresh random value.
+    let mut again = [0u8; 5 + OVERHEAD];
+    data(&mut again).copy_from_slice(b"hello");
+    key.seal(&mut rng, b"", &mut again);
+    assert_ne!(sealed[..NONCE_LEN], again[..NONCE_LEN]);
+  }
+}
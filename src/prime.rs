@@ -0,0 +1,226 @@
+//! Random probable primes for RSA key generation.
+//!
+//! Candidates are searched for incrementally from a random odd start:
+//! trial division by the small primes is tracked through residues, and
+//! only candidates that survive it are given to Miller-Rabin.
+
+use crate::rsa;
+use crate::rsa::MontgomeryParams;
+use crate::rsa::PUBLIC_EXPONENT;
+use crypto_bigint::Limb;
+use crypto_bigint::LimbUInt;
+use crypto_bigint::UInt;
+use rand_core::CryptoRng;
+use rand_core::RngCore;
+use zeroize::Zeroize;
+
+/// Miller-Rabin rounds for primes of 1024 bits and more. FIPS 186-4,
+/// table C.3 asks for 5 rounds for 1024 bit primes and 4 for 1536 bits.
+pub const MILLER_RABIN_ROUNDS: usize = 5;
+
+/// Number of odd primes below 2048.
+const SMALL_PRIMES_LEN: usize = 308;
+
+/// The odd primes below 2048, used for trial division.
+const SMALL_PRIMES: [u16; SMALL_PRIMES_LEN] = small_primes();
+
+/// How far to step from a random start before drawing a new one.
+const MAX_DELTA: u32 = 1 << 20;
+
+const fn small_primes() -> [u16; SMALL_PRIMES_LEN] {
+  let mut primes = [0u16; SMALL_PRIMES_LEN];
+  let mut count = 0;
+  let mut n = 3;
+  while count < SMALL_PRIMES_LEN {
+    let mut prime = true;
+    let mut i = 0;
+    while i < count {
+      if n % primes[i] == 0 {
+        prime = false;
+        break;
+      }
+      i += 1;
+    }
+
+    if prime {
+      primes[count] = n;
+      count += 1;
+    }
+    n += 2;
+  }
+
+  primes
+}
+
+/// Uniformly random integer of H limbs, drawn in a single request.
+fn random_uint<R: RngCore + CryptoRng, const H: usize>(rng: &mut R) -> UInt<H> {
+  let mut bytes = [0u8; rsa::MAX_LIMBS * Limb::BYTE_SIZE];
+  let bytes = &mut bytes[..H * Limb::BYTE_SIZE];
+  rng.fill_bytes(bytes);
+  let x = rsa::uint_from_be(bytes);
+  bytes.zeroize();
+  x
+}
+
+/// Returns false if n has a factor below 2048 other than itself.
+pub fn trial_division<const H: usize>(n: &UInt<H>) -> bool {
+  let limbs = n.to_uint_array();
+  SMALL_PRIMES.iter().all(|p| {
+    rsa::rem_limb(&limbs, *p as LimbUInt) != 0 || *n == UInt::from_u16(*p)
+  })
+}
+
+/// Returns a random prime of exactly H * BIT_SIZE bits with the top two bits
+/// set, so that the product of two of them has exactly twice as many bits.
+/// p - 1 is never a multiple of PUBLIC_EXPONENT.
+pub fn generate_prime<R: RngCore + CryptoRng, const H: usize>(
+  rng: &mut R,
+) -> UInt<H> {
+  loop {
+    let mut limbs = random_uint::<_, H>(rng).to_uint_array();
+    limbs[H - 1] |= 0b11 << (Limb::BIT_SIZE - 2);
+    limbs[0] |= 1;
+    let base = UInt::<H>::from_uint_array(limbs);
+
+    let mut residues = [0 as LimbUInt; SMALL_PRIMES_LEN];
+    for (r, p) in residues.iter_mut().zip(SMALL_PRIMES.iter()) {
+      *r = rsa::rem_limb(&limbs, *p as LimbUInt);
+    }
+    let residue_e = rsa::rem_limb(&limbs, PUBLIC_EXPONENT);
+
+    let mut delta: u32 = 0;
+    while delta < MAX_DELTA {
+      let sieved = residues
+        .iter()
+        .zip(SMALL_PRIMES.iter())
+        .any(|(r, p)| (r + delta as LimbUInt) % *p as LimbUInt == 0);
+      let e_divides = (residue_e + delta as LimbUInt) % PUBLIC_EXPONENT == 1;
+
+      if !sieved && !e_divides {
+        let candidate = base.wrapping_add(&UInt::from_u32(delta));
+        // Stepping may carry out of the top bits in the rare case where
+        // base is within MAX_DELTA of 2**(H * BIT_SIZE).
+        if candidate < base {
+          break;
+        }
+
+        if is_probable_prime(rng, &candidate, MILLER_RABIN_ROUNDS) {
+          return candidate;
+        }
+      }
+
+      delta += 2;
+    }
+  }
+}
+
+/// Miller-Rabin test of n with rounds random bases. Composites pass with
+/// probability at most 4**-rounds.
+pub fn is_probable_prime<R: RngCore + CryptoRng, const H: usize>(
+  rng: &mut R,
+  n: &UInt<H>,
+  rounds: usize,
+) -> bool {
+  // Also rejects even n and n = 1.
+  let mp = match MontgomeryParams::new(n) {
+    Some(mp) => mp,
+    None => return false,
+  };
+  // There is no base in [2, n - 2] to pick for n = 3.
+  if *n == UInt::from_u8(3) {
+    return true;
+  }
+  let n1 = n.wrapping_sub(&UInt::ONE);
+
+  // n - 1 = 2**s * d with d odd.
+  let mut s = 0;
+  for limb in n1.to_uint_array().iter() {
+    s += limb.trailing_zeros() as usize;
+    if *limb != 0 {
+      break;
+    }
+  }
+  let d = n1.shr_vartime(s).to_uint_array();
+
+  'rounds: for _ in 0..rounds {
+    // a in [2, n - 2]: below 2**(bits(n) - 1) <= n - 1 and not 0 or 1.
+    let a = loop {
+      let a =
+        random_uint::<_, H>(rng).shr_vartime(H * Limb::BIT_SIZE - n.bits() + 1);
+      if a > UInt::ONE {
+        break a;
+      }
+    };
+
+    let mut x = rsa::decrypt(&a, &d, &mp);
+    if x == UInt::ONE || x == n1 {
+      continue;
+    }
+
+    for _ in 1..s {
+      x = rsa::encrypt(&x, 2, &mp);
+      if x == n1 {
+        continue 'rounds;
+      }
+      if x == UInt::ONE {
+        return false;
+      }
+    }
+
+    return false;
+  }
+
+  true
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand_core::SeedableRng;
+  use rand_hc::Hc128Rng;
+
+  type U128 = UInt<{ 128 / Limb::BIT_SIZE }>;
+
+  #[test]
+  fn small_primes() {
+    assert_eq!(SMALL_PRIMES[0], 3);
+    assert_eq!(SMALL_PRIMES[SMALL_PRIMES_LEN - 1], 2039);
+    for i in 1..SMALL_PRIMES_LEN {
+      assert!(SMALL_PRIMES[i - 1] < SMALL_PRIMES[i]);
+    }
+  }
+
+  #[test]
+  fn miller_rabin() {
+    let mut rng = Hc128Rng::from_seed([0x12; 32]);
+    let primes = [3, 5, 65537, (1 << 31) - 1];
+    for p in primes.iter() {
+      assert!(is_probable_prime(&mut rng, &U128::from_u64(*p), 20));
+    }
+    // 2**127 - 1 is prime, (2**89 - 1)(2**61 - 1) is not, nor are small
+    // squares and Carmichael numbers.
+    let m127 = U128::MAX.shr_vartime(1);
+    assert!(is_probable_prime(&mut rng, &m127, 20));
+    let (m89, m61) = (U128::MAX.shr_vartime(39), U128::MAX.shr_vartime(67));
+    assert!(!is_probable_prime(&mut rng, &m89.wrapping_mul(&m61), 20));
+    for n in [1, 4, 9, 561, 41041, 825265, 321197185].iter() {
+      assert!(!is_probable_prime(&mut rng, &U128::from_u32(*n), 20));
+    }
+  }
+
+  #[test]
+  fn generate() {
+    let mut rng = Hc128Rng::from_seed([0x12; 32]);
+    for _ in 0..8 {
+      let p: U128 = generate_prime(&mut rng);
+      assert_eq!(p.shr_vartime(126), U128::from_u8(0b11));
+      let limbs = p.to_uint_array();
+      for q in SMALL_PRIMES.iter() {
+        assert_ne!(rsa::rem_limb(&limbs, *q as LimbUInt), 0);
+      }
+      assert!(is_probable_prime(&mut rng, &p, 20));
+      let p1 = p.wrapping_sub(&UInt::ONE).to_uint_array();
+      assert_ne!(rsa::rem_limb(&p1, PUBLIC_EXPONENT), 0);
+    }
+  }
+}
@@ -1,39 +1,687 @@
-use core::ops::Shl;
-use crypto_bigint::prelude::ArrayEncoding;
 use crypto_bigint::subtle::Choice;
+use crypto_bigint::subtle::ConditionallySelectable;
+use crypto_bigint::subtle::ConstantTimeEq;
 use crypto_bigint::Integer;
 use crypto_bigint::Limb;
 use crypto_bigint::LimbUInt;
-use crypto_bigint::U4096;
-use num_bigint::BigUint;
+use crypto_bigint::UInt;
+use crypto_bigint::WideLimbUInt;
+use rand_core::CryptoRng;
+use rand_core::RngCore;
 use sha2_const::Sha256;
+use zeroize::Zeroize;
+
+use crate::prime;
+
+/// Limb count of the largest supported modulus. Scratch buffers are sized
+/// for it and sliced down to the actual key size.
+pub const MAX_LIMBS: usize = 4096 / Limb::BIT_SIZE;
+
+/// Arweave keys always use e = 65537.
+pub const PUBLIC_EXPONENT: LimbUInt = 65537;
+
+/// Limb count of the random factor that blinds private exponents, 64 bits.
+const BLIND_LIMBS: usize = 64 / Limb::BIT_SIZE;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  /// The message can not be encoded with this key, e.g. wrong digest size.
+  Encoding,
+  /// The signature is not valid for the digest under the public key.
+  Verify,
+  /// A signature made by the device does not verify under the public key.
+  /// This points to a fault during the private key operation, and the
+  /// signature must not be released since it may leak the factorisation
+  /// of n.
+  Fault,
+  /// The ciphertext does not decrypt to a valid encoding. Deliberately
+  /// says nothing about which check failed.
+  Decryption,
+}
+
+/// Why a key given in full, e.g. from an imported JWK, was rejected. The
+/// values are the error codes sent back to the host.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyError {
+  /// n is not exactly L * BIT_SIZE bits long.
+  ModulusSize = 0x01,
+  /// e is not PUBLIC_EXPONENT.
+  PublicExponent = 0x02,
+  /// n is not p * q.
+  Modulus = 0x03,
+  /// p or q is not prime.
+  Composite = 0x04,
+  /// dp or dq is not e**-1 mod (p - 1) or (q - 1).
+  CrtExponent = 0x05,
+  /// qi is not q**-1 mod p.
+  CrtCoefficient = 0x06,
+  /// d is not congruent to dp and dq, so e * d is not 1 mod λ(n).
+  PrivateExponent = 0x07,
+}
+
+/// Hash functions supported by EMSA-PKCS1-v1_5.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hash {
+  Sha256,
+  Sha512,
+}
+
+impl Hash {
+  /// Digest size in bytes.
+  pub fn size(self) -> usize {
+    match self {
+      Hash::Sha256 => 256 / 8,
+      Hash::Sha512 => 512 / 8,
+    }
+  }
+
+  /// DER encoding of the DigestInfo up to the digest itself.
+  /// See [1], section 9.2, note 1.
+  fn digest_info_prefix(self) -> &'static [u8] {
+    match self {
+      Hash::Sha256 => &[
+        0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03,
+        0x04, 0x02, 0x01, 0x05, 0x00, 0x04, 0x20,
+      ],
+      Hash::Sha512 => &[
+        0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03,
+        0x04, 0x02, 0x03, 0x05, 0x00, 0x04, 0x40,
+      ],
+    }
+  }
+}
+
+/// CRT form of the private exponent, as carried by Arweave JWKs in the
+/// `p`, `q`, `dp`, `dq` and `qi` members.
+/// H is the limb count of p and q, half of the modulus.
+pub struct CrtParams<const H: usize> {
+  pub p: UInt<H>,
+  pub q: UInt<H>,
+  /// d mod (p - 1)
+  pub dp: UInt<H>,
+  /// d mod (q - 1)
+  pub dq: UInt<H>,
+  /// q^-1 mod p
+  pub qinv: UInt<H>,
+  monty_p: MontgomeryParams<H>,
+  monty_q: MontgomeryParams<H>,
+}
+
+impl<const H: usize> CrtParams<H> {
+  /// Returns None if p or q is even.
+  pub fn new(
+    p: UInt<H>,
+    q: UInt<H>,
+    dp: UInt<H>,
+    dq: UInt<H>,
+    qinv: UInt<H>,
+  ) -> Option<Self> {
+    Some(CrtParams {
+      monty_p: MontgomeryParams::new(&p)?,
+      monty_q: MontgomeryParams::new(&q)?,
+      p,
+      q,
+      dp,
+      dq,
+      qinv,
+    })
+  }
+}
+
+/// L is the limb count of the modulus and H = L / 2 the limb count of the
+/// CRT parameters.
+pub struct PrivateKey<const L: usize, const H: usize> {
+  pub n: UInt<L>,
+  pub d: UInt<L>,
+  pub crt: Option<CrtParams<H>>,
+  monty: MontgomeryParams<L>,
+}
+
+pub type PrivateKey2048 =
+  PrivateKey<{ 2048 / Limb::BIT_SIZE }, { 1024 / Limb::BIT_SIZE }>;
+pub type PrivateKey3072 =
+  PrivateKey<{ 3072 / Limb::BIT_SIZE }, { 1536 / Limb::BIT_SIZE }>;
+pub type PrivateKey4096 =
+  PrivateKey<{ 4096 / Limb::BIT_SIZE }, { 2048 / Limb::BIT_SIZE }>;
+
+impl<const H: usize> Drop for CrtParams<H> {
+  fn drop(&mut self) {
+    self.p.zeroize();
+    self.q.zeroize();
+    self.dp.zeroize();
+    self.dq.zeroize();
+    self.qinv.zeroize();
+    self.monty_p.zeroize();
+    self.monty_q.zeroize();
+  }
+}
+
+impl<const L: usize, const H: usize> Drop for PrivateKey<L, H> {
+  // The CRT parameters are cleared by their own Drop.
+  fn drop(&mut self) {
+    self.d.zeroize();
+  }
+}
+
+impl<const L: usize, const H: usize> PrivateKey<L, H> {
+  /// Builds the Montgomery context for n, which is then reused by every
+  /// operation with this key. Returns None if n is even.
+  pub fn new(
+    n: UInt<L>,
+    d: UInt<L>,
+    crt: Option<CrtParams<H>>,
+  ) -> Option<Self> {
+    Some(PrivateKey {
+      monty: MontgomeryParams::new(&n)?,
+      n,
+      d,
+      crt,
+    })
+  }
 
-const EM_LEN: usize = (4095 + 7) / 8;
-const EM_BITS: usize = 4095;
+  /// The Montgomery context of the modulus.
+  pub fn monty(&self) -> &MontgomeryParams<L> {
+    &self.monty
+  }
+
+  /// Raw private key operation. Uses the CRT parameters when available.
+  pub fn decrypt(&self, c: &UInt<L>) -> UInt<L> {
+    match &self.crt {
+      Some(crt) => decrypt_crt(c, crt, 0),
+      None => decrypt(c, &self.d.to_uint_array(), &self.monty),
+    }
+  }
+
+  /// Raw private key operation with base blinding: c is multiplied by r^e
+  /// before the exponentiation and the result by r^-1 afterwards, for a
+  /// fresh random r.
+  ///
+  /// The exponent is blinded as well, for a random 64-bit k. d becomes
+  /// d + k * (e * d - 1), where e * d - 1 is a multiple of λ(n), and on the
+  /// CRT path dP and dQ become dP + k * (p - 1) and dQ + k * (q - 1).
+  pub fn decrypt_blinded<R: RngCore + CryptoRng>(
+    &self,
+    rng: &mut R,
+    c: &UInt<L>,
+  ) -> UInt<L> {
+    let mp = &self.monty;
+    let (r, r_inv) = blinding_pair(rng, &self.n);
+
+    let c = mul_mod(c, &encrypt(&r, PUBLIC_EXPONENT, mp), mp);
+    let k = rng.next_u64();
+    let m = match &self.crt {
+      Some(crt) => decrypt_crt(&c, crt, k),
+      None => {
+        // e * d - 1 = (e - 1) * d + (d - 1), one limb longer than d.
+        let d = self.d.to_uint_array();
+        let d1 = self.d.wrapping_sub(&UInt::ONE);
+        let mut f = [0 as LimbUInt; MAX_LIMBS + 1];
+        let f = &mut f[..L + 1];
+        f[..L].copy_from_slice(&d1.to_uint_array());
+        f[L] = add_mul_vvw(&mut f[..L], &d, PUBLIC_EXPONENT - 1);
+
+        let mut y = [0 as LimbUInt; MAX_LIMBS + 1 + BLIND_LIMBS];
+        let y = &mut y[..L + 1 + BLIND_LIMBS];
+        blind_exponent(y, &d, f, k);
+        monty_modpow(&c, y, mp)
+      }
+    };
+
+    mul_mod(&m, &r_inv, mp)
+  }
+
+  /// Generates a new key with e = PUBLIC_EXPONENT from two random primes
+  /// of half the modulus size. d is e**-1 mod (p - 1)(q - 1).
+  ///
+  /// Before the key is returned it signs and verifies a test digest. If
+  /// that fails, Error::Fault is returned and the key is dropped.
+  pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Result<Self, Error> {
+    let key = loop {
+      let p = prime::generate_prime::<_, H>(rng);
+      let q = prime::generate_prime::<_, H>(rng);
+
+      // FIPS 186-4, B.3.1: |p - q| > 2**(nlen / 2 - 100)
+      let diff = if p > q {
+        p.wrapping_sub(&q)
+      } else {
+        q.wrapping_sub(&p)
+      };
+      if diff.bits() <= H * Limb::BIT_SIZE - 100 {
+        continue;
+      }
+
+      // Primes from generate_prime never have e | p - 1, so this does not
+      // fail, but a retry is cheaper than reasoning about it.
+      if let Some(key) = PrivateKey::from_primes(p, q) {
+        break key;
+      }
+    };
+
+    key.self_test(rng)?;
+    Ok(key)
+  }
+
+  /// Builds the key with e = PUBLIC_EXPONENT for the primes p and q, with
+  /// d = e**-1 mod (p - 1)(q - 1) and the CRT parameters. Returns None if
+  /// e is not invertible mod p - 1 or q - 1, or if p and q are equal.
+  pub fn from_primes(p: UInt<H>, q: UInt<H>) -> Option<Self> {
+    let p1 = p.wrapping_sub(&UInt::ONE);
+    let q1 = q.wrapping_sub(&UInt::ONE);
+    let (lo, hi) = p.mul_wide(&q);
+    let n = concat::<H, L>(&lo, &hi);
+    let (lo, hi) = p1.mul_wide(&q1);
+    let phi = concat::<H, L>(&lo, &hi);
+
+    let d = inv_public_exponent(&phi)?;
+    let dp = inv_public_exponent(&p1)?;
+    let dq = inv_public_exponent(&q1)?;
+    let qinv = inv_mod(&q, &p)?;
+
+    PrivateKey::new(n, d, CrtParams::new(p, q, dp, dq, qinv))
+  }
+
+  /// Builds the key from all of its components, as found in a JWK, after
+  /// checking that they belong together:
+  ///
+  /// - n has exactly L * BIT_SIZE bits, e = PUBLIC_EXPONENT and n = p * q
+  ///   for probable primes p and q.
+  /// - e * dp = 1 mod (p - 1), e * dq = 1 mod (q - 1) and qi * q = 1 mod p,
+  ///   with dp, dq and qi reduced.
+  /// - d = dp mod (p - 1) and d = dq mod (q - 1). With the above, this is
+  ///   e * d = 1 mod lcm(p - 1, q - 1) = λ(n).
+  #[allow(clippy::too_many_arguments)]
+  pub fn from_components<R: RngCore + CryptoRng>(
+    rng: &mut R,
+    n: UInt<L>,
+    e: u32,
+    d: UInt<L>,
+    p: UInt<H>,
+    q: UInt<H>,
+    dp: UInt<H>,
+    dq: UInt<H>,
+    qinv: UInt<H>,
+  ) -> Result<Self, KeyError> {
+    if n.bits() != L * Limb::BIT_SIZE {
+      return Err(KeyError::ModulusSize);
+    }
+    if e as LimbUInt != PUBLIC_EXPONENT {
+      return Err(KeyError::PublicExponent);
+    }
+
+    let (lo, hi) = p.mul_wide(&q);
+    if concat::<H, L>(&lo, &hi) != n {
+      return Err(KeyError::Modulus);
+    }
+    if !prime::is_probable_prime(rng, &p, prime::MILLER_RABIN_ROUNDS)
+      || !prime::is_probable_prime(rng, &q, prime::MILLER_RABIN_ROUNDS)
+    {
+      return Err(KeyError::Composite);
+    }
+
+    let p1 = p.wrapping_sub(&UInt::ONE);
+    let q1 = q.wrapping_sub(&UInt::ONE);
+    if inv_public_exponent(&p1) != Some(dp)
+      || inv_public_exponent(&q1) != Some(dq)
+    {
+      return Err(KeyError::CrtExponent);
+    }
+    if inv_mod(&q, &p) != Some(qinv) {
+      return Err(KeyError::CrtCoefficient);
+    }
+
+    let zero = UInt::<H>::ZERO;
+    if rem(&d, &concat::<H, L>(&p1, &zero)) != concat::<H, L>(&dp, &zero)
+      || rem(&d, &concat::<H, L>(&q1, &zero)) != concat::<H, L>(&dq, &zero)
+    {
+      return Err(KeyError::PrivateExponent);
+    }
+
+    // Both p and q are odd primes by now, so neither of these fails.
+    let crt = CrtParams::new(p, q, dp, dq, qinv).ok_or(KeyError::Composite)?;
+    PrivateKey::new(n, d, Some(crt)).ok_or(KeyError::Modulus)
+  }
+
+  /// Pairwise consistency test: signs a fixed digest and checks the
+  /// signature under the public key.
+  pub fn self_test<R: RngCore + CryptoRng>(
+    &self,
+    rng: &mut R,
+  ) -> Result<(), Error> {
+    let k = L * Limb::BYTE_SIZE;
+    let digest = [0x5A; 256 / 8];
+    let mut salt = [0u8; 32];
+    rng.fill_bytes(&mut salt);
+
+    let mut sig = [0u8; MAX_LIMBS * Limb::BYTE_SIZE];
+    let sig = &mut sig[..k];
+    sign_pss_with_salt(rng, &digest, &salt, self, sig)?;
+    verify_pss(&digest, sig, salt.len(), &self.monty).map_err(|_| Error::Fault)
+  }
+}
 
 // signPSSWithSalt calculates the signature of hashed using PSS [1] with specified salt.
 /// Note that hashed must be the result of hashing the input message using the
 /// given hash function. salt is a random sequence of bytes whose length will be
-/// later used to verify the signature.
-pub fn sign_pss_with_salt(
+/// later used to verify the signature. The signature is written to sig,
+/// which must be exactly as long as the modulus in bytes.
+pub fn sign_pss_with_salt<
+  R: RngCore + CryptoRng,
+  const L: usize,
+  const H: usize,
+>(
+  rng: &mut R,
   hashed: &[u8],
   salt: &[u8],
-  d: &[LimbUInt; 4096 / Limb::BIT_SIZE],
-  n: &U4096,
-) -> Result<[u8; 512], ()> {
-  let em = emsa_pss_encode(hashed, salt)?;
+  key: &PrivateKey<L, H>,
+  sig: &mut [u8],
+) -> Result<(), Error> {
+  let k = L * Limb::BYTE_SIZE;
+  if sig.len() != k {
+    return Err(Error::Encoding);
+  }
+
+  // See [1], section 8.1.1: emBits = modBits - 1.
+  let em_bits = key.n.bits() - 1;
+  let em_len = (em_bits + 7) / 8;
+
+  // EM is right-aligned in a buffer of the modulus size so that it can be
+  // read back as an integer and compared with the check below.
+  let mut em = [0u8; MAX_LIMBS * Limb::BYTE_SIZE];
+  let em = &mut em[..k];
+  emsa_pss_encode(hashed, salt, em_bits, &mut em[k - em_len..])?;
+
+  sign_encoded(rng, em, key, sig)
+}
+
+/// Signs hashed with RSASSA-PKCS1-v1_5 [1]. hashed must be the digest of
+/// the message under hash. The signature is written to sig, which must be
+/// exactly as long as the modulus in bytes.
+pub fn sign_pkcs1v15<R: RngCore + CryptoRng, const L: usize, const H: usize>(
+  rng: &mut R,
+  hash: Hash,
+  hashed: &[u8],
+  key: &PrivateKey<L, H>,
+  sig: &mut [u8],
+) -> Result<(), Error> {
+  // See [1], section 8.2.1
+  let k = L * Limb::BYTE_SIZE;
+  if sig.len() != k {
+    return Err(Error::Encoding);
+  }
+
+  // 1. EM = EMSA-PKCS1-V1_5-ENCODE (M, k), where k is the length of n in
+  //    octets. EM is right-aligned in a buffer of the signature size, as
+  //    for PSS.
+  let em_len = (key.n.bits() + 7) / 8;
+  let mut em = [0u8; MAX_LIMBS * Limb::BYTE_SIZE];
+  let em = &mut em[..k];
+  emsa_pkcs1v15_encode(hash, hashed, &mut em[k - em_len..])?;
+
+  sign_encoded(rng, em, key, sig)
+}
+
+/// RSASP1 on an encoded message em of the modulus size, followed by the
+/// fault check. Writes the signature to sig.
+fn sign_encoded<R: RngCore + CryptoRng, const L: usize, const H: usize>(
+  rng: &mut R,
+  em: &[u8],
+  key: &PrivateKey<L, H>,
+  sig: &mut [u8],
+) -> Result<(), Error> {
+  let k = L * Limb::BYTE_SIZE;
+
+  let c = uint_from_be::<L>(em);
+  let m = key.decrypt_blinded(rng, &c);
+
+  // Re-apply the public exponent and compare against the encoded message
+  // before letting the signature out. Checked twice so that a single
+  // skipped branch is not enough to release a faulty signature.
+  let mut check = [0u8; MAX_LIMBS * Limb::BYTE_SIZE];
+  let check = &mut check[..k];
+  uint_to_be(&encrypt(&m, PUBLIC_EXPONENT, &key.monty), check);
+  if !redundant_eq(check, em) {
+    return Err(Error::Fault);
+  }
+  if !redundant_eq(em, check) {
+    return Err(Error::Fault);
+  }
+
+  uint_to_be(&m, sig);
+  Ok(())
+}
+
+/// verifyPSS checks that sig is a valid PSS signature of hashed under the
+/// public key (n, PUBLIC_EXPONENT), given by the Montgomery context of n.
+/// salt_len must match the salt length used when signing.
+pub fn verify_pss<const L: usize>(
+  hashed: &[u8],
+  sig: &[u8],
+  salt_len: usize,
+  monty: &MontgomeryParams<L>,
+) -> Result<(), Error> {
+  // See [1], section 8.1.2
+  let n = &monty.modulus;
+  let k = L * Limb::BYTE_SIZE;
+  if sig.len() != k {
+    return Err(Error::Verify);
+  }
+
+  // 2.a / 2.b. Signature representative must be in [0, n - 1].
+  let s = uint_from_be::<L>(sig);
+  if s >= *n {
+    return Err(Error::Verify);
+  }
+
+  // 2.c. m = s^e mod n
+  let m = encrypt(&s, PUBLIC_EXPONENT, monty);
+
+  // 2.d. EM = I2OSP(m, emLen) with emLen = ceil((modBits - 1) / 8). When
+  // modBits - 1 is a multiple of 8 the leading octet of m must be zero.
+  let em_bits = n.bits() - 1;
+  let em_len = (em_bits + 7) / 8;
+
+  let mut em = [0u8; MAX_LIMBS * Limb::BYTE_SIZE];
+  let em = &mut em[..k];
+  uint_to_be(&m, em);
+  if em[..k - em_len].iter().any(|e| *e != 0) {
+    return Err(Error::Verify);
+  }
+
+  emsa_pss_verify(hashed, &mut em[k - em_len..], em_bits, salt_len)
+}
+
+/// verify_pkcs1v15 checks that sig is a valid RSASSA-PKCS1-v1_5 signature
+/// of hashed under the public key (n, PUBLIC_EXPONENT), given by the
+/// Montgomery context of n.
+pub fn verify_pkcs1v15<const L: usize>(
+  hash: Hash,
+  hashed: &[u8],
+  sig: &[u8],
+  monty: &MontgomeryParams<L>,
+) -> Result<(), Error> {
+  // See [1], section 8.2.2
+  let k = L * Limb::BYTE_SIZE;
+
+  // 1. If the length of the signature S is not k octets, output "invalid
+  //    signature" and stop.
+  if sig.len() != k {
+    return Err(Error::Verify);
+  }
+
+  // 2.a / 2.b. Signature representative must be in [0, n - 1].
+  let s = uint_from_be::<L>(sig);
+  if s >= monty.modulus {
+    return Err(Error::Verify);
+  }
+
+  // 2.c. m = s^e mod n
+  //
+  // 2.d. EM = I2OSP(m, k)
+  let mut em = [0u8; MAX_LIMBS * Limb::BYTE_SIZE];
+  let em = &mut em[..k];
+  uint_to_be(&encrypt(&s, PUBLIC_EXPONENT, monty), em);
+
+  // 3. EM' = EMSA-PKCS1-V1_5-ENCODE (M, k), right-aligned as when signing.
+  let em_len = (monty.modulus.bits() + 7) / 8;
+  let mut expected = [0u8; MAX_LIMBS * Limb::BYTE_SIZE];
+  let expected = &mut expected[..k];
+  if emsa_pkcs1v15_encode(hash, hashed, &mut expected[k - em_len..]).is_err() {
+    return Err(Error::Verify);
+  }
+
+  // 4. Compare the encoded message EM and the second encoded message EM'.
+  if em.ct_eq(expected).into() {
+    Ok(())
+  } else {
+    Err(Error::Verify)
+  }
+}
+
+/// Decrypts ciphertext with RSAES-OAEP [1], using SHA-256 both for the
+/// label and in MGF1. The message is written to the start of out, which
+/// must hold at least k - 2 * hLen - 2 octets, and its length is returned.
+///
+/// The padding and label checks do not branch on the decrypted data and
+/// all fail with the same error, so that the device can not be used as a
+/// padding oracle.
+pub fn decrypt_oaep<R: RngCore + CryptoRng, const L: usize, const H: usize>(
+  rng: &mut R,
+  label: &[u8],
+  ciphertext: &[u8],
+  key: &PrivateKey<L, H>,
+  out: &mut [u8],
+) -> Result<usize, Error> {
+  // See [1], section 7.1.2
+  let h_len = 256 / 8;
+  let k = L * Limb::BYTE_SIZE;
+  let em_len = (key.n.bits() + 7) / 8;
+
+  // 1.b. If the length of the ciphertext C is not k octets, output
+  //      "decryption error" and stop.
+  //
+  // 1.c. If k < 2hLen + 2, output "decryption error" and stop.
+  if ciphertext.len() != k || em_len < 2 * h_len + 2 {
+    return Err(Error::Decryption);
+  }
+  if out.len() < em_len - 2 * h_len - 2 {
+    return Err(Error::Encoding);
+  }
+
+  // 2.a. c = OS2IP(C), which must be in [0, n - 1].
+  let c = uint_from_be::<L>(ciphertext);
+  if c >= key.n {
+    return Err(Error::Decryption);
+  }
+
+  // 2.b. m = RSADP(K, c)
+  //
+  // 2.c. EM = I2OSP(m, k)
+  let m = key.decrypt_blinded(rng, &c);
+  let mut em = [0u8; MAX_LIMBS * Limb::BYTE_SIZE];
+  let em = &mut em[..k];
+  uint_to_be(&m, em);
+  let em = &mut em[k - em_len..];
 
-  let c = U4096::from_be_slice(&em);
-  let m = decrypt(&c, d, n);
+  // 3.a. lHash = Hash(L)
+  let l_hash = Sha256::new().update(label).finalize();
 
-  let mut m_bytes = m.to_be_byte_array();
-  let plaintext = left_pad(m_bytes.as_slice());
+  // 3.b. EM = Y || maskedSeed || maskedDB
+  let first_byte_is_zero = em[0].ct_eq(&0);
+  let (seed, db) = em[1..].split_at_mut(h_len);
+
+  // 3.c. seedMask = MGF(maskedDB, hLen)
+  //
+  // 3.d. seed = maskedSeed \xor seedMask
+  mgf1_xor(seed, db);
+
+  // 3.e. dbMask = MGF(seed, k - hLen - 1)
+  //
+  // 3.f. DB = maskedDB \xor dbMask
+  mgf1_xor(db, seed);
+
+  // 3.g. DB = lHash' || PS || 0x01 || M. If there is no 0x01 octet to
+  //      separate PS from M, if lHash does not equal lHash', or if Y is
+  //      nonzero, output "decryption error" and stop.
+  let (l_hash2, rest) = db.split_at(h_len);
+  let l_hash_good = l_hash[..].ct_eq(l_hash2);
+
+  // looking_for_index is set until the first 0x01 octet, index is its
+  // offset in rest and invalid records a nonzero octet before it.
+  let mut looking_for_index = Choice::from(1);
+  let mut index: u32 = 0;
+  let mut invalid = Choice::from(0);
+  for (i, b) in rest.iter().enumerate() {
+    let equals0 = b.ct_eq(&0);
+    let equals1 = b.ct_eq(&1);
+    index.conditional_assign(&(i as u32), looking_for_index & equals1);
+    looking_for_index &= !equals1;
+    invalid |= looking_for_index & !equals0;
+  }
+
+  let valid = first_byte_is_zero & l_hash_good & !invalid & !looking_for_index;
+  if !bool::from(valid) {
+    return Err(Error::Decryption);
+  }
 
-  Ok(plaintext)
+  // 4. Output the message M.
+  let msg = &rest[index as usize + 1..];
+  out[..msg.len()].copy_from_slice(msg);
+  Ok(msg.len())
 }
 
-// n (in bits) = 4096
-fn emsa_pss_encode(m_hash: &[u8], salt: &[u8]) -> Result<[u8; EM_LEN], ()> {
+/// Writes the encoding of m_hash to em, whose length is the intended length
+/// of the encoded message.
+fn emsa_pkcs1v15_encode(
+  hash: Hash,
+  m_hash: &[u8],
+  em: &mut [u8],
+) -> Result<(), Error> {
+  // See [1], section 9.2
+  let em_len = em.len();
+  let prefix = hash.digest_info_prefix();
+
+  // 1. Apply the hash function to the message M to produce a hash
+  //    value H = Hash(M).
+  if m_hash.len() != hash.size() {
+    return Err(Error::Encoding);
+  }
+
+  // 2. Encode the algorithm ID for the hash function and the hash value
+  //    into an ASN.1 value of type DigestInfo. T is the DER encoding.
+  let t_len = prefix.len() + m_hash.len();
+
+  // 3. If emLen < tLen + 11, output "intended encoded message length too
+  //    short" and stop.
+  if em_len < t_len + 11 {
+    return Err(Error::Encoding);
+  }
+
+  // 4. Generate an octet string PS consisting of emLen - tLen - 3 octets
+  //    with hexadecimal value 0xff.
+  //
+  // 5. Concatenate PS, the DER encoding T, and other padding to form the
+  //    encoded message EM as
+  //
+  //      EM = 0x00 || 0x01 || PS || 0x00 || T.
+  em[0] = 0x00;
+  em[1] = 0x01;
+  em[2..em_len - t_len - 1].fill(0xFF);
+  em[em_len - t_len - 1] = 0x00;
+  em[em_len - t_len..em_len - m_hash.len()].copy_from_slice(prefix);
+  em[em_len - m_hash.len()..].copy_from_slice(m_hash);
+
+  Ok(())
+}
+
+/// Writes the encoding of m_hash to em, whose length must be
+/// ceil(em_bits / 8).
+fn emsa_pss_encode(
+  m_hash: &[u8],
+  salt: &[u8],
+  em_bits: usize,
+  em: &mut [u8],
+) -> Result<(), Error> {
   // See [1], section 9.1.1
   let h_len = 256 / 8;
   let s_len = salt.len();
@@ -44,19 +692,23 @@ fn emsa_pss_encode(m_hash: &[u8], salt: &[u8]) -> Result<[u8; EM_LEN], ()> {
   //
   // 2.  Let mHash = Hash(M), an octet string of length hLen.
   if m_hash.len() != h_len {
-    return Err(());
+    return Err(Error::Encoding);
+  }
+
+  let em_len = em.len();
+  if em_len != (em_bits + 7) / 8 {
+    return Err(Error::Encoding);
   }
 
   // 3. If em_len < h_len + s_len + 2, output "encoding error" and stop.
-  if EM_LEN < h_len + s_len + 2 {
-    // TODO: Key size too small
-    return Err(());
+  if em_len < h_len + s_len + 2 {
+    return Err(Error::Encoding);
   }
 
-  let mut em = [0; EM_LEN];
+  em.fill(0);
 
-  let (db, h) = em.split_at_mut(EM_LEN - h_len - 1);
-  let h = &mut h[..(EM_LEN - 1) - db.len()];
+  let (db, h) = em.split_at_mut(em_len - h_len - 1);
+  let h = &mut h[..(em_len - 1) - db.len()];
 
   // 4. Generate a random octet string salt of length s_len; if s_len = 0,
   //     then salt is the empty string.
@@ -70,7 +722,7 @@ fn emsa_pss_encode(m_hash: &[u8], salt: &[u8]) -> Result<[u8; EM_LEN], ()> {
   // 6.  Let H = Hash(M'), an octet string of length h_len.
   let prefix = [0u8; 8];
 
-  let mut hashed = Sha256::new()
+  let hashed = Sha256::new()
     .update(&prefix)
     .update(m_hash)
     .update(salt)
@@ -83,22 +735,144 @@ fn emsa_pss_encode(m_hash: &[u8], salt: &[u8]) -> Result<[u8; EM_LEN], ()> {
   //
   // 8.  Let DB = PS || 0x01 || salt; DB is an octet string of length
   //     emLen - hLen - 1.
-  db[EM_LEN - s_len - h_len - 2] = 0x01;
-  db[EM_LEN - s_len - h_len - 1..].copy_from_slice(salt);
+  db[em_len - s_len - h_len - 2] = 0x01;
+  db[em_len - s_len - h_len - 1..].copy_from_slice(salt);
 
   // 9.  Let dbMask = MGF(H, emLen - hLen - 1).
   //
   // 10. Let maskedDB = DB \xor dbMask.
-  mgf1_xor(db, &h);
+  mgf1_xor(db, h);
 
   // 11. Set the leftmost 8 * em_len - em_bits bits of the leftmost octet in
   //     maskedDB to zero.
-  db[0] &= 0xFF >> (8 * EM_LEN - EM_BITS);
+  db[0] &= 0xFF >> (8 * em_len - em_bits);
 
   // 12. Let EM = maskedDB || H || 0xbc.
-  em[EM_LEN - 1] = 0xBC;
+  em[em_len - 1] = 0xBC;
 
-  Ok(em)
+  Ok(())
+}
+
+/// em must be ceil(em_bits / 8) octets long.
+fn emsa_pss_verify(
+  m_hash: &[u8],
+  em: &mut [u8],
+  em_bits: usize,
+  s_len: usize,
+) -> Result<(), Error> {
+  // See [1], section 9.1.2
+  let h_len = 256 / 8;
+
+  // 1. If the length of M is greater than the input limitation for the
+  //    hash function (2^61 - 1 octets for SHA-1), output "inconsistent"
+  //    and stop.
+  //
+  // 2. Let mHash = Hash(M), an octet string of length hLen
+  if m_hash.len() != h_len {
+    return Err(Error::Verify);
+  }
+
+  let em_len = em.len();
+  if em_len != (em_bits + 7) / 8 {
+    return Err(Error::Verify);
+  }
+
+  // 3. If emLen < hLen + sLen + 2, output "inconsistent" and stop.
+  if em_len < h_len + s_len + 2 {
+    return Err(Error::Verify);
+  }
+
+  // 4. If the rightmost octet of EM does not have hexadecimal value
+  //    0xbc, output "inconsistent" and stop.
+  if em[em_len - 1] != 0xBC {
+    return Err(Error::Verify);
+  }
+
+  // 5. Let maskedDB be the leftmost emLen - hLen - 1 octets of EM, and
+  //    let H be the next hLen octets.
+  let (db, h) = em.split_at_mut(em_len - h_len - 1);
+  let h = &h[..h_len];
+
+  // 6. If the leftmost 8 * em_len - em_bits bits of the leftmost octet in
+  //    maskedDB are not all equal to zero, output "inconsistent" and
+  //    stop.
+  if db[0] & !(0xFF >> (8 * em_len - em_bits)) != 0 {
+    return Err(Error::Verify);
+  }
+
+  // 7. Let dbMask = MGF(H, em_len - h_len - 1)
+  //
+  // 8. Let DB = maskedDB \xor dbMask
+  mgf1_xor(db, h);
+
+  // 9.  Set the leftmost 8 * emLen - emBits bits of the leftmost octet in DB
+  //     to zero.
+  db[0] &= 0xFF >> (8 * em_len - em_bits);
+
+  // 10. If the emLen - hLen - sLen - 2 leftmost octets of DB are not zero
+  //     or if the octet at position emLen - hLen - sLen - 1 (the leftmost
+  //     position is "position 1") does not have hexadecimal value 0x01,
+  //     output "inconsistent" and stop.
+  let ps_len = em_len - h_len - s_len - 2;
+  if db[..ps_len].iter().any(|e| *e != 0) || db[ps_len] != 0x01 {
+    return Err(Error::Verify);
+  }
+
+  // 11. Let salt be the last s_len octets of DB.
+  let salt = &db[db.len() - s_len..];
+
+  // 12. Let
+  //          M' = (0x)00 00 00 00 00 00 00 00 || mHash || salt ;
+  //     M' is an octet string of length 8 + hLen + sLen with eight
+  //     initial zero octets.
+  //
+  // 13. Let H' = Hash(M'), an octet string of length hLen.
+  let prefix = [0u8; 8];
+
+  let h0 = Sha256::new()
+    .update(&prefix)
+    .update(m_hash)
+    .update(salt)
+    .finalize();
+
+  // 14. If H = H', output "consistent." Otherwise, output "inconsistent."
+  if h0[..].ct_eq(h).into() {
+    Ok(())
+  } else {
+    Err(Error::Verify)
+  }
+}
+
+/// Compares a and b with two independent passes in opposite directions and
+/// only reports equality if both passes agree and ran to completion. The
+/// intermediate results go through volatile memory so the compiler can not
+/// fold the passes together.
+fn redundant_eq(a: &[u8], b: &[u8]) -> bool {
+  if a.len() != b.len() {
+    return false;
+  }
+
+  let mut d1: u8 = 0;
+  let mut i1 = 0;
+  while i1 < a.len() {
+    d1 |= a[i1] ^ b[i1];
+    i1 += 1;
+  }
+
+  let mut d2: u8 = 0xFF;
+  let mut i2 = a.len();
+  while i2 > 0 {
+    i2 -= 1;
+    d2 &= !(a[i2] ^ b[i2]);
+  }
+
+  // d1 == 0x00 and d2 == 0xFF on a match.
+  let d1 = unsafe { core::ptr::read_volatile(&d1) };
+  let d2 = unsafe { core::ptr::read_volatile(&d2) };
+  let i1 = unsafe { core::ptr::read_volatile(&i1) };
+  let i2 = unsafe { core::ptr::read_volatile(&i2) };
+
+  (d1 as u16 | ((!d2 as u16) << 8)) == 0 && i1 == a.len() && i2 == 0
 }
 
 /// Mask generation function.
@@ -108,12 +882,7 @@ pub fn mgf1_xor(out: &mut [u8], seed: &[u8]) {
   let mut counter = [0u8; 4];
   let mut i = 0;
   while i < out.len() {
-    let mut digest_input = [0u8; 32 + 4];
-    digest_input[0..seed.len()].copy_from_slice(seed);
-    digest_input[seed.len()..].copy_from_slice(&counter);
-
-    let digest_output =
-      Sha256::new().update(digest_input.as_slice()).finalize();
+    let digest_output = Sha256::new().update(seed).update(&counter).finalize();
     let mut j = 0;
     loop {
       if j >= digest_output.len() || i >= out.len() {
@@ -138,6 +907,12 @@ fn inc_counter(counter: &mut [u8; 4]) {
   }
 }
 
+#[cfg(all(feature = "riscv-asm", target_arch = "riscv32"))]
+use crate::rv32::{add_mul_vvw, mul_add_www};
+
+/// z += x * y, returns the carry out of z. Portable version, replaced by
+/// the kernel in `rv32` on RISC-V when the `riscv-asm` feature is enabled.
+#[cfg(not(all(feature = "riscv-asm", target_arch = "riscv32")))]
 #[inline(always)]
 fn add_mul_vvw(z: &mut [LimbUInt], x: &[LimbUInt], y: LimbUInt) -> LimbUInt {
   let mut c = 0;
@@ -155,16 +930,18 @@ fn add_mul_vvw(z: &mut [LimbUInt], x: &[LimbUInt], y: LimbUInt) -> LimbUInt {
 fn add_ww(x: LimbUInt, y: LimbUInt, c: LimbUInt) -> (LimbUInt, LimbUInt) {
   let yc = y.wrapping_add(c);
   let z0 = x.wrapping_add(yc);
-  let z1 = if z0 < x || yc < y { 1 } else { 0 };
+  // No branches: the carry is derived from the comparison flags.
+  let z1 = (z0 < x) as LimbUInt | (yc < y) as LimbUInt;
 
   (z1, z0)
 }
 
 /// z1 << _W + z0 = x * y + c
+#[cfg(not(all(feature = "riscv-asm", target_arch = "riscv32")))]
 #[inline(always)]
 fn mul_add_www(x: LimbUInt, y: LimbUInt, c: LimbUInt) -> (LimbUInt, LimbUInt) {
-  let z: u64 = x as u64 * y as u64 + c as u64;
-  ((z >> Limb::BIT_SIZE) as u32, z as u32)
+  let z = x as WideLimbUInt * y as WideLimbUInt + c as WideLimbUInt;
+  ((z >> Limb::BIT_SIZE) as LimbUInt, z as LimbUInt)
 }
 
 /// The resulting carry c is either 0 or 1.
@@ -181,298 +958,1842 @@ fn sub_vv(z: &mut [LimbUInt], x: &[LimbUInt], y: &[LimbUInt]) -> LimbUInt {
   c
 }
 
-/// Computes z mod m = x * y * 2 ** (-n*_W) mod m
+#[cfg(test)]
+thread_local! {
+  /// Montgomery multiplications done on this thread, so that tests can
+  /// check that their number does not depend on secrets.
+  static MONT_OPS: core::cell::Cell<usize> = core::cell::Cell::new(0);
+}
+
+/// Computes x = x * y * 2 ** (-n*_W) mod m in place, with n = m.len(),
 /// assuming k = -1/m mod 2**_W
 /// See Gueron, "Efficient Software Implementations of Modular Exponentiation".
 /// https://eprint.iacr.org/2011/239.pdf
 /// In the terminology of that paper, this is an "Almost Montgomery Multiplication":
 /// x and y are required to satisfy 0 <= z < 2**(n*_W) and then the result
 /// z is guaranteed to satisfy 0 <= z < 2**(n*_W), but it may not be < m.
-fn montgomery(x: &U4096, y: &U4096, m: &U4096, k: LimbUInt, n: usize) -> U4096 {
-  // This code assumes x, y, m are all the same length, n.
-  // (required by addMulVVW and the for loop).
-  // It also assumes that x, y are already reduced mod m,
-  // or else the result will not be properly reduced.
-
-  let mut z = [0 as LimbUInt; (4096 / Limb::BIT_SIZE) * 2];
-
-  // k = 17616413863366944509
-  // n = 64
-  // z = 0 (128)
-  // x = 1 (64)
-  // y = 8860687789152922376 (64)
-  // m = 6151168719874890155 (64)
-  let x_data = x.to_uint_array();
-  let y_data = y.to_uint_array();
-  let m_data = m.to_uint_array();
+///
+/// t is scratch space of at least 2n limbs.
+fn mont_mul(
+  x: &mut [LimbUInt],
+  y: &[LimbUInt],
+  m: &[LimbUInt],
+  k: LimbUInt,
+  t: &mut [LimbUInt],
+) {
+  #[cfg(test)]
+  MONT_OPS.with(|ops| ops.set(ops.get() + 1));
+
+  let n = m.len();
+  let t = &mut t[..n * 2];
+  t[..n].fill(0);
 
   let mut c: LimbUInt = 0;
   for i in 0..n {
-    let c2 = add_mul_vvw(&mut z[i..n + i], &x_data, y_data[i]);
+    let c2 = add_mul_vvw(&mut t[i..n + i], x, y[i]);
 
-    let t = z[i].wrapping_mul(k);
-    let c3 = add_mul_vvw(&mut z[i..n + i], &m_data, t);
+    let u = t[i].wrapping_mul(k);
+    let c3 = add_mul_vvw(&mut t[i..n + i], m, u);
 
     let cx = c.wrapping_add(c2);
     let cy = cx.wrapping_add(c3);
-    z[n + i] = cy;
+    t[n + i] = cy;
 
-    if cx < c2 || cy < c3 {
-      c = 1;
-    } else {
-      c = 0;
-    }
+    c = (cx < c2) as LimbUInt | (cy < c3) as LimbUInt;
   }
 
-  if c == 0 {
-    let mut a = [0 as LimbUInt; (4096 / Limb::BIT_SIZE)];
-    a.copy_from_slice(&z[n..]);
+  mont_finish(x, &t[n..], m, c);
+}
 
-    U4096::from_uint_array(a)
-  } else {
-    {
-      let (mut first, second) = z.split_at_mut(n);
-      sub_vv(&mut first, &second, &m_data);
+/// Computes x = x * x * 2 ** (-n*_W) mod m in place, like `mont_mul`.
+/// The square is computed first, using each cross product x[i] * x[j]
+/// only once, and then reduced.
+fn mont_sqr(
+  x: &mut [LimbUInt],
+  m: &[LimbUInt],
+  k: LimbUInt,
+  t: &mut [LimbUInt],
+) {
+  #[cfg(test)]
+  MONT_OPS.with(|ops| ops.set(ops.get() + 1));
+
+  let n = m.len();
+  let t = &mut t[..n * 2];
+  sqr_vv(t, x);
+
+  let c = mont_redc(t, m, k);
+  mont_finish(x, &t[n..], m, c);
+}
+
+/// Computes x = x * 2 ** (-n*_W) mod m in place, i.e. converts x out of the
+/// Montgomery domain. Same as `mont_mul` with y = 1.
+fn mont_from(
+  x: &mut [LimbUInt],
+  m: &[LimbUInt],
+  k: LimbUInt,
+  t: &mut [LimbUInt],
+) {
+  let n = m.len();
+  let t = &mut t[..n * 2];
+  t[..n].copy_from_slice(x);
+  t[n..].fill(0);
+
+  let c = mont_redc(t, m, k);
+  mont_finish(x, &t[n..], m, c);
+}
+
+/// Montgomery reduction of the 2n limb value t. Leaves the result in the
+/// upper half of t and returns the carry out of it.
+#[inline(always)]
+fn mont_redc(t: &mut [LimbUInt], m: &[LimbUInt], k: LimbUInt) -> LimbUInt {
+  let n = m.len();
+  let mut c: LimbUInt = 0;
+  for i in 0..n {
+    let u = t[i].wrapping_mul(k);
+    let c2 = add_mul_vvw(&mut t[i..n + i], m, u);
+    let (c3, z) = add_ww(t[n + i], c2, c);
+    t[n + i] = z;
+    c = c3;
+  }
+
+  c
+}
+
+/// x = z - m if c is set, otherwise x = z. Always computes z - m, so the
+/// same work is done whether or not the carry was set.
+#[inline(always)]
+fn mont_finish(
+  x: &mut [LimbUInt],
+  z: &[LimbUInt],
+  m: &[LimbUInt],
+  c: LimbUInt,
+) {
+  sub_vv(x, z, m);
+
+  let c = Choice::from(c as u8);
+  for (xi, zi) in x.iter_mut().zip(z.iter()) {
+    *xi = LimbUInt::conditional_select(zi, xi, c);
+  }
+}
+
+/// z = x * x, where z is twice as long as x.
+fn sqr_vv(z: &mut [LimbUInt], x: &[LimbUInt]) {
+  let n = x.len();
+  z.fill(0);
+
+  // Cross products x[i] * x[j] for i < j.
+  for i in 0..n {
+    z[n + i] = add_mul_vvw(&mut z[2 * i + 1..n + i], &x[i + 1..], x[i]);
+  }
+
+  // Double them.
+  let mut c = 0;
+  for zi in z.iter_mut() {
+    let top = *zi >> (Limb::BIT_SIZE - 1);
+    *zi = (*zi << 1) | c;
+    c = top;
+  }
+
+  // And add the squares x[i] * x[i].
+  let mut c = 0;
+  for i in 0..n {
+    let (hi, lo) = mul_add_www(x[i], x[i], 0);
+    let (c1, z0) = add_ww(z[2 * i], lo, c);
+    let (c2, z1) = add_ww(z[2 * i + 1], hi, c1);
+    z[2 * i] = z0;
+    z[2 * i + 1] = z1;
+    c = c2;
+  }
+}
+
+/// z - m if z >= m, otherwise z. Does not branch on z.
+fn reduce_once<const L: usize>(z: &UInt<L>, m: &UInt<L>) -> UInt<L> {
+  let (t, borrow) = z.sbb(m, Limb::ZERO);
+  // borrow is all ones when z < m.
+  UInt::conditional_select(&t, z, Choice::from((borrow.0 & 1) as u8))
+}
+
+/// out = powers[w], reading every entry of the table so the memory access
+/// pattern does not depend on w.
+fn select_power<const L: usize>(
+  out: &mut [LimbUInt],
+  powers: &[[LimbUInt; L]],
+  w: LimbUInt,
+) {
+  for (i, p) in powers.iter().enumerate() {
+    let c = (i as LimbUInt).ct_eq(&w);
+    for (o, pi) in out.iter_mut().zip(p.iter()) {
+      o.conditional_assign(pi, c);
     }
-    let mut a = [0 as LimbUInt; (4096 / Limb::BIT_SIZE)];
-    a.copy_from_slice(&z[..n]);
-    U4096::from_uint_array(a)
   }
 }
 
-struct MontyReducer {
-  n0inv: LimbUInt,
+/// Montgomery context of an odd modulus. Computing R**2 mod m is
+/// comparatively slow, so this is built once per key and kept with it.
+/// Neither Copy nor Debug, as for the CRT halves the modulus is a secret
+/// prime.
+#[derive(Clone)]
+pub struct MontgomeryParams<const L: usize> {
+  pub modulus: UInt<L>,
+  /// R**2 mod modulus, where R = 2**(L * BIT_SIZE).
+  pub rr: UInt<L>,
+  /// -modulus**-1 mod 2**BIT_SIZE
+  pub n0inv: LimbUInt,
 }
 
 // k0 = -m**-1 mod 2**BITS. Algorithm from: Dumas, J.G. "On Newton–Raphson
 // Iteration for Multiplicative Inverses Modulo Prime Powers".
 fn inv_mod_alt(b: LimbUInt) -> LimbUInt {
   assert_ne!(b & 1, 0);
-  // -11030582649679118445
-  let mut k0 = 2 - b as i64;
-  // 6151168719874890155
-  let mut t = (b - 1) as i64;
+  let mut k0 = (2 as LimbUInt).wrapping_sub(b);
+  let mut t = b - 1;
   let mut i = 1;
-  while i < 64 {
+  while i < Limb::BIT_SIZE {
     t = t.wrapping_mul(t);
-    k0 = k0.wrapping_mul(t + 1);
+    k0 = k0.wrapping_mul(t.wrapping_add(1));
 
     i <<= 1;
   }
-  -k0 as LimbUInt
+  k0.wrapping_neg()
 }
 
-impl MontyReducer {
-  fn new(n: &BigUint) -> Self {
-    // 11030582649679118447
-    let n0inv = inv_mod_alt(n.get_limb(0));
-    MontyReducer { n0inv }
+impl<const L: usize> Zeroize for MontgomeryParams<L> {
+  fn zeroize(&mut self) {
+    self.modulus.zeroize();
+    self.rr.zeroize();
+    self.n0inv.zeroize();
   }
 }
 
-const LIMBS: usize = 4096 / Limb::BIT_SIZE;
+impl<const L: usize> MontgomeryParams<L> {
+  /// Returns None unless m is odd and greater than one.
+  pub fn new(m: &UInt<L>) -> Option<Self> {
+    if !bool::from(m.is_odd()) || *m == UInt::ONE {
+      return None;
+    }
 
-#[inline(always)]
-pub fn shl_u64(s: U4096) -> U4096 {
-  const n: u64 = 2 * LIMBS as u64 * Limb::BIT_SIZE as u64;
+    // 1 doubled 2 * L * BIT_SIZE times modulo m.
+    let mut rr = UInt::<L>::ONE;
+    for _ in 0..2 * L * Limb::BIT_SIZE {
+      rr = double_mod(&rr, m);
+    }
+
+    Some(MontgomeryParams {
+      modulus: *m,
+      rr,
+      n0inv: inv_mod_alt(m.to_uint_array()[0]),
+    })
+  }
+}
+
+/// 2 * x mod m for x < m.
+fn double_mod<const L: usize>(x: &UInt<L>, m: &UInt<L>) -> UInt<L> {
+  let (y, carry) = x.adc(x, Limb::ZERO);
+  let (t, borrow) = y.sbb(m, Limb::ZERO);
+  // 2 * x >= m if the doubling carried out or the subtraction did not
+  // borrow.
+  let ge =
+    Choice::from((carry.0 & 1) as u8) | !Choice::from((borrow.0 & 1) as u8);
+  UInt::conditional_select(&y, &t, ge)
+}
+
+/// Reads a big-endian integer of up to L limbs. Unlike
+/// `UInt::from_be_slice` the input may be shorter than the integer.
+pub fn uint_from_be<const L: usize>(bytes: &[u8]) -> UInt<L> {
+  let mut limbs = [0 as LimbUInt; L];
+  for (i, b) in bytes.iter().rev().enumerate().take(L * Limb::BYTE_SIZE) {
+    limbs[i / Limb::BYTE_SIZE] |=
+      (*b as LimbUInt) << (8 * (i % Limb::BYTE_SIZE));
+  }
+
+  UInt::from_uint_array(limbs)
+}
+
+/// Writes x big-endian to out, left padded with zeros. High limbs that do
+/// not fit are dropped.
+pub fn uint_to_be<const L: usize>(x: &UInt<L>, out: &mut [u8]) {
+  let limbs = x.to_uint_array();
+  for (i, b) in out.iter_mut().rev().enumerate() {
+    *b = if i < L * Limb::BYTE_SIZE {
+      (limbs[i / Limb::BYTE_SIZE] >> (8 * (i % Limb::BYTE_SIZE))) as u8
+    } else {
+      0
+    };
+  }
+}
+
+/// Performs raw RSA encryption with no padding, i.e. base^e mod modulus.
+/// Only used with public values, so this is not constant time.
+pub fn encrypt<const L: usize>(
+  base: &UInt<L>,
+  e: LimbUInt,
+  mp: &MontgomeryParams<L>,
+) -> UInt<L> {
+  if e == 0 {
+    return UInt::ONE;
+  }
+
+  let m = mp.modulus.to_uint_array();
+  let mut t = [0 as LimbUInt; MAX_LIMBS * 2];
+
+  let mut x = base.to_uint_array();
+  mont_mul(&mut x, &mp.rr.to_uint_array(), &m, mp.n0inv, &mut t);
+
+  // left-to-right binary exponentiation, e is small and public.
+  let mut z = x;
+  let bits = Limb::BIT_SIZE - e.leading_zeros() as usize;
+  for i in (0..bits - 1).rev() {
+    mont_sqr(&mut z, &m, mp.n0inv, &mut t);
+    if (e >> i) & 1 == 1 {
+      mont_mul(&mut z, &x, &m, mp.n0inv, &mut t);
+    }
+  }
+
+  // convert to regular number
+  mont_from(&mut z, &m, mp.n0inv, &mut t);
+  reduce_once(&UInt::from_uint_array(z), &mp.modulus)
+}
 
-  const bits: u64 = Limb::BIT_SIZE as u64;
-  const digits: usize = (n / bits) as usize;
-  const shift: u8 = (n % bits) as u8;
+/// x * y mod m.
+fn mul_mod<const L: usize>(
+  x: &UInt<L>,
+  y: &UInt<L>,
+  mp: &MontgomeryParams<L>,
+) -> UInt<L> {
+  let m = mp.modulus.to_uint_array();
+  let mut t = [0 as LimbUInt; MAX_LIMBS * 2];
+
+  // (x * y / R) * R**2 / R = x * y
+  let mut z = x.to_uint_array();
+  mont_mul(&mut z, &y.to_uint_array(), &m, mp.n0inv, &mut t);
+  mont_mul(&mut z, &mp.rr.to_uint_array(), &m, mp.n0inv, &mut t);
+  reduce_once(&UInt::from_uint_array(z), &mp.modulus)
+}
+
+/// a**-1 mod m for an odd modulus m, using the binary extended Euclidean
+/// algorithm. Always runs 2 * bits iterations and does not branch on a.
+/// Returns None if a is not invertible.
+pub fn inv_mod<const L: usize>(a: &UInt<L>, m: &UInt<L>) -> Option<UInt<L>> {
+  // Invariants: u * a0 = a and v * a0 = b (mod m), b is odd.
+  let mut a = *a;
+  let mut b = *m;
+  let mut u = UInt::<L>::ONE;
+  let mut v = UInt::<L>::ZERO;
+
+  // (m + 1) / 2, used to halve odd values mod m.
+  let half = m.shr_vartime(1).wrapping_add(&UInt::ONE);
+
+  for _ in 0..2 * L * Limb::BIT_SIZE {
+    let odd = a.is_odd();
+
+    // If a is odd, a = a - b. When a < b, swap first so that it stays
+    // positive: a, b = b - a, a.
+    let (diff, borrow) = a.sbb(&b, Limb::ZERO);
+    let swap = odd & Choice::from((borrow.0 & 1) as u8);
+    let neg = UInt::<L>::ZERO.wrapping_sub(&diff);
+
+    b = UInt::conditional_select(&b, &a, swap);
+    a = UInt::conditional_select(
+      &a,
+      &UInt::conditional_select(&diff, &neg, swap),
+      odd,
+    );
+
+    let u_sub = u.sub_mod(&v, m);
+    let v_sub = v.sub_mod(&u, m);
+    v = UInt::conditional_select(&v, &u, swap);
+    u = UInt::conditional_select(
+      &u,
+      &UInt::conditional_select(&u_sub, &v_sub, swap),
+      odd,
+    );
+
+    // a is now even: a = a / 2, u = u / 2 mod m.
+    a = a.shr_vartime(1);
+    let u_odd = u.is_odd();
+    u = u.shr_vartime(1);
+    u = UInt::conditional_select(&u, &u.wrapping_add(&half), u_odd);
+  }
 
-  let mut limbs = [0 as LimbUInt; LIMBS];
-  limbs.copy_from_slice(&s.to_uint_array()[..]);
+  // b = gcd(a0, m)
+  if b == UInt::ONE {
+    Some(v)
+  } else {
+    None
+  }
+}
 
-  if shift > 0 {
-    let mut carry = 0;
-    let carry_shift = Limb::BIT_SIZE as u8 - shift;
-    for elem in limbs[digits..].iter_mut() {
-      let new_carry = *elem >> carry_shift;
-      *elem = (*elem << shift) | carry;
-      carry = new_carry;
+/// r = 2 * r + bit, minus d if that is at least d, for r < d. Returns the
+/// new r and 1 if d was subtracted, 0 otherwise, without branching.
+fn div_step(r: LimbUInt, bit: LimbUInt, d: LimbUInt) -> (LimbUInt, LimbUInt) {
+  let y = (r as WideLimbUInt) << 1 | bit as WideLimbUInt;
+  // y < 2 * d, so this borrows, and sets the top bit, only if y < d.
+  let t = y.wrapping_sub(d as WideLimbUInt);
+  let q = 1 - (t >> (2 * Limb::BIT_SIZE - 1)) as LimbUInt;
+  let mask = (q as WideLimbUInt).wrapping_neg();
+  ((y - (d as WideLimbUInt & mask)) as LimbUInt, q)
+}
+
+/// x mod d for a single limb d. Shifts x in one bit at a time like `rem`,
+/// as `%` on WideLimbUInt is a variable-time library call on RV32 and x
+/// is often a secret prime candidate.
+pub fn rem_limb(x: &[LimbUInt], d: LimbUInt) -> LimbUInt {
+  let mut r = 0;
+  for xi in x.iter().rev() {
+    for j in (0..Limb::BIT_SIZE).rev() {
+      r = div_step(r, (xi >> j) & 1, d).0;
     }
-    if carry != 0 {
-      limbs[limbs.len() - 1] = carry;
+  }
+
+  r
+}
+
+/// x = x / d in place for a single limb d, returns the remainder. Constant
+/// time like `rem_limb`.
+fn div_limb(x: &mut [LimbUInt], d: LimbUInt) -> LimbUInt {
+  let mut r = 0;
+  for xi in x.iter_mut().rev() {
+    let mut q = 0;
+    for j in (0..Limb::BIT_SIZE).rev() {
+      let (s, b) = div_step(r, (*xi >> j) & 1, d);
+      r = s;
+      q |= b << j;
     }
+    *xi = q;
+  }
+
+  r
+}
+
+/// x * y mod m for single limb values, reduced with `rem_limb`.
+fn mul_mod_limb(x: LimbUInt, y: LimbUInt, m: LimbUInt) -> LimbUInt {
+  let z = x as WideLimbUInt * y as WideLimbUInt;
+  rem_limb(&[z as LimbUInt, (z >> Limb::BIT_SIZE) as LimbUInt], m)
+}
+
+/// x**y mod m for single limb values. Runs in time independent of x, but
+/// not of y.
+fn pow_mod_limb(x: LimbUInt, mut y: LimbUInt, m: LimbUInt) -> LimbUInt {
+  let mut x = rem_limb(&[x], m);
+  let mut z = rem_limb(&[1], m);
+  while y > 0 {
+    if y & 1 == 1 {
+      z = mul_mod_limb(z, x, m);
+    }
+    x = mul_mod_limb(x, x, m);
+    y >>= 1;
+  }
+
+  z
+}
+
+/// n / p for n = p * q with p odd and q < 2**(H * BIT_SIZE), computed as
+/// n * p**-1 mod 2**(H * BIT_SIZE) in constant time, unlike a division.
+pub fn cofactor<const L: usize, const H: usize>(
+  n: &UInt<L>,
+  p: &UInt<H>,
+) -> UInt<H> {
+  // Each step of x = x * (2 - p * x) doubles the low bits in which x is
+  // p**-1. An odd p is its own inverse mod 8, so x = p starts with three.
+  let mut x = *p;
+  let mut bits = 3;
+  while bits < H * Limb::BIT_SIZE {
+    x = x.wrapping_mul(&UInt::from_u8(2).wrapping_sub(&p.wrapping_mul(&x)));
+    bits *= 2;
   }
 
-  U4096::from_uint_array(limbs)
+  narrow::<L, H>(n).wrapping_mul(&x)
 }
 
-/// Performs raw RSA decryption with no padding, resulting in a plaintext `BigUint`.
+/// PUBLIC_EXPONENT**-1 mod m, or None if m is a multiple of it. m may be
+/// even. With e prime, this is d = (1 + j * m) / e for j = -m**-1 mod e,
+/// which is an exact division and gives d < m.
+fn inv_public_exponent<const M: usize>(m: &UInt<M>) -> Option<UInt<M>> {
+  let e = PUBLIC_EXPONENT;
+  let m = m.to_uint_array();
+
+  let r = rem_limb(&m, e);
+  if r == 0 {
+    return None;
+  }
+  // m**-1 mod e by Fermat's little theorem.
+  let j = e - pow_mod_limb(r, e - 2, e);
+
+  // 1 + j * m, one limb longer than m.
+  let mut t = [0 as LimbUInt; MAX_LIMBS + 1];
+  t[0] = 1;
+  t[M] = add_mul_vvw(&mut t[..M], &m, j);
+  div_limb(&mut t[..M + 1], e);
+
+  let mut d = [0 as LimbUInt; M];
+  d.copy_from_slice(&t[..M]);
+  Some(UInt::from_uint_array(d))
+}
+
+/// A random blinding factor r in [1, n) and its inverse mod n.
+fn blinding_pair<R: RngCore + CryptoRng, const L: usize>(
+  rng: &mut R,
+  n: &UInt<L>,
+) -> (UInt<L>, UInt<L>) {
+  loop {
+    // One request for all limbs, which matters for an RNG that reseeds on
+    // every request.
+    let mut bytes = [0u8; MAX_LIMBS * Limb::BYTE_SIZE];
+    let bytes = &mut bytes[..L * Limb::BYTE_SIZE];
+    rng.fill_bytes(bytes);
+    let mut limbs = [0 as LimbUInt; L];
+    for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks(Limb::BYTE_SIZE)) {
+      let mut le = [0u8; Limb::BYTE_SIZE];
+      le.copy_from_slice(chunk);
+      *limb = LimbUInt::from_le_bytes(le);
+    }
+    bytes.zeroize();
+
+    // r < 2**(bits(n) - 1) <= n
+    let r = UInt::<L>::from_uint_array(limbs)
+      .shr_vartime(L * Limb::BIT_SIZE - n.bits() + 1);
+    if let Some(r_inv) = inv_mod(&r, n) {
+      return (r, r_inv);
+    }
+  }
+}
+
+/// Exponent bits handled per multiplication in `monty_modpow`. The power
+/// table has 2**WINDOW entries of L limbs, 4 KiB at 4096 bits. A window of
+/// 4 takes a quarter fewer multiplications but twice the table.
+const WINDOW: usize = 3;
+
+/// Bits [i, i + WINDOW) of y, with zeros above the end of y. Which limbs
+/// are read depends only on i.
+fn window(y: &[LimbUInt], i: usize) -> LimbUInt {
+  let (l, s) = (i / Limb::BIT_SIZE, i % Limb::BIT_SIZE);
+  let mut w = y[l] >> s;
+  if s + WINDOW > Limb::BIT_SIZE && l + 1 < y.len() {
+    w |= y[l + 1] << (Limb::BIT_SIZE - s);
+  }
+  w & ((1 << WINDOW) - 1)
+}
+
+/// x**y mod m for an odd modulus m, using WINDOW-bit windows of Montgomery
+/// multiplications.
+fn monty_modpow<const L: usize>(
+  x: &UInt<L>,
+  y: &[LimbUInt],
+  mp: &MontgomeryParams<L>,
+) -> UInt<L> {
+  let m = mp.modulus.to_uint_array();
+  let k = mp.n0inv;
+  let mut t = [0 as LimbUInt; MAX_LIMBS * 2];
+
+  // powers[i] contains x^i in the Montgomery domain. Even powers are
+  // squares of smaller ones.
+  let mut powers = [[0 as LimbUInt; L]; 1 << WINDOW];
+  powers[0][0] = 1;
+  mont_mul(&mut powers[0], &mp.rr.to_uint_array(), &m, k, &mut t);
+  powers[1] = x.to_uint_array();
+  mont_mul(&mut powers[1], &mp.rr.to_uint_array(), &m, k, &mut t);
+  for i in 2..1 << WINDOW {
+    let (lo, hi) = powers.split_at_mut(i);
+    if i % 2 == 0 {
+      hi[0] = lo[i / 2];
+      mont_sqr(&mut hi[0], &m, k, &mut t);
+    } else {
+      hi[0] = lo[i - 1];
+      mont_mul(&mut hi[0], &lo[1], &m, k, &mut t);
+    }
+  }
+
+  // initialize z = 1 (Montgomery 1)
+  let mut z = powers[0];
+  let mut w = [0 as LimbUInt; L];
+
+  // Windows from the top, the first one padded with zeros above y.
+  let bits = y.len() * Limb::BIT_SIZE;
+  let mut i = (bits + WINDOW - 1) / WINDOW * WINDOW;
+  while i > 0 {
+    if i < bits {
+      for _ in 0..WINDOW {
+        mont_sqr(&mut z, &m, k, &mut t);
+      }
+    }
+    i -= WINDOW;
+    select_power(&mut w, &powers, window(y, i));
+    mont_mul(&mut z, &w, &m, k, &mut t);
+  }
+
+  // convert to regular number
+  mont_from(&mut z, &m, k, &mut t);
+  // One last reduction, just in case.
+  // See golang.org/issue/13907.
+  // z = (z + t * m) / R < (R + R * m) / R = m + 1, so a single
+  // conditional subtraction is always enough.
+  reduce_once(&UInt::from_uint_array(z), &mp.modulus)
+}
+
+/// Performs raw RSA decryption with no padding, i.e. base^exp mod modulus.
 #[inline]
-pub fn decrypt(
-  base: &U4096,
-  exp_data: &[LimbUInt; 4096 / Limb::BIT_SIZE],
-  modulus: &U4096,
-) -> U4096 {
-  // if odd, monty_modpow
-  if modulus.is_odd().into() {
-    // x, exponent, modulus
-    let x = base;
-    let y = exp_data;
-    // 0: 11030582649679118447
-    let m = modulus;
-    let m_bytes = m.to_be_byte_array();
-    let mn = BigUint::from_bytes_be(&m_bytes);
-
-    // n0inv: 17616413863366944509
-    let mr = MontyReducer::new(&mn);
-
-    // 64
-    let num_words: usize = LIMBS as usize;
-    let mut rr = BigUint::from(1u8);
-    rr = (rr.shl(2 * num_words * Limb::BIT_SIZE)) % mn;
-
-    let mut rem_b = rr.to_bytes_be();
-    rem_b.resize(512, 0);
-    let rr = U4096::from_be_byte_array(
-      *crypto_bigint::generic_array::GenericArray::from_slice(&rem_b),
+pub fn decrypt<const L: usize>(
+  base: &UInt<L>,
+  exp_data: &[LimbUInt],
+  mp: &MontgomeryParams<L>,
+) -> UInt<L> {
+  monty_modpow(base, exp_data, mp)
+}
+
+/// Zero extends an H limb integer to L limbs.
+fn widen<const H: usize, const L: usize>(x: &UInt<H>) -> UInt<L> {
+  let mut limbs = [0 as LimbUInt; L];
+  limbs[..H].copy_from_slice(&x.to_uint_array());
+  UInt::from_uint_array(limbs)
+}
+
+/// The low H limbs of x.
+fn narrow<const L: usize, const H: usize>(x: &UInt<L>) -> UInt<H> {
+  let mut limbs = [0 as LimbUInt; H];
+  limbs.copy_from_slice(&x.to_uint_array()[..H]);
+  UInt::from_uint_array(limbs)
+}
+
+/// hi * 2**(H * BIT_SIZE) + lo, for L = 2 * H. `Concat` is only implemented
+/// for the fixed size aliases.
+fn concat<const H: usize, const L: usize>(
+  lo: &UInt<H>,
+  hi: &UInt<H>,
+) -> UInt<L> {
+  let mut limbs = [0 as LimbUInt; L];
+  limbs[..H].copy_from_slice(&lo.to_uint_array());
+  limbs[H..].copy_from_slice(&hi.to_uint_array());
+  UInt::from_uint_array(limbs)
+}
+
+/// x mod m, shifting x in one bit at a time. Always runs L * BIT_SIZE
+/// steps and does not branch on x or m, unlike crypto-bigint's
+/// `wrapping_rem` whose running time depends on their bit lengths.
+fn rem<const L: usize>(x: &UInt<L>, m: &UInt<L>) -> UInt<L> {
+  let x = x.to_uint_array();
+  let mut r = UInt::<L>::ZERO;
+  for i in (0..L * Limb::BIT_SIZE).rev() {
+    // r = 2 * r + bit, minus m if that is at least m. r < m before, so one
+    // subtraction is enough.
+    let bit = (x[i / Limb::BIT_SIZE] >> (i % Limb::BIT_SIZE)) & 1;
+    let (y, carry) = r.adc(&r, Limb::ZERO);
+    let mut y = y.to_uint_array();
+    y[0] |= bit;
+    let y = UInt::from_uint_array(y);
+    let (t, borrow) = y.sbb(m, Limb::ZERO);
+    let ge =
+      Choice::from((carry.0 & 1) as u8) | !Choice::from((borrow.0 & 1) as u8);
+    r = UInt::conditional_select(&y, &t, ge);
+  }
+
+  r
+}
+
+/// x mod p for a p of half the size of x.
+fn reduce_half<const L: usize, const H: usize>(
+  x: &UInt<L>,
+  p: &UInt<H>,
+) -> UInt<H> {
+  narrow(&rem(x, &widen(p)))
+}
+
+/// y = d + k * f, for a multiple f of the order of the group the
+/// exponent is used in. y must be BLIND_LIMBS limbs longer than f, and d
+/// no longer than f.
+fn blind_exponent(y: &mut [LimbUInt], d: &[LimbUInt], f: &[LimbUInt], k: u64) {
+  let k = UInt::<BLIND_LIMBS>::from_u64(k).to_uint_array();
+  let n = f.len();
+  y.fill(0);
+  y[..d.len()].copy_from_slice(d);
+  for (j, kj) in k.iter().enumerate() {
+    y[n + j] = add_mul_vvw(&mut y[j..n + j], f, *kj);
+  }
+}
+
+/// Performs raw RSA decryption using the Chinese remainder theorem: two
+/// half size exponentiations recombined with Garner's formula.
+/// See [1], section 5.1.2.
+///
+/// k blinds the exponents, which are replaced by dP + k * (p - 1) and
+/// dQ + k * (q - 1). The result is the same for any k.
+pub fn decrypt_crt<const L: usize, const H: usize>(
+  c: &UInt<L>,
+  crt: &CrtParams<H>,
+  k: u64,
+) -> UInt<L> {
+  debug_assert_eq!(L, 2 * H);
+  let mut y = [0 as LimbUInt; MAX_LIMBS / 2 + BLIND_LIMBS];
+  let y = &mut y[..H + BLIND_LIMBS];
+
+  // m_1 = c^dP mod p
+  let p1 = crt.p.wrapping_sub(&UInt::ONE).to_uint_array();
+  blind_exponent(y, &crt.dp.to_uint_array(), &p1, k);
+  let m1 = monty_modpow(&reduce_half(c, &crt.p), y, &crt.monty_p);
+  // m_2 = c^dQ mod q
+  let q1 = crt.q.wrapping_sub(&UInt::ONE).to_uint_array();
+  blind_exponent(y, &crt.dq.to_uint_array(), &q1, k);
+  let m2 = monty_modpow(&reduce_half(c, &crt.q), y, &crt.monty_q);
+
+  // h = (m_1 - m_2) * qInv mod p
+  let diff = m1.sub_mod(&rem(&m2, &crt.p), &crt.p);
+  let (lo, hi) = diff.mul_wide(&crt.qinv);
+  let h = reduce_half(&concat::<H, L>(&lo, &hi), &crt.p);
+
+  // m = m_2 + q * h
+  let (lo, hi) = h.mul_wide(&crt.q);
+  concat::<H, L>(&lo, &hi).wrapping_add(&widen(&m2))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::hex;
+  use rand_core::RngCore;
+  use rand_core::SeedableRng;
+  use rand_hc::Hc128Rng;
+
+  const LIMBS: usize = 4096 / Limb::BIT_SIZE;
+  const EM_LEN: usize = 4096 / 8;
+  type U4096 = UInt<LIMBS>;
+  type U2048 = UInt<{ LIMBS / 2 }>;
+
+  fn uint<const L: usize>(s: &str) -> UInt<L> {
+    uint_from_be(&hex(s))
+  }
+
+  fn half(s: &str) -> U2048 {
+    uint(s)
+  }
+
+  // A 4096-bit key from Python's cryptography, with p < q.
+  const N: &str = concat!(
+    "d82b5312afb28aba186fbc6f8d910aac9f9a879f24ba61ae0319498c73354dd4",
+    "ea3df56362f4381bda22ccc0ab7d15c0f1e21267b988ef599c50f31bc47be4c1",
+    "97556e8378b22643f2e8078ce8282f19541e876157328f84786a3f4c1db86c78",
+    "e98a56ede8444736a9545b2ac97926a130fb2cfad6f5752fdae83953f8bafa56",
+    "a84bf7d9280732a9fdf2e7d4daca47cf5ff93f67606673e02fc4459818d35fd0",
+    "ae4f3d5b2ba7c9ea70e7b74e6f8e51503664477a9a26138aa864a9e2224e5e87",
+    "1864d26c2ad3377dd2e01e2fb1cc273edeea86430c3c4e0e8457a61d7d7f6d0f",
+    "68da0c7ecef101c09057e3807e81a04426b9d33186182c41ead38f226feb1adc",
+    "2ca391b52ec7511597ef9c1f1e5b2b6f995d9950a8f88eda172892aac8d6bd51",
+    "f2a8530f463bdd9d85583f6cd9325a8cc8c4b88162004c49f006ded4f75ec779",
+    "83df903fea4e548cf4424d76f17d1aac3fb78f640b4b7cb492564b547257f058",
+    "523c1ed06eda7624dd95759d4139944856ffb93dc240ad35e8a00a07e579e3a6",
+    "f64cb39797eac36add2d877208421ff0fc91d5c260246221fd74456bdbd9f41a",
+    "33a4a7ceca4a88051af141a4d9000dfee3d4b5f4a748000efd10e40ca3300d63",
+    "e063693d9f7125381dfcd289807b26d3b22cdfc119e557427dca74e9690b864f",
+    "5d9115e1b434681dfb9bca8099902158ebe03a85959c3f6b0221756da7b6b8c5",
+  );
+  const D: &str = concat!(
+    "04112499f8664287e7c7ca62cae60cae5cbe5a88daf70ab96c638067bd0f26ac",
+    "b7cf7c9b6714332e0af796a495556020662c9bae09e692a23bf7a5eb42d215e6",
+    "b2832ff617423ac8127cdd0f809aa8f0300df6fc14d8e5ac575ba05f3d3be806",
+    "8a5648f666e549f5b44fdafb3e192269ebf2fdd0a654ade4bd989ae3828d7a02",
+    "415e548668636247a2787c761bbbac8831450e3552b1fac22003f04f414050d0",
+    "2b47605f23be1f6562c469457781d4f3ce622a9a1ae6567dc857447ef5a845cd",
+    "02df7aba0040430e01f6a77abc3c9c0c69c33dcd6b2083537097f98f1a7a5841",
+    "eef613401b829b46d530621e5bc2f291f5ac8f55a231383bf14013b45128ba33",
+    "9b652880e3bc3a864a8fe3fe22e84868922578ac25519d398363724c340eb6ca",
+    "372174b61f980aa06a4640a2b53e6c6004b3b0bfd130e91c811f87ff07d47056",
+    "ee22f0c6bc82094c64085d800cb18d97889f9786b5b28b8891740d88ebe99086",
+    "0d91dd005c6a175c792e261b2626e56a84441f315cd59b142e3f45cda63fdede",
+    "6ba4562e60361383fd9fae2c4ec423c66ca62f351eb7c267f9527a4df8ffbc02",
+    "0d4075c7bd0b050b0bbc00ecf0f9533d0797e6a0737b2b22430646f0770f9102",
+    "3306adadf2276d6971203af6badc97268b4e98d589d115935c7a20bd06284bf4",
+    "f6828d9156f7ca0c08b70f59fd845b0fa9af5dbce5d3b085c2ff78aae8a7eee1",
+  );
+  const P: &str = concat!(
+    "e84972e8f5bacdaf783bc0fbbd58e5e7f05b2d0422fde4911863576e36ef7bbf",
+    "af27c8b03532b8b8856dcd14eb73960f4287d4028381d67927599b6edbc44d32",
+    "d959622283bddae0f8302de17839b9ac2aa2644da5eb9fd24bcb97cfebc39d79",
+    "2803cce31b6c6822ec8921a04748c9c8a0ba8fdf84a6245486b8136d4dc9452a",
+    "1ae6ce602e3e7eb33dd8cbbc2af47ff588f173412f2a544fd45d35185dabdc21",
+    "3693bcdf99a68e98dd1cd44bbf1b6bad23feb1be64fa9d760a0eb405b3cc3e20",
+    "0f802b08b9ec6867ee4d9e22cdbc7b8a9507f45ff216cd6ba44c9496e54c995f",
+    "36ac55acc09b41851f34e8140f48d5810d9a41c088c0cb5ef16b219b3011d489",
+  );
+  const Q: &str = concat!(
+    "ee3ca8a662c1b6fd4181e9404266b225c115c508a1d9bf6fb18af44d504fb8f8",
+    "39306e38cce6a1ff2e48ef222ddfa6b55bf16e75befde0dcfc28fb71c557966f",
+    "ad40057cd9384681f9d66cbbd805a7c82fd70ec9bc860484398a49d7b0a0467e",
+    "649462e7d76279707e1a631536f8fa5fcdce83604fac6889131f636a348ba333",
+    "e004242fa63486ba4ae57d37d9791d0feb176206b57739af560cf7bba82e4cc0",
+    "e04ec86d763ea2d483c9e8b01dc85a686a4f391539741545a8c6664538e1d2a2",
+    "b15ab00ff95f74965af02c1abe8349425ff84044fdbccf17d3295425111228ae",
+    "07d9bb09dcbb9cfab37b0298604444fce0c2402331b13baffadd03cededdab5d",
+  );
+  const DP: &str = concat!(
+    "0468c081a38874370c7535e891638a99a87a52946d859c3d14d56de76dab8611",
+    "d16553ede4549e2502835f0d329af3123f150cf52f41dbac590ede6070537d93",
+    "a58488d456e3d7afc5864498af0b8976b72875f6f35b51a15047010eb54ef542",
+    "437c2efb709dd22c378932d6340431b0593cb15670d226866438424624dfd92c",
+    "3f6e7a24613c5673588d30f0d56fe6ade6778ed481ecafc50426f0c711a01d81",
+    "93e3a2062c8878d475fe02199dfb0b443216b79101a1b0337965660d65a84ed5",
+    "94f5bddb608c183f40cac061b0be0f2a4641ec6595d6d0f7661afe070f3b30fe",
+    "19af93c0bfb732bb302874ef4c8af96285bb93dbefbc991fa83d7c59d1c3c4e1",
+  );
+  const DQ: &str = concat!(
+    "a97b4fc3669846f4ee485c4691b67b5fee77ed3f11dd559a4125085d8417e30f",
+    "fba75f925afa6c1e9cafd82769992500fa52cc71499955020105aab3aa5f802f",
+    "1c8262250a674589abd20672afe07e1725298a64ac106f601d8b2ab31dfd3789",
+    "2d467f024e30c911e534226ef237b7eeb5fc076c59e1de503de16399279e7b51",
+    "2939e90058ee1f36a29796384827282cade369a23a3082422126828ae6bf616d",
+    "096622a1f4afe4a8e8c4981bca644124ed144518bb51c0dc5890242b74a6fa71",
+    "3158c1cdeb6b2406ded4e39b7712f0e81e5bfe64d65a1ebde5440e8f7c107e0e",
+    "289995bd136208f03504fab8aa2b87249b44b5da13bcf12865801bf86c862825",
+  );
+  const QI: &str = concat!(
+    "270d0dba43737f508696ed6a22c3ac40f5324a5b66c7984afa2be2a2ec2b8c19",
+    "dfaefd06be6bb0b1f27908f3cd02139a22e8917e336fab5b4c0f5dd085530933",
+    "136c7aa413589dfc6be5aac512d6c701ab68457f6335a1fe1628dd1e64e6d430",
+    "878313fd93d9f12cb7c468b3547bcba665cc14837dd390034cf92f5d155d62bf",
+    "427ae4996d848230fbc2b41ac2938b0757f989a707078e7f7df47e614f572f95",
+    "055e2653a4df8b85def3decfb14369736d68dac4400f99e6c551b0da811ccc07",
+    "78494fcd5cd41181f204f569805f86ac2509ab22a552e2ddd56fd9a7dddb19fe",
+    "3751c5c07d5968f90ff7ffb03d03341ed2b2e173d76ac5c45153251ea60136a9",
+  );
+
+  fn crt() -> CrtParams<{ LIMBS / 2 }> {
+    CrtParams::new(half(P), half(Q), half(DP), half(DQ), half(QI)).unwrap()
+  }
+
+  fn monty() -> MontgomeryParams<LIMBS> {
+    MontgomeryParams::new(&uint(N)).unwrap()
+  }
+
+  /// Raw signature of an encoded message em, so that malformed encodings
+  /// can be signed.
+  fn sign_raw(em: &[u8]) -> Vec<u8> {
+    let d = uint::<LIMBS>(D).to_uint_array();
+    let s = decrypt(&uint_from_be(em), &d, &monty());
+    let mut sig = vec![0; EM_LEN];
+    uint_to_be(&s, &mut sig);
+    sig
+  }
+
+  #[test]
+  fn verify_pss() {
+    let n: U4096 = uint(N);
+    let mp = monty();
+
+    // PSS with SHA-256, MGF1-SHA-256 and a 32 byte salt from Python's
+    // cryptography.
+    let digest =
+      hex("ab563f05b2a5a01d45db759ec869b74bf747e5e35c2cb57ca87f160a0247d5cc");
+    let sig = hex(concat!(
+      "bf827abf254847817e57f1e5b95a38f6e4a3730720bafb9dca49730736c323f7",
+      "0c908aa27f0a80cc1e8d9f6bd5f1d72a7f4c03d53a4620f9459c65aed48181ad",
+      "e08892e89ff2bba57bafd33b46d1acce9f20c1ef63d287b406c05ba7a24febba",
+      "498a60433d327bea6a797f4a31c85ccd77e625868ee57fc283ba0fdb39f4310f",
+      "e2bfd14021c3cb43b679e9a93cb0a5ad8852a4ae23e45830ed6e9aa8bc58a369",
+      "496ad1869dd7fed31faf0c86d9b2c0f5fb9a616e96e9a7d3095dbe3a1ab9436e",
+      "1ed0489c362fbf976481224a11eec3f66e4ab211cabf8971cb66ddb741eb8015",
+      "7b4984fb19e430089e8c17fdf8f5c2b3f7b6365aa8fa2e184bd7e59fcb3d4082",
+      "b09cbd5282e690636d3a5e67736915146e1fc5dc53eec94642c2bbd587bfe0ad",
+      "6f9b065d8732a09ee371ee3b9865cf12d775ea4ec0e43ff0688e6d9bd9a240a1",
+      "5d726553752e104c317bba5f09ff4e464b4c06fd42b759e8ad1bc5425704a0d0",
+      "79c4d5c360c6f5eef34682e98b039d9d4a271e70db971cda5d3ebe6ae907628b",
+      "2d9dcac77eb4ab36854551b347c228f18e47d831c3834d377a0deb57ea1302f8",
+      "49e88d022e3ede62470de3127de9cfbae76fbb55f6fed1bf512e874c97fcffba",
+      "09fd8fee01245445baa1be1a639c50320f02fadb4cb48a03ce5b29a272d77117",
+      "3f5e1984c5af13ab0d7bfb31fda8991855dccfd211c3307afd9a6ff26fdc1338",
+    ));
+    assert_eq!(super::verify_pss(&digest, &sig, 32, &mp), Ok(()));
+    assert!(super::verify_pss(&digest, &sig, 20, &mp).is_err());
+    let mut other = digest.clone();
+    other[0] ^= 1;
+    assert!(super::verify_pss(&other, &sig, 32, &mp).is_err());
+
+    // Signature representatives n and above.
+    let ones = [0xFF; EM_LEN];
+    assert!(super::verify_pss(&digest, &hex(N), 32, &mp).is_err());
+    assert!(super::verify_pss(&digest, &ones, 32, &mp).is_err());
+
+    let mut em = [0; EM_LEN];
+    emsa_pss_encode(&digest, &[0x33; 32], 4095, &mut em).unwrap();
+    assert_eq!(super::verify_pss(&digest, &sign_raw(&em), 32, &mp), Ok(()));
+
+    // Trailer other than 0xbc.
+    let mut bad = em;
+    bad[EM_LEN - 1] ^= 1;
+    let sig = sign_raw(&bad);
+    assert!(super::verify_pss(&digest, &sig, 32, &mp).is_err());
+
+    // emBits = modBits - 1, so the top bit of EM must be clear. The check
+    // is needed since step 9 clears it again before DB is checked.
+    let mut bad = em;
+    bad[0] |= 0x80;
+    assert!(uint_from_be::<LIMBS>(&bad) < n);
+    let sig = sign_raw(&bad);
+    assert!(super::verify_pss(&digest, &sig, 32, &mp).is_err());
+  }
+
+  #[test]
+  fn sign_pss() {
+    let key = PrivateKey4096::new(uint(N), uint(D), None).unwrap();
+    let mut rng = Hc128Rng::from_seed([0x01; 32]);
+    let digest = [0x5A; 32];
+    let mut sig = [0; EM_LEN];
+    sign_pss_with_salt(&mut rng, &digest, &[0x33; 32], &key, &mut sig).unwrap();
+    assert_eq!(super::verify_pss(&digest, &sig, 32, key.monty()), Ok(()));
+    assert_eq!(
+      sign_pss_with_salt(&mut rng, &digest[..20], &[0x33; 32], &key, &mut sig),
+      Err(Error::Encoding)
     );
-    // 0:8860687789152922376
-    // 1:64
+  }
 
-    let one = U4096::from_u8(1u8);
+  #[test]
+  fn crt_matches_plain() {
+    let n: U4096 = uint(N);
+    let mp = monty();
+    let d = uint::<LIMBS>(D).to_uint_array();
+    let crt = crt();
+
+    // With p < q, m = p + 1 and m = q - 1 give m_2 = m mod q > p.
+    assert!(crt.p < crt.q);
+    let mut rng = Hc128Rng::from_seed([0x02; 32]);
+    let mut ms = vec![
+      U4096::ZERO,
+      U4096::ONE,
+      widen(&crt.p.wrapping_add(&U2048::ONE)),
+      widen(&crt.q.wrapping_sub(&U2048::ONE)),
+      n.wrapping_sub(&U4096::ONE),
+    ];
+    for _ in 0..4 {
+      let mut bytes = [0u8; 512];
+      rng.fill_bytes(&mut bytes[1..]);
+      ms.push(uint_from_be(&bytes));
+    }
 
-    // powers[i] contains x^i
-    let mut powers = [U4096::default(); 1 << 4];
+    for m in ms.iter() {
+      let c = encrypt(m, PUBLIC_EXPONENT, &mp);
+      assert_eq!(decrypt_crt(&c, &crt, 0), *m);
+      assert_eq!(decrypt(&c, &d, &mp), *m);
+    }
 
-    // 12295575353834661461
-    let mut i = 0;
-    powers[i] = montgomery(&one, &rr, &m, mr.n0inv, num_words);
-    i += 1;
+    let key = PrivateKey4096::new(n, uint(D), Some(crt)).unwrap();
+    let digest = [0x5A; 32];
+    let mut sig = [0; EM_LEN];
+    sign_pss_with_salt(&mut rng, &digest, &[0x33; 32], &key, &mut sig).unwrap();
+    assert_eq!(super::verify_pss(&digest, &sig, 32, key.monty()), Ok(()));
+  }
 
-    // x = 8203905367948014444 (64)
-    // 10628657572930017130
-    powers[i] = montgomery(&x, &rr, &m, mr.n0inv, num_words);
-    i += 1;
+  /// Montgomery multiplications done by f.
+  fn mont_ops(f: impl FnOnce()) -> usize {
+    let before = MONT_OPS.with(|ops| ops.get());
+    f();
+    MONT_OPS.with(|ops| ops.get()) - before
+  }
 
-    for idx in 2..1 << 4 {
-      let r = montgomery(&powers[idx - 1], &powers[1], m, mr.n0inv, num_words);
+  #[test]
+  fn constant_operation_count() {
+    let n: U4096 = uint(N);
+    let mp = monty();
+    let crt = crt();
+    let c = n.wrapping_sub(&U4096::from_u8(2));
+
+    // Exponents of the same length with one, half and all bits set.
+    let mut sparse = [0 as LimbUInt; LIMBS];
+    sparse[0] = 1;
+    let exponents =
+      [sparse, [LimbUInt::MAX / 3; LIMBS], [LimbUInt::MAX; LIMBS]];
+    let counts: Vec<_> = exponents
+      .iter()
+      .map(|y| {
+        mont_ops(|| {
+          monty_modpow(&c, y, &mp);
+        })
+      })
+      .collect();
+    assert!(counts[0] > 0);
+    assert!(counts.iter().all(|n| *n == counts[0]), "{:?}", counts);
+
+    // CRT, including the reductions mod p and q, for inputs of different
+    // sizes and different exponent blinding.
+    let counts: Vec<_> = [(U4096::ONE, 0), (c, 0), (c, u64::MAX)]
+      .iter()
+      .map(|(c, k)| {
+        mont_ops(|| {
+          decrypt_crt(c, &crt, *k);
+        })
+      })
+      .collect();
+    assert!(counts.iter().all(|n| *n == counts[0]), "{:?}", counts);
+  }
 
-      powers[i] = r;
-      i += 1;
+  #[test]
+  fn reductions() {
+    let n: U4096 = uint(N);
+    let p = widen(&half(P));
+
+    let mut rng = Hc128Rng::from_seed([0x03; 32]);
+    let mut xs = vec![U4096::ZERO, U4096::ONE, p, n, U4096::MAX];
+    for _ in 0..4 {
+      let mut bytes = [0u8; 512];
+      rng.fill_bytes(&mut bytes);
+      xs.push(uint_from_be(&bytes));
     }
-    // initialize z = 1 (Montgomery 1)
-    let mut z = powers[0].clone();
-    let mut zz = U4096::default();
-
-    // same windowed exponent, but with Montgomery multiplications
-    for i in (0..y.len()).rev() {
-      let mut yi = y[i];
-      let mut j = 0;
-      while j < Limb::BIT_SIZE {
-        if i != y.len() - 1 || j != 0 {
-          zz = montgomery(&z, &z, m, mr.n0inv, num_words);
-          z = montgomery(&zz, &zz, m, mr.n0inv, num_words);
-          zz = montgomery(&z, &z, m, mr.n0inv, num_words);
-          z = montgomery(&zz, &zz, m, mr.n0inv, num_words);
-        }
-        zz = montgomery(
-          &z,
-          &powers[(yi >> (Limb::BIT_SIZE - 4)) as usize],
-          m,
-          mr.n0inv,
-          num_words,
-        );
-        core::mem::swap(&mut z, &mut zz);
-
-        yi <<= 4;
-        j += 4;
-      }
+
+    for x in xs.iter() {
+      // wrapping_rem is variable time, and returns zero for x < p.
+      let expected = if *x < p { *x } else { x.wrapping_rem(&p) };
+      assert_eq!(rem(x, &p), expected);
+      assert_eq!(widen(&reduce_half(x, &half(P))), expected);
     }
+    assert_eq!(
+      rem(&UInt::<1>::from_u8(5), &UInt::from_u8(7)),
+      UInt::from_u8(5)
+    );
+  }
+
+  #[test]
+  fn blinding() {
+    let n: U4096 = uint(N);
+    let key = PrivateKey4096::new(n, uint(D), Some(crt())).unwrap();
+    let crt = key.crt.as_ref().unwrap();
+    let plain = PrivateKey4096::new(n, uint(D), None).unwrap();
+
+    let mut rng = Hc128Rng::from_seed([0x04; 32]);
+    let mut bytes = [0u8; 512];
+    rng.fill_bytes(&mut bytes[1..]);
+    let m = uint_from_be(&bytes);
+    let c = encrypt(&m, PUBLIC_EXPONENT, key.monty());
+
+    for k in [0, 1, 1 << 32, u64::MAX / 3, u64::MAX].iter() {
+      assert_eq!(decrypt_crt(&c, crt, *k), m);
+    }
+    // A fresh r and k every time.
+    for _ in 0..4 {
+      assert_eq!(key.decrypt_blinded(&mut rng, &c), m);
+      assert_eq!(plain.decrypt_blinded(&mut rng, &c), m);
+    }
+    assert_eq!(plain.decrypt(&c), m);
+  }
+
+  #[test]
+  fn fault_withheld() {
+    // dP off by one, as if a fault hit the exponentiation mod p.
+    let mut faulty = crt();
+    faulty.dp = faulty.dp.wrapping_add(&U2048::ONE);
+    let key = PrivateKey4096::new(uint(N), uint(D), Some(faulty)).unwrap();
+
+    let mut rng = Hc128Rng::from_seed([0x05; 32]);
+    let digest = [0x5A; 256 / 8];
+    assert_eq!(
+      sign_pss_with_salt(&mut rng, &digest, &[0; 32], &key, &mut [0; EM_LEN]),
+      Err(Error::Fault)
+    );
+
+    let good = PrivateKey {
+      crt: Some(crt()),
+      ..key
+    };
+    let mut sig = [0; EM_LEN];
+    sign_pss_with_salt(&mut rng, &digest, &[0; 32], &good, &mut sig).unwrap();
+    assert_eq!(super::verify_pss(&digest, &sig, 32, good.monty()), Ok(()));
+  }
+
+  #[test]
+  fn redundant_eq() {
+    assert!(super::redundant_eq(b"", b""));
+    assert!(super::redundant_eq(b"arienai", b"arienai"));
+    assert!(!super::redundant_eq(b"arienai", b"brienai"));
+    assert!(!super::redundant_eq(b"arienai", b"arienaj"));
+    assert!(!super::redundant_eq(b"arienai", b"arienai!"));
+  }
+
+  // Primes and private exponents of 2048 and 3072-bit keys from Python's
+  // cryptography, with p < q.
+  const P2048: &str = concat!(
+    "b7af2bd45522b1436ff6e03f428787aa27cb0627605d300c16bb66a0dbbceae5",
+    "4959c6e3d36153960617a6fec175c067b61182491f200df7c1be73d45506b375",
+    "dc15254814ecceb7f96daee1bf7776dd3bd2fca9dd5f15e50405d9b8dcbd7a59",
+    "55b1bec370214614521dcd00e63465e578c0b075a8abde015570038389f2932b",
+  );
+  const Q2048: &str = concat!(
+    "d65604903281036ba08f2f566b9f42c4b5e725374e5f928adaaf7e454c3b47ad",
+    "e91d86b6afcacfd4fcf297c4648301d19ca35d8b47f2322d93af18917d1a905e",
+    "a20de1e8527f1392cecc1cde244e26476e40586541b7bccf75c6ef68be196245",
+    "f49b100a8d90451215b789c48216eeba6d796f2e6b99dee6c2620961e2ac9689",
+  );
+  const D2048: &str = concat!(
+    "0ed753d33a60cbee95c305a989c329cd7cc67ed56c291b07921beb10d51c642d",
+    "29badeccec2256357ff1ffda6227f49a4212ed4cd6a3c8bafba014f8d0e80579",
+    "508d4475e288288c5033f23b681dbe948b297717d17e8003c31616744fa867e5",
+    "94cd8e60d2678d8b9f207233fccd51698005bc777f9f1f04d4d382f86ad15281",
+    "7dff86d5017a8d75dd6711852f2f6a190da9b30045c9b24f63483dd145d81775",
+    "c08824fcefe93c6a107392b5b90f9c80f55b5ec99761fc7251e6d05472898563",
+    "b65c7e77c9371483edb13593f73e8ed4bfb28b90ae198343026a6b8ab21d64e5",
+    "3e0c868b48831454a534fb2c2eec3860b527c7902681f4bdc28c25f422052569",
+  );
+  const P3072: &str = concat!(
+    "b5cb52eb15f3b5f32bfab6913d7c0c0c66f7d8c3dbcc300b65f4ef5b5d1abb78",
+    "632bd9d8ee450d20da0b06b3908ecb6457b6f119b685cd02a6e6a05053edc27a",
+    "a6f37138b390fb2134c4172f660ddc45c2be9878ebe928f9cfce5f2e2ddaf1d5",
+    "2682c603faa5584408881f737000e86974df3a89a51562c1de98fe9abc0b662a",
+    "b28ef06176adfa866f3daa49655869ac42c86ebc35d06cddda4d35ac6c549425",
+    "3d533165e146d575aeb83f33d3db0bd6314ca04ee640a34ec43206bfc981fa0b",
+  );
+  const Q3072: &str = concat!(
+    "f0c9d74edf2836305872ca949cfb16e3977c1f65ea916e3384758f31b734a8b2",
+    "f50a45ec98b861f2ba9925d8c43d1d8bcd27356d84eb3e72254c5c90431386ad",
+    "6c1e0774c1c8124780984cbadc1060604a09b4ddca1f1cacf5af0ccd11efa585",
+    "4733df28105073f5132cb8e4bd963526dbef14203f37cceb618ff34359da35bb",
+    "32a16b2e89ca8f138724de769cf3a628f295518d5de7d6e2acf04aed65e681d4",
+    "baa42125e3b4f2867151fa674297e09e1bf274b2dc70101fd104a75f59713a11",
+  );
+  const D3072: &str = concat!(
+    "3c250f490bfdde2fbbbb5812c830213f54dbe79aeb94e147a6ee5af17beb7f9a",
+    "597e47146a4b8e15071b3cee30c0f0547596a10b7163d73bd4e507dfe8d82b17",
+    "d5dcf7d0a4d685bc6e2ac8324207618a71a20a37518be4f34ba041de93925043",
+    "74552a7be9be95bfe0593648a019177397432efafe5054a6d41efb4fed9f72e1",
+    "6c34f839c0526208a8f20fca6676f74a9ff030cfd1aa5374168b7d086bb2fc19",
+    "3ca87fae7fe96a4f6cab5bc01435932277be70c3a53bdd5a7d860c985df125d6",
+    "97799c2017987ecbd3a1dd063f2f5e96d0a213ffdb6add2debab86f7929869f2",
+    "d0adc26c7a9c9c3996e1f552ef79d41f7ac166e7070f06c2c9739a8f4130c2f3",
+    "21ce63cd8e878e903f86fc9ef76a00267769059c414d52b76b389916630e9740",
+    "b4c2517a5d8c987f10f69ace0aa2dfe0e2c44dff3e880d94e9ea26e8a98b428a",
+    "b413902fb228f6ca6fc1f4115be62161627d5f95a563d720549b72f9b2ebda42",
+    "2b717cd14beb043cd78a2d50e11a1fa680991966961a1929dc8cfa93851df781",
+  );
+
+  /// A key from its primes and private exponent, with n and the CRT
+  /// parameters derived from them.
+  fn key_from<const L: usize, const H: usize>(
+    p: &str,
+    q: &str,
+    d: &str,
+  ) -> PrivateKey<L, H> {
+    let (p, q, d): (UInt<H>, UInt<H>, UInt<L>) = (uint(p), uint(q), uint(d));
+    let (lo, hi) = p.mul_wide(&q);
+    let dp = narrow(&rem(&d, &widen(&p.wrapping_sub(&UInt::ONE))));
+    let dq = narrow(&rem(&d, &widen(&q.wrapping_sub(&UInt::ONE))));
+    let qinv = inv_mod(&rem(&q, &p), &p).unwrap();
+    let crt = CrtParams::new(p, q, dp, dq, qinv);
+    PrivateKey::new(concat(&lo, &hi), d, crt).unwrap()
+  }
 
-    // convert to regular number
-    zz = montgomery(&z, &one, m, mr.n0inv, num_words);
-    // One last reduction, just in case.
-    // See golang.org/issue/13907.
-    if zz >= *m {
-      // Common case is m has high bit set; in that case,
-      // since zz is the same length as m, there can be just
-      // one multiple of m to remove. Just subtract.
-      // We think that the subtract should be sufficient in general,
-      // so do that unconditionally, but double-check,
-      // in case our beliefs are wrong.
-      // The div is not expected to be reached.
-      zz = zz.wrapping_sub(m);
-      if zz >= *m {
-        zz = zz.wrapping_rem(m);
+  fn round_trip<const L: usize, const H: usize>(key: PrivateKey<L, H>) {
+    assert_eq!(key.n.bits(), L * Limb::BIT_SIZE);
+    let plain = PrivateKey::<L, H> { crt: None, ..key };
+
+    let mut rng = Hc128Rng::from_seed([0x06; 32]);
+    let mut sig = [0u8; MAX_LIMBS * Limb::BYTE_SIZE];
+    let sig = &mut sig[..L * Limb::BYTE_SIZE];
+    let digest = [0x5A; 256 / 8];
+    for key in [&key, &plain].iter() {
+      sign_pss_with_salt(&mut rng, &digest, &[0x33; 32], key, sig).unwrap();
+      assert_eq!(super::verify_pss(&digest, sig, 32, key.monty()), Ok(()));
+      sig[L * Limb::BYTE_SIZE - 1] ^= 1;
+      assert!(super::verify_pss(&digest, sig, 32, key.monty()).is_err());
+    }
+
+    let digest = [0xA5; 512 / 8];
+    sign_pkcs1v15(&mut rng, Hash::Sha512, &digest, &key, sig).unwrap();
+    assert_eq!(
+      verify_pkcs1v15(Hash::Sha512, &digest, sig, key.monty()),
+      Ok(())
+    );
+  }
+
+  #[test]
+  fn round_trips() {
+    round_trip::<{ 2048 / Limb::BIT_SIZE }, { 1024 / Limb::BIT_SIZE }>(
+      key_from(P2048, Q2048, D2048),
+    );
+    round_trip::<{ 3072 / Limb::BIT_SIZE }, { 1536 / Limb::BIT_SIZE }>(
+      key_from(P3072, Q3072, D3072),
+    );
+    round_trip::<LIMBS, { LIMBS / 2 }>(key_from(P, Q, D));
+  }
+
+  #[test]
+  fn montgomery_kernels() {
+    const L: usize = 1024 / Limb::BIT_SIZE;
+    const W: usize = 2 * L;
+    let random = |rng: &mut Hc128Rng| {
+      let mut bytes = [0u8; 1024 / 8];
+      rng.fill_bytes(&mut bytes);
+      uint_from_be::<L>(&bytes)
+    };
+
+    let mut rng = Hc128Rng::from_seed([0x08; 32]);
+    let mut t = [0 as LimbUInt; MAX_LIMBS * 2];
+    for i in 0..16 {
+      // Odd moduli with and without the top bit set, and operands up to R,
+      // so that the almost Montgomery results can be above m.
+      let mut m = random(&mut rng) | UInt::ONE;
+      if i % 2 == 0 {
+        m = m.shr_vartime(i + 1) | UInt::ONE;
+      } else {
+        m |= UInt::ONE.shl_vartime(L * Limb::BIT_SIZE - 1);
       }
+      let mp = MontgomeryParams::new(&m).unwrap();
+      let ml = m.to_uint_array();
+      let mw = widen::<L, W>(&m);
+      let (x, y) = (random(&mut rng), random(&mut rng));
+
+      // x * y
+      let (lo, hi) = x.mul_wide(&y);
+      let xy = concat::<L, W>(&lo, &hi);
+      let mut z = [0 as LimbUInt; W];
+      sqr_vv(&mut z, &x.to_uint_array());
+      let (lo, hi) = x.mul_wide(&x);
+      assert_eq!(UInt::from_uint_array(z), concat::<L, W>(&lo, &hi));
+
+      // z * R = x * y mod m
+      let mut z = x.to_uint_array();
+      mont_mul(&mut z, &y.to_uint_array(), &ml, mp.n0inv, &mut t);
+      let zr = concat::<L, W>(&UInt::ZERO, &UInt::from_uint_array(z));
+      assert_eq!(rem(&zr, &mw), rem(&xy, &mw));
+
+      // Squaring does the same reduction as a multiplication by itself.
+      let mut z = x.to_uint_array();
+      mont_mul(&mut z, &x.to_uint_array(), &ml, mp.n0inv, &mut t);
+      let mut s = x.to_uint_array();
+      mont_sqr(&mut s, &ml, mp.n0inv, &mut t);
+      assert_eq!(s, z);
+
+      // z * R = x mod m, also through mont_redc on a full 2n limbs.
+      let mut z = x.to_uint_array();
+      mont_from(&mut z, &ml, mp.n0inv, &mut t);
+      let zr = concat::<L, W>(&UInt::ZERO, &UInt::from_uint_array(z));
+      assert_eq!(rem(&zr, &mw), rem(&widen(&x), &mw));
+
+      let mut t2 = xy.to_uint_array();
+      let c = mont_redc(&mut t2, &ml, mp.n0inv);
+      let mut z = [0 as LimbUInt; L];
+      mont_finish(&mut z, &t2[L..], &ml, c);
+      let zr = concat::<L, W>(&UInt::ZERO, &UInt::from_uint_array(z));
+      assert_eq!(rem(&zr, &mw), rem(&xy, &mw));
+
+      // And the fully reduced x * y mod m.
+      let (xm, ym) = (rem(&x, &m), rem(&y, &m));
+      let (lo, hi) = xm.mul_wide(&ym);
+      let xy = concat::<L, W>(&lo, &hi);
+      assert_eq!(widen::<L, W>(&mul_mod(&xm, &ym, &mp)), rem(&xy, &mw));
+    }
+  }
+
+  #[test]
+  fn pkcs1v15() {
+    let key = PrivateKey4096::new(uint(N), uint(D), Some(crt())).unwrap();
+    let mut rng = Hc128Rng::from_seed([0x10; 32]);
+
+    // Signatures of "arienai pkcs1 test" from Python's cryptography. The
+    // scheme is deterministic, so they must match exactly.
+    let vectors = [
+      (
+        Hash::Sha256,
+        "136b3a556c88c65408abf3b722eb4a0add4c71990f23cc0d697e1a2d26346d03",
+        concat!(
+          "1ba77d0a20be1015a3df20a878212c9531db162263557d389bed8d9e0979456e",
+          "6d4657800e172c2a5f32573d2a68790c6b70ae6fe13d43ac7b1b2374d81c4266",
+          "5825927611ce09528c7bfe75871ec7a96a20ef91ce0006080e048042db4df3ea",
+          "82031fd9c767794dd3276ec37009a1ca49fa3fad3d215cd85c469c5d427af125",
+          "de56ac7c753fa73801f206767737d63c5c94fa55d031f67f4f4bfada8256b794",
+          "15dd6768ce208c182ee04235619b7620a130e7d6799bd3945675c1c585474fb5",
+          "c803d5f05d78e4564450bab892ffee3f5742e91fd8c1dd9ab3ec8b88dbfb6b22",
+          "d30b9b194041f28f59dc2d26e86b148f0a20bea7b3bcb5e69d3896d044f009bc",
+          "9bdb0c2b9e602930c6816eb26efb3fa494dc531d8a25aa75e28a012be0de20c5",
+          "1921a6d08f1418a98f0920f4056d89208d37d05001894d7c41226f41a4a53c35",
+          "12f1edad109a5e3e83d812b97195582aa83118170338be3b826dcdc10db72f9f",
+          "70447f86e0709dd1924585b468785fd99786d45b996cb90367257db1ee1c2fd7",
+          "84b84864a22fdf60345c0add70a4d8a3deba32e2d4c27e6b60c017934073bf6c",
+          "81adae1692cde66b0008dea3ec9e3def65966e31dea78d71747c06e5cffbcf57",
+          "90ad31b73a73aaf1e495d578db3096c2f3494aeea8b55f062f2216cc78c84972",
+          "2a92c6bcc9d0f457f7f67dd0775048d9daab098c8f8cfef866027681bac67867",
+        ),
+      ),
+      (
+        Hash::Sha512,
+        concat!(
+          "d8005fa7cdb42b5ec24f011e5f6e6a2aa8000c38afc63c08cd5e325a65bf79c2",
+          "ec1f2694aedce868d643fdc85f4bdab4560c150820871ed3f3cb52de8637f129",
+        ),
+        concat!(
+          "3eb5ce082341fb1da92d2a47c7edcb5b32011efd34edf3b833a84103a5cfab6d",
+          "263eab4e11d737fd56d2b4913865b9896cc9d64d0cbe13bca3c65b81fa85626e",
+          "df8d9e2a899f22e236bd9eba65c1ee7fb556a6e57db38af989fc8b621c6b8f2a",
+          "946c86479d49ed96a0e057f2c03f9a5aae013fcd62f531275e3d2f1299352307",
+          "9dc6d9abaccb24676410c5f0357cdf4ff52027ae877004e772b79130e04c03b8",
+          "b3968f24194005bee5e03664792acad70da4622037b7ff8f5fda61a2d3595e84",
+          "2369c919d9f708cf1460222057141e3d4b9e7fa960773ae1cab9a68849adb66f",
+          "8fcb9210c47343a419e5744f042c45d78e9113a7ad5316848f34af5fa185a7a3",
+          "84727bc4d2d383c13bc418ea56c53fbbd7fc77a1b35b802132234de91fb693f0",
+          "fda353d14b1d15dea9746a0d07354e7793ef3d4ec6fd4085275db002bffce1f9",
+          "3731dc0f896560a743b4774ac53b694a9fc1ea0e78d4768611cdc1755ca85634",
+          "f6601012d5125f2994c7441f764655a00721b02691bf776d0a15d77ecaf8c7bc",
+          "611ea763ff05ae323e6bf31aa2db603f994449d23247465479da9b5e74883552",
+          "05ba7a83331451d158d7bfc27a906e86dc16bfbb2292db1b5ce458c33f794cd2",
+          "61b967d1cffa154c1bef4abce81050e877ae55a6680eee0171c0ad82f5a4d877",
+          "04d17b525dbdfd3ac74151d5834d6b7306f5b3d8122d05ba3e91fa1db0219b67",
+        ),
+      ),
+    ];
+    for (hash, digest, expected) in vectors.iter() {
+      let digest = hex(digest);
+      let mut sig = [0u8; EM_LEN];
+      sign_pkcs1v15(&mut rng, *hash, &digest, &key, &mut sig).unwrap();
+      assert_eq!(sig[..], hex(expected)[..]);
+      assert_eq!(verify_pkcs1v15(*hash, &digest, &sig, key.monty()), Ok(()));
+
+      let mut other = digest.clone();
+      other[0] ^= 1;
+      assert_eq!(
+        verify_pkcs1v15(*hash, &other, &sig, key.monty()),
+        Err(Error::Verify)
+      );
+    }
+
+    let digest = hex(vectors[0].1);
+    let mut sig = [0u8; EM_LEN];
+    assert_eq!(
+      sign_pkcs1v15(&mut rng, Hash::Sha512, &digest, &key, &mut sig),
+      Err(Error::Encoding)
+    );
+    let sig = hex(vectors[0].2);
+    assert!(verify_pkcs1v15(Hash::Sha512, &digest, &sig, key.monty()).is_err());
+
+    // The DigestInfo must be exactly the one of the hash: the SHA-384 OID
+    // in its place, or no DigestInfo at all, is rejected.
+    let mut em = [0u8; EM_LEN];
+    emsa_pkcs1v15_encode(Hash::Sha256, &digest, &mut em).unwrap();
+    let oid = EM_LEN - digest.len() - 5;
+    assert_eq!(em[oid], 0x01);
+    em[oid] = 0x02;
+    let sig = sign_raw(&em);
+    assert!(verify_pkcs1v15(Hash::Sha256, &digest, &sig, key.monty()).is_err());
+
+    let mut em = [0xFF; EM_LEN];
+    em[0] = 0x00;
+    em[1] = 0x01;
+    em[EM_LEN - digest.len() - 1] = 0x00;
+    em[EM_LEN - digest.len()..].copy_from_slice(&digest);
+    let sig = sign_raw(&em);
+    assert!(verify_pkcs1v15(Hash::Sha256, &digest, &sig, key.monty()).is_err());
+  }
+
+  /// RSAES-OAEP encryption with SHA-256 under the test key, with the label
+  /// hash, the octet separating PS from M and the leading octet of EM
+  /// given.
+  fn oaep_encrypt(
+    l_hash: &[u8],
+    separator: u8,
+    first: u8,
+    msg: &[u8],
+  ) -> Vec<u8> {
+    let k = EM_LEN;
+    let mut db = vec![0u8; k - 32 - 1];
+    db[..32].copy_from_slice(l_hash);
+    db[k - 32 - 2 - msg.len()] = separator;
+    db[k - 32 - 1 - msg.len()..].copy_from_slice(msg);
+
+    let mut seed = [0x77; 32];
+    mgf1_xor(&mut db, &seed);
+    mgf1_xor(&mut seed, &db);
+    let mut em = vec![first];
+    em.extend_from_slice(&seed);
+    em.extend_from_slice(&db);
+
+    let mut c = vec![0u8; k];
+    let m = uint_from_be::<LIMBS>(&em);
+    uint_to_be(&encrypt(&m, PUBLIC_EXPONENT, &monty()), &mut c);
+    c
+  }
+
+  #[test]
+  fn oaep() {
+    let key = PrivateKey4096::new(uint(N), uint(D), Some(crt())).unwrap();
+    let mut rng = Hc128Rng::from_seed([0x11; 32]);
+    let mut out = [0u8; EM_LEN];
+
+    // Message, label and ciphertext from Python's cryptography, OAEP with
+    // SHA-256 and MGF1-SHA-256.
+    let vectors = [
+      (
+        "68656c6c6f20617269656e6169",
+        "",
+        concat!(
+          "d63bc0454df040a69baba63e7a6c5c04b4a67018d19c1b0c3ed3fed8db2781d6",
+          "39018a28b9f171904291d3effc9ade8eb0c82567fe016ee1e6eb55d1ba84c1d9",
+          "3e5dcbe703161c0cd0b68eb4c01d13f20434ed1023febc514149486e51d04ece",
+          "fece4277e6216e097677585ccb851e6ac5710c4c1cc9d061fead25741869f76e",
+          "ad0c1daa71d0995042ec8b621126ac8d0a6db97b791c02ab375fa2b58f1d73b4",
+          "0237786d3c18d17f15ce0c25812adab30c640f494ff7166b301eacde4563d145",
+          "a66ac56e1b44567dabeb3396aa3c3f0501d19293f45b7d1ff9c350b94a2d86b2",
+          "38ca207ad2d04b5c37ecad444ded27766f6d6623cd19609d152c8d3bd06cae4d",
+          "6428e4ef6feed4159d4def5b4326bbabadda7312bbe416ad91e030fd30c7721e",
+          "c35fba90e4f94685eb5ad1353bd71d8fc6e12f1b00086bf9570d9ab48521ef0f",
+          "3d957c2f403691c979103c139ce0ed9669d7bcaf1f95eb13c7e01d0415e8f58a",
+          "ddf10839a9a1731d234bec9ae032fbba00a1ef8aa3ff5163883d273faa26761e",
+          "0fc00d6c3843afbb20628efbdb818a89cd4e124a60163f113bbcdbf43d47a8d5",
+          "3c60fb80503a8f4329461b2d4e66edf4198dbcedf376eae86e707a19995340a4",
+          "33b30bbfacfabe2bb0273c10b472ca16e54f9effade88eff72a422f9f4d23380",
+          "6992d883f87f089ec4ea20699c4ba9b6f1741366c61220b162f3bc6c303ab581",
+        ),
+      ),
+      (
+        "",
+        "",
+        concat!(
+          "47527c7b24d79282456f1339ebdd1a414441d5316c0a593ca7b8e89962c44d9f",
+          "853328eaf61a01308e08077afa3342c0db78af252dcb73194e000a9d00b5a643",
+          "d789ed129c2c5e6513258e3a9594ddbf721d13f3d2c76dfd38dc19293dfbf936",
+          "c308be3d613038b2e0711047ab4cb7fab847424e0210fd35aba7dd35a00fae4f",
+          "5dfd865c0458287c2dd1a0a957b0a1b1735bbcc5e51fef7e92a63d7132a0143e",
+          "b15182a3f2c37bf6dca55fa5d6e206b14af97f8ed0d90b889ff647b20246b340",
+          "0ccfa20800163c814d38549fc11bb53ccc4a78786309d396cd8dcec315e97a5a",
+          "f27b4053729d0d2a0d36409ec5a69712ef0bb2497695016054ba03bede0fe778",
+          "6a9b46b921f4f5e97279e74d91f479e7f86c41c126b6e1b90ca2bad69856542d",
+          "b07ea2c119bb5cbd250d39c1192abbe41b6a7e8958129f0d8b34bbeb2d9a2235",
+          "ccab62e253399a302151705569a20b0c10463d9ad21c58796454f6b7b6e66f62",
+          "64ece37d401a2d215928a484582cf8df30997f0f5a8c9c2c13b2318c091eb0b6",
+          "72d96e5869cd3d1e8baa9ca207156586af9885cd8a99f46c0ef52a11ec8a173d",
+          "536e661ddf6382cace96fd120327955c889f48061b91d48cfe8bf7908e18ddec",
+          "057eb3b8e3d2a664438bc5030c0f123c9e9252ff76c34fe1e7d67219e1110853",
+          "dfac386ea556e84c6b60f44f898bb5894d63a87f2f67af172b2e81ada008cb66",
+        ),
+      ),
+      (
+        "6c6162656c2074657374",
+        "61727765617665",
+        concat!(
+          "c9a234ccaa330f5c3d8b2f73d71229bfb5f442ff912e7d9e6edc2119bbae4826",
+          "6c17ef3f7a9653bc20529051663c6a7fa0c11b9e13079cb57e7c33d932110640",
+          "7179de806c1982b83c69d9f0b53aa4295513949336bacfca672818b49582d0e3",
+          "45162b406f76e6f233ea557c2d7ef6fcff2901b312993e594e8e670c3742c084",
+          "5f36054b958fe4168b3ce1c1eae6af4a0043fcbf09a27711ee919d0a5eb01bc8",
+          "477788023c2cfcc400f608eb5671aa6e1f2843874ca9bd44eb189934068afd6f",
+          "091d7d04469c364c31f28940b8b01d5f0ec4df19e56ed84c821eba09a6b3bddd",
+          "71728d311edba07b5f91db39f7c104b8dc8c39864241a0a4909dc45f63403df4",
+          "28cee41a218898bab3f0c62fc511bee8e776b444efc606cb15e1ae4befbafbbe",
+          "e2d005e7683617f200c53e1e516e65b513549cb05b3a1bca4fad3fb765969bcb",
+          "5fbb7a9d44fc94cee2ee0b094095a5d2f1b197816628a427673920ae24b96fdb",
+          "1f7f2570ac3580f7446137d5aff6f6b3876eea8ca1d584f45a1aca3c4238e4d2",
+          "a13c4d8871dd437b34830a484e510e97285e94f3e657b92c58e1bb098bf463bb",
+          "5a41e6a8292eb13115cdb8ff5c86b9d7a8c019db431062001811b8b57302a178",
+          "1bcd78f6533cbe077cf99f5232472de77a5612f13df0801858740a9e0645c219",
+          "6af16eeb278756bd8e21d2d9bbbd5a57704d199bff6792d1f5e9196f8db0adda",
+        ),
+      ),
+    ];
+    for (msg, label, c) in vectors.iter() {
+      let len = decrypt_oaep(&mut rng, &hex(label), &hex(c), &key, &mut out);
+      assert_eq!(out[..len.unwrap()], hex(msg)[..]);
+      let wrong = decrypt_oaep(&mut rng, b"other", &hex(c), &key, &mut out);
+      assert_eq!(wrong, Err(Error::Decryption));
+    }
+
+    let l_hash = Sha256::new().update(b"").finalize();
+    let c = oaep_encrypt(&l_hash, 0x01, 0x00, b"hello");
+    assert_eq!(decrypt_oaep(&mut rng, b"", &c, &key, &mut out), Ok(5));
+    assert_eq!(&out[..5], b"hello");
+
+    let other = Sha256::new().update(b"other").finalize();
+    let malformed = [
+      // lHash of another label.
+      oaep_encrypt(&other, 0x01, 0x00, b"hello"),
+      // No 0x01 after PS.
+      oaep_encrypt(&l_hash, 0x00, 0x00, b"hello"),
+      // A nonzero octet other than 0x01 after PS.
+      oaep_encrypt(&l_hash, 0x02, 0x00, b"hello"),
+      // Leading octet Y not zero.
+      oaep_encrypt(&l_hash, 0x01, 0x01, b"hello"),
+    ];
+    for c in malformed.iter() {
+      assert_eq!(
+        decrypt_oaep(&mut rng, b"", c, &key, &mut out),
+        Err(Error::Decryption)
+      );
+    }
+
+    // Representatives n and above, and ciphertexts of the wrong size.
+    assert_eq!(
+      decrypt_oaep(&mut rng, b"", &hex(N), &key, &mut out),
+      Err(Error::Decryption)
+    );
+    assert_eq!(
+      decrypt_oaep(&mut rng, b"", &c[1..], &key, &mut out),
+      Err(Error::Decryption)
+    );
+  }
+
+  #[test]
+  fn generate() {
+    const L: usize = 1024 / Limb::BIT_SIZE;
+    const H: usize = 512 / Limb::BIT_SIZE;
+    const W: usize = 2048 / Limb::BIT_SIZE;
+
+    let mut rng = Hc128Rng::from_seed([0x12; 32]);
+    let key = PrivateKey::<L, H>::generate(&mut rng).unwrap();
+    let crt = key.crt.as_ref().unwrap();
+    assert_eq!(key.n.bits(), 1024);
+    let (lo, hi) = crt.p.mul_wide(&crt.q);
+    assert_eq!(concat::<H, L>(&lo, &hi), key.n);
+
+    // e * d = 1 mod p - 1 and mod q - 1, so mod λ(n).
+    let (lo, hi) = key.d.mul_wide(&UInt::from_u32(65537));
+    let ed = concat::<L, W>(&lo, &hi);
+    for p in [crt.p, crt.q].iter() {
+      let p1 = widen::<H, W>(&p.wrapping_sub(&UInt::ONE));
+      assert_eq!(rem(&ed, &p1), UInt::ONE);
     }
+    assert_eq!(
+      mul_mod(&crt.qinv, &rem(&crt.q, &crt.p), &crt.monty_p),
+      UInt::ONE
+    );
+
+    // FIPS 186-4, B.3.1: |p - q| > 2**(nlen / 2 - 100)
+    let diff = if crt.p > crt.q {
+      crt.p.wrapping_sub(&crt.q)
+    } else {
+      crt.q.wrapping_sub(&crt.p)
+    };
+    assert!(diff.bits() > 512 - 100);
 
-    return zz;
+    let digest = [0x5A; 256 / 8];
+    let mut sig = [0u8; 1024 / 8];
+    sign_pss_with_salt(&mut rng, &digest, &[0x33; 32], &key, &mut sig).unwrap();
+    assert_eq!(super::verify_pss(&digest, &sig, 32, key.monty()), Ok(()));
   }
 
-  // plain_modpow
-  let i = match exp_data.iter().position(|&r| r != 0) {
-    None => {
-      return U4096::from_u8(1u8);
+  type Components = (U4096, u32, U4096, [U2048; 5]);
+
+  /// Imports the test key after f has changed one of its components.
+  fn import(f: impl FnOnce(&mut Components)) -> Result<(), KeyError> {
+    let mut rng = Hc128Rng::from_seed([0x14; 32]);
+    let mut c = (
+      uint(N),
+      65537,
+      uint(D),
+      [half(P), half(Q), half(DP), half(DQ), half(QI)],
+    );
+    f(&mut c);
+    let (n, e, d, [p, q, dp, dq, qi]) = c;
+    PrivateKey::from_components(&mut rng, n, e, d, p, q, dp, dq, qi).map(|_| ())
+  }
+
+  #[test]
+  fn from_components() {
+    assert_eq!(import(|_| {}), Ok(()));
+  }
+
+  #[test]
+  fn modulus_size() {
+    let err = import(|c| c.0 = c.0.shr_vartime(1));
+    assert_eq!(err, Err(KeyError::ModulusSize));
+  }
+
+  #[test]
+  fn public_exponent() {
+    assert_eq!(import(|c| c.1 = 3), Err(KeyError::PublicExponent));
+  }
+
+  #[test]
+  fn modulus() {
+    let err = import(|c| c.0 = c.0.wrapping_sub(&UInt::from_u8(2)));
+    assert_eq!(err, Err(KeyError::Modulus));
+  }
+
+  #[test]
+  fn composite() {
+    // p + 2 is a multiple of 3, and n is made to match it.
+    let err = import(|c| {
+      let p = c.3[0].wrapping_add(&UInt::from_u8(2));
+      let (lo, hi) = p.mul_wide(&c.3[1]);
+      c.0 = concat(&lo, &hi);
+      c.3[0] = p;
+    });
+    assert_eq!(err, Err(KeyError::Composite));
+  }
+
+  #[test]
+  fn crt_exponent() {
+    for i in 2..4 {
+      let err = import(|c| c.3[i] = c.3[i].wrapping_add(&UInt::ONE));
+      assert_eq!(err, Err(KeyError::CrtExponent));
     }
-    Some(i) => i,
-  };
+  }
+
+  #[test]
+  fn crt_coefficient() {
+    let err = import(|c| c.3[4] = c.3[4].wrapping_add(&UInt::ONE));
+    assert_eq!(err, Err(KeyError::CrtCoefficient));
+  }
 
-  let mut base = base.wrapping_rem(modulus);
+  #[test]
+  fn private_exponent() {
+    let err = import(|c| c.2 = c.2.wrapping_add(&UInt::ONE));
+    assert_eq!(err, Err(KeyError::PrivateExponent));
+  }
 
-  for _ in 0..i {
-    for _ in 0..Limb::BIT_SIZE {
-      base = base.wrapping_mul(&base).wrapping_rem(modulus);
+  #[test]
+  fn inverses() {
+    let (p, q) = (half(P), half(Q));
+    assert_eq!(inv_mod(&q, &p), Some(half(QI)));
+
+    let mp = MontgomeryParams::new(&p).unwrap();
+    let mut rng = Hc128Rng::from_seed([0x14; 32]);
+    let mut bytes = [0u8; 2048 / 8];
+    rng.fill_bytes(&mut bytes[1..]);
+    let p1 = p.wrapping_sub(&UInt::ONE);
+    for a in [UInt::ONE, UInt::from_u8(2), p1, uint_from_be(&bytes)].iter() {
+      let inv = inv_mod(a, &p).unwrap();
+      assert!(inv < p);
+      assert_eq!(mul_mod(a, &inv, &mp), UInt::ONE);
     }
+
+    // Not invertible: zero, a common factor, and the modulus itself.
+    type U64 = UInt<{ 64 / Limb::BIT_SIZE }>;
+    let m = U64::from_u8(15);
+    assert_eq!(inv_mod(&U64::ZERO, &m), None);
+    assert_eq!(inv_mod(&U64::from_u8(6), &m), None);
+    assert_eq!(inv_mod(&U64::from_u8(10), &m), None);
+    assert_eq!(inv_mod(&U64::from_u8(7), &m), Some(U64::from_u8(13)));
+    assert_eq!(inv_mod(&p, &p), None);
+    assert_eq!(inv_mod(&widen::<{ LIMBS / 2 }, LIMBS>(&q), &uint(N)), None);
+
+    assert_eq!(cofactor(&uint(N), &p), q);
+    assert_eq!(cofactor(&uint(N), &q), p);
   }
 
-  let mut r = exp_data[i];
-  let mut b = 0u8;
-  while r % 2 == 0 {
-    base = base.wrapping_mul(&base).wrapping_rem(modulus);
-    r >>= 1;
-    b += 1;
+  #[test]
+  fn single_limb_division() {
+    let mut rng = Hc128Rng::from_seed([0x15; 32]);
+    let mut xs = vec![[0; LIMBS], [LimbUInt::MAX; LIMBS]];
+    for _ in 0..4 {
+      let mut x = [0; LIMBS];
+      x.iter_mut().for_each(|xi| *xi = random_limb(&mut rng));
+      xs.push(x);
+    }
+    let ds = [1, 2, 3, 30, 2039, PUBLIC_EXPONENT, LimbUInt::MAX];
+
+    for x in xs.iter() {
+      for d in ds.iter() {
+        let mut q = *x;
+        let r = div_limb(&mut q, *d);
+        assert!(r < *d);
+        assert_eq!(rem_limb(x, *d), r);
+
+        // q * d + r == x
+        let mut z = [0; LIMBS];
+        z[0] = r;
+        assert_eq!(add_mul_vvw(&mut z, &q, *d), 0);
+        assert_eq!(z, *x);
+      }
+    }
+
+    assert_eq!(pow_mod_limb(3, PUBLIC_EXPONENT - 2, PUBLIC_EXPONENT), 21846);
+    assert_eq!(pow_mod_limb(LimbUInt::MAX - 1, 2, LimbUInt::MAX), 1);
+    assert_eq!(pow_mod_limb(5, 0, 1), 0);
   }
 
-  let mut exp_iter = exp_data[i + 1..].iter();
-  if exp_iter.len() == 0 && r == 1 {
-    return base;
+  fn random_limb(rng: &mut Hc128Rng) -> LimbUInt {
+    // Edge cases for the carry handling a quarter of the time.
+    let edges = [0, 1, 2, LimbUInt::MAX - 1, LimbUInt::MAX];
+    match rng.next_u32() % 8 {
+      0 | 1 => edges[rng.next_u32() as usize % edges.len()],
+      _ => rng.next_u64() as LimbUInt,
+    }
   }
 
-  let mut acc = base.clone();
-  r >>= 1;
-  b += 1;
+  /// Lengths on both sides of the four limb blocks of the unrolled RV32
+  /// add_mul_vvw.
+  const KERNEL_LENS: [usize; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 13, 64];
+
+  /// z + x * y as double width limbs, and the carry out.
+  fn schoolbook<T: Copy + Into<u128>>(
+    z: &[T],
+    x: &[T],
+    y: T,
+    bits: usize,
+  ) -> (Vec<u128>, u128) {
+    let mut c = 0u128;
+    let out = z
+      .iter()
+      .zip(x.iter())
+      .map(|(zi, xi)| {
+        let t = (*xi).into() * y.into() + (*zi).into() + c;
+        c = t >> bits;
+        t & ((1 << bits) - 1)
+      })
+      .collect();
+    (out, c)
+  }
 
-  {
-    let mut unit = |exp_is_odd| {
-      base = base.wrapping_mul(&base).wrapping_rem(modulus);
-      if exp_is_odd {
-        acc = acc.wrapping_mul(&base);
-        acc = acc.wrapping_rem(modulus);
+  #[test]
+  fn multiply_accumulate() {
+    let mut rng = Hc128Rng::from_seed([0x09; 32]);
+    for trial in 0..4000 {
+      let n = KERNEL_LENS[trial % KERNEL_LENS.len()];
+      let x: Vec<_> = (0..n).map(|_| random_limb(&mut rng)).collect();
+      let z: Vec<_> = (0..n).map(|_| random_limb(&mut rng)).collect();
+      let y = random_limb(&mut rng);
+
+      let (expected, c) = schoolbook(&z, &x, y, Limb::BIT_SIZE);
+      let mut out = z.clone();
+      let carry = add_mul_vvw(&mut out, &x, y);
+      let out: Vec<u128> = out.iter().map(|v| *v as u128).collect();
+      assert_eq!(out, expected, "x = {:x?}, y = {:x}, z = {:x?}", x, y, z);
+      assert_eq!(carry as u128, c);
+
+      if n > 0 {
+        let (hi, lo) = mul_add_www(x[0], y, z[0]);
+        let (expected, c) = schoolbook(&z[..1], &x[..1], y, Limb::BIT_SIZE);
+        assert_eq!((hi as u128, lo as u128), (c, expected[0]));
       }
+    }
+  }
+
+  /// The RV32IM add_mul_vvw of `rv32`, one statement per instruction with
+  /// the same registers, so that its carry chain can be checked on the
+  /// host.
+  fn rv32_add_mul_vvw(z: &mut [u32], x: &[u32], y: u32) -> u32 {
+    // add_mul_step!
+    let step = |z: &mut u32, x: u32, c: &mut u32| {
+      let t0 = x;
+      let mut t1 = *z;
+      let mut t2 = t0.wrapping_mul(y);
+      let mut t3 = ((t0 as u64 * y as u64) >> 32) as u32;
+      t2 = t2.wrapping_add(t1);
+      t1 = (t2 < t1) as u32;
+      t3 = t3.wrapping_add(t1);
+      t2 = t2.wrapping_add(*c);
+      t1 = (t2 < *c) as u32;
+      *c = t3.wrapping_add(t1);
+      *z = t2;
     };
 
-    if let Some(&last) = exp_iter.next_back() {
-      // consume exp_data[i]
-      for _ in b as usize..Limb::BIT_SIZE {
-        r >>= 1;
+    let n = z.len().min(x.len());
+    let mut c = 0;
+    let mut i = 0;
+    for _ in 0..n >> 2 {
+      step(&mut z[i], x[i], &mut c);
+      step(&mut z[i + 1], x[i + 1], &mut c);
+      step(&mut z[i + 2], x[i + 2], &mut c);
+      step(&mut z[i + 3], x[i + 3], &mut c);
+      i += 4;
+    }
+    for _ in 0..n & 3 {
+      step(&mut z[i], x[i], &mut c);
+      i += 1;
+    }
+
+    c
+  }
+
+  /// The RV32IM mul_add_www of `rv32`, like `rv32_add_mul_vvw`.
+  fn rv32_mul_add_www(x: u32, y: u32, c: u32) -> (u32, u32) {
+    let mut z0 = x.wrapping_mul(y);
+    let mut z1 = ((x as u64 * y as u64) >> 32) as u32;
+    z0 = z0.wrapping_add(c);
+    let t = (z0 < c) as u32;
+    z1 = z1.wrapping_add(t);
+    (z1, z0)
+  }
+
+  #[test]
+  fn rv32_kernels() {
+    let mut rng = Hc128Rng::from_seed([0x0A; 32]);
+    for trial in 0..4000 {
+      let n = KERNEL_LENS[trial % KERNEL_LENS.len()];
+      let limb = |rng: &mut Hc128Rng| random_limb(rng) as u32;
+      let x: Vec<_> = (0..n).map(|_| limb(&mut rng)).collect();
+      let z: Vec<_> = (0..n).map(|_| limb(&mut rng)).collect();
+      let y = limb(&mut rng);
+
+      let mut out = z.clone();
+      let carry = rv32_add_mul_vvw(&mut out, &x, y);
+      let (expected, c) = schoolbook(&z, &x, y, 32);
+      let got: Vec<u128> = out.iter().map(|v| *v as u128).collect();
+      assert_eq!(got, expected, "x = {:x?}, y = {:x}, z = {:x?}", x, y, z);
+      assert_eq!(carry as u128, c);
+
+      // The portable code, when it has the same limb size.
+      if Limb::BIT_SIZE == 32 {
+        let xl: Vec<_> = x.iter().map(|v| *v as LimbUInt).collect();
+        let mut zl: Vec<_> = z.iter().map(|v| *v as LimbUInt).collect();
+        assert_eq!(add_mul_vvw(&mut zl, &xl, y as LimbUInt) as u32, carry);
+        assert!(zl.iter().zip(out.iter()).all(|(a, b)| *a as u32 == *b));
       }
 
-      // consume all other digits before the last
-      for &r in exp_iter {
-        let mut r = r;
-        for _ in 0..Limb::BIT_SIZE {
-          r >>= 1;
-        }
+      if n > 0 {
+        let (hi, lo) = rv32_mul_add_www(x[0], y, z[0]);
+        let t = x[0] as u64 * y as u64 + z[0] as u64;
+        assert_eq!((hi as u64) << 32 | lo as u64, t);
       }
-      r = last;
     }
+  }
 
-    while r != 0 {
-      r >>= 1;
+  /// Stack bytes used by f. The stack below the caller is painted with a
+  /// pattern first, and the deepest byte that f changed is looked up
+  /// afterwards. Approximate, but good enough to compare two versions.
+  fn stack_used(f: impl FnOnce()) -> usize {
+    const DEPTH: usize = 256 * 1024;
+    const PAINT: u8 = 0xA5;
+
+    #[inline(never)]
+    fn paint() -> usize {
+      let mut area = [0u8; DEPTH];
+      for b in area.iter_mut() {
+        unsafe { core::ptr::write_volatile(b, PAINT) };
+      }
+      area.as_ptr() as usize
     }
+
+    let low = paint();
+    f();
+    let deepest = (low..low + DEPTH)
+      .find(|a| unsafe { core::ptr::read_volatile(*a as *const u8) } != PAINT)
+      .unwrap();
+    low + DEPTH - deepest
   }
-  acc
-}
 
-#[inline]
-pub fn left_pad(input: &[u8]) -> [u8; 512] {
-  let n = if input.len() > 512 { 512 } else { input.len() };
+  /// Time and stack use of the private key operations, for comparing
+  /// changes to the exponentiation. Run with `make bench`.
+  #[test]
+  #[ignore]
+  fn bench() {
+    use std::time::Instant;
+
+    fn report<const L: usize, const H: usize>(key: &PrivateKey<L, H>) {
+      let bits = L * Limb::BIT_SIZE;
+      let mut rng = Hc128Rng::from_seed([0x08; 32]);
+      let c = key.n.wrapping_sub(&UInt::from_u8(2));
+      let d = key.d.to_uint_array();
+      let runs = 8;
+
+      let start = Instant::now();
+      for _ in 0..runs {
+        monty_modpow(&c, &d, key.monty());
+      }
+      let modpow = start.elapsed() / runs;
+      let modpow_stack = stack_used(|| {
+        monty_modpow(&c, &d, key.monty());
+      });
+
+      let digest = [0x5A; 256 / 8];
+      let mut sig = [0u8; MAX_LIMBS * Limb::BYTE_SIZE];
+      let sig = &mut sig[..L * Limb::BYTE_SIZE];
+      let start = Instant::now();
+      for _ in 0..runs {
+        sign_pss_with_salt(&mut rng, &digest, &[0x33; 32], key, sig).unwrap();
+      }
+      let sign = start.elapsed() / runs;
+      let sign_stack = stack_used(|| {
+        sign_pss_with_salt(&mut rng, &digest, &[0x33; 32], key, sig).unwrap();
+      });
+
+      println!(
+        "{} bits: modpow {:?}, {} bytes of stack; CRT sign {:?}, {} bytes",
+        bits, modpow, modpow_stack, sign, sign_stack
+      );
+    }
 
-  let mut out = [0u8; 512];
-  out[512 - n..].copy_from_slice(input);
-  out
+    report::<{ 2048 / Limb::BIT_SIZE }, { 1024 / Limb::BIT_SIZE }>(&key_from(
+      P2048, Q2048, D2048,
+    ));
+    report::<LIMBS, { LIMBS / 2 }>(&key_from(P, Q, D));
+  }
 }
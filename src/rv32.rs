@@ -0,0 +1,108 @@
+//! Multiply-accumulate kernels for RV32IM, used by the Montgomery
+//! arithmetic in `rsa` instead of the portable versions when the
+//! `riscv-asm` feature is enabled.
+//!
+//! Products are formed with `mul`/`mulhu` pairs and carries with `sltu`.
+//! x * y + z + c <= 2**64 - 1 for 32-bit words, so the high word never
+//! overflows when the carries are added to it.
+
+/// One step of add_mul_vvw on the limb at byte offset $off:
+/// z[i], c = z[i] + x[i] * y + c
+macro_rules! add_mul_step {
+  ($off:literal) => {
+    concat!(
+      "lw {t0}, ",
+      $off,
+      "({x})\n",
+      "lw {t1}, ",
+      $off,
+      "({z})\n",
+      "mul {t2}, {t0}, {y}\n",
+      "mulhu {t3}, {t0}, {y}\n",
+      "add {t2}, {t2}, {t1}\n",
+      "sltu {t1}, {t2}, {t1}\n",
+      "add {t3}, {t3}, {t1}\n",
+      "add {t2}, {t2}, {c}\n",
+      "sltu {t1}, {t2}, {c}\n",
+      "add {c}, {t3}, {t1}\n",
+      "sw {t2}, ",
+      $off,
+      "({z})\n",
+    )
+  };
+}
+
+/// z += x * y, returns the carry out of z. Only the first
+/// min(z.len(), x.len()) limbs are used.
+#[inline(always)]
+pub fn add_mul_vvw(z: &mut [u32], x: &[u32], y: u32) -> u32 {
+  let n = z.len().min(x.len());
+  let mut c: u32 = 0;
+
+  unsafe {
+    asm!(
+      // Blocks of four limbs, with the carry chain unrolled.
+      "srli {blocks}, {n}, 2",
+      "beqz {blocks}, 2f",
+      "1:",
+      add_mul_step!("0"),
+      add_mul_step!("4"),
+      add_mul_step!("8"),
+      add_mul_step!("12"),
+      "addi {x}, {x}, 16",
+      "addi {z}, {z}, 16",
+      "addi {blocks}, {blocks}, -1",
+      "bnez {blocks}, 1b",
+      // Then the remaining n % 4 limbs.
+      "2:",
+      "andi {n}, {n}, 3",
+      "beqz {n}, 4f",
+      "3:",
+      add_mul_step!("0"),
+      "addi {x}, {x}, 4",
+      "addi {z}, {z}, 4",
+      "addi {n}, {n}, -1",
+      "bnez {n}, 3b",
+      "4:",
+      z = inout(reg) z.as_mut_ptr() => _,
+      x = inout(reg) x.as_ptr() => _,
+      n = inout(reg) n => _,
+      blocks = out(reg) _,
+      y = in(reg) y,
+      c = inout(reg) c,
+      t0 = out(reg) _,
+      t1 = out(reg) _,
+      t2 = out(reg) _,
+      t3 = out(reg) _,
+      options(nostack),
+    );
+  }
+
+  c
+}
+
+/// z1 << 32 + z0 = x * y + c
+#[inline(always)]
+pub fn mul_add_www(x: u32, y: u32, c: u32) -> (u32, u32) {
+  let z0: u32;
+  let z1: u32;
+
+  unsafe {
+    asm!(
+      "mul {z0}, {x}, {y}",
+      "mulhu {z1}, {x}, {y}",
+      "add {z0}, {z0}, {c}",
+      "sltu {t}, {z0}, {c}",
+      "add {z1}, {z1}, {t}",
+      x = in(reg) x,
+      y = in(reg) y,
+      c = in(reg) c,
+      z0 = out(reg) z0,
+      z1 = out(reg) z1,
+      t = out(reg) _,
+      options(pure, nomem, nostack),
+    );
+  }
+
+  (z1, z0)
+}
@@ -1,3 +1,4 @@
+use arienai::deephash;
 use gd32vf103xx_hal::gpio::gpioa::PA10;
 use gd32vf103xx_hal::gpio::gpioa::PA9;
 use gd32vf103xx_hal::gpio::Alternate;
@@ -62,3 +63,14 @@ impl UART {
     block!(self.tx.write(byte));
   }
 }
+
+/// Every header and chunk of a deep-hash stream is answered with 0x00.
+impl deephash::Stream for UART {
+  fn read(&mut self, buf: &mut [u8]) {
+    unsafe { UART::read(self, buf) }
+  }
+
+  fn ack(&mut self) {
+    unsafe { self.write(0) }
+  }
+}
@@ -1,6 +1,63 @@
+import base64
+import getpass
 import serial
 import hashlib
 
+
+def deep_hash(item):
+    """deepHash of arweave-js, over bytes and lists."""
+    h = lambda data: hashlib.sha384(data).digest()
+    if isinstance(item, list):
+        acc = h(b"list" + str(len(item)).encode())
+        for i in item:
+            acc = h(acc + deep_hash(i))
+        return acc
+    return h(h(b"blob" + str(len(item)).encode()) + h(item))
+
+
+def send_items(item):
+    """Streams item to SignDeepHash, waiting for every header and chunk."""
+    if isinstance(item, list):
+        fd.write(bytes([1]) + len(item).to_bytes(4, "big"))
+        assert fd.read(1) == b"\0"
+        for i in item:
+            send_items(i)
+        return
+    fd.write(bytes([0]) + len(item).to_bytes(4, "big"))
+    assert fd.read(1) == b"\0"
+    for i in range(0, len(item), 512):
+        fd.write(item[i : i + 512])
+        assert fd.read(1) == b"\0"
+
+
+# Single byte answers in place of a signature.
+ERRORS = {
+    b"E": "locked, or no key in the slot",
+    b"F": "fault caught while signing, the device may be under attack",
+    b"N": "the noise sources failed their health tests",
+}
+
+
+def check_status(name, status):
+    """Exits unless status is the 0x00 of a ready device."""
+    if status != b"\0":
+        print(name + ":", ERRORS.get(status, status))
+        exit(1)
+
+
+def read_signature(name):
+    """Reads a 512 byte signature, exits on a single byte answer."""
+    # Only a timeout tells a single byte apart from the start of a
+    # signature.
+    fd.timeout = 30
+    sig = fd.read(512)
+    fd.timeout = None
+    if len(sig) == 1:
+        print(name + ":", ERRORS.get(sig, sig))
+        exit(1)
+    return sig
+
+
 fd = serial.Serial("/dev/ttyUSB0")
 fd.baudrate = 115_200
 
@@ -8,10 +65,70 @@ m = hashlib.sha256()
 m.update(b"swap wen?")
 digest = m.digest()
 
-fd.write(bytes([1]))
+# Unlock the accounts.
+pin = getpass.getpass("PIN: ").encode()
+fd.write(bytes([0x0B, len(pin)]) + pin)
+status = fd.read(1)
+if status == b"P":
+    print("unlock: wrong PIN,", fd.read(1)[0], "tries left")
+    exit(1)
+print("unlock:", status)
+
+# List the accounts.
+fd.write(bytes([0x0E]))
+for slot in range(fd.read(1)[0]):
+    if fd.read(1)[0]:
+        address = base64.urlsafe_b64encode(fd.read(32)).rstrip(b"=")
+        label = fd.read(16).rstrip(b"\0")
+        print(slot, address.decode(), label.decode())
+
+# Show the address of slot 0 on the device.
+fd.write(bytes([0x04, 0]))
+print("address:", fd.read(43).decode())
+
+# Sign with the account in slot 0.
+fd.write(bytes([1, 0]))
 fd.write(digest)
 
-sig = fd.read(512)
+sig = read_signature("sign")
 print(list(sig))
 
+# Verify against the account in slot 0.
+fd.write(bytes([2]))
+fd.write(digest)
+fd.write(sig)
+fd.write(bytes([0, 0]))
+
+print("verify:", fd.read(1)[0] == 1)
+
+# Sign a whole message, hashed on the device, in chunks of 512 bytes.
+message = b"swap wen?" * 200
+fd.write(bytes([0x0F, 0]) + len(message).to_bytes(4, "big"))
+check_status("sign message", fd.read(1))
+for i in range(0, len(message), 512):
+    fd.write(message[i : i + 512])
+    fd.read(1)
+
+sig = read_signature("sign message")
+fd.write(bytes([2]))
+fd.write(hashlib.sha256(message).digest())
+fd.write(sig)
+fd.write(bytes([0, 0]))
+
+print("verify message:", fd.read(1)[0] == 1)
+
+# Sign the deep hash of a transaction-like list, computed on the device.
+tx = [b"2", b"\1" * 512, b"", b"0", b"0", b"", [[b"App", b"arienai"]], message]
+fd.write(bytes([0x10, 0]))
+check_status("sign deep hash", fd.read(1))
+send_items(tx)
+
+sig = read_signature("sign deep hash")
+fd.write(bytes([2]))
+fd.write(hashlib.sha256(deep_hash(tx)).digest())
+fd.write(sig)
+fd.write(bytes([0, 0]))
+
+print("verify deep hash:", fd.read(1)[0] == 1)
+
 fd.close()